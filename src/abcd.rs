@@ -0,0 +1,225 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::batch::{RecordFields, check_all, item_results};
+use crate::models::{AppState, BatchItemResult, ImplementingCountries, NagoyaError};
+use crate::upload::malformed;
+use crate::xml::walk;
+use tracing::{Level, event, instrument};
+
+// Gathering data of an ABCD unit
+#[derive(Default, Debug, PartialEq)]
+struct Unit {
+    id: Option<String>,
+    country_code: Option<String>,
+    country_name: Option<String>,
+    latitude: Option<String>,
+    longitude: Option<String>,
+    uncertainty_in_meters: Option<String>,
+    date_begin: Option<String>,
+    date_end: Option<String>,
+}
+
+impl Unit {
+    fn into_record(self) -> RecordFields {
+        // ABCD dates are ISO date-times, the check works on days
+        let day = |date: String| date.split('T').next().unwrap_or_default().to_string();
+        let date = match (self.date_begin.map(day), self.date_end.map(day)) {
+            (Some(begin), Some(end)) if begin != end => Some(format!("{begin}/{end}")),
+            (begin, end) => begin.or(end),
+        };
+        RecordFields {
+            country: self.country_code.or(self.country_name),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            uncertainty_in_meters: self.uncertainty_in_meters,
+            date,
+        }
+    }
+}
+
+// Reads the units of an ABCD 2.06 document, also when wrapped in a BioCASe response. Only the
+// first coordinates of a gathering are used
+fn parse_units(xml: &str) -> Result<Vec<Unit>, NagoyaError> {
+    let mut units = Vec::new();
+    let mut unit = Unit::default();
+    walk(xml, |element| {
+        let text = Some(element.text.clone()).filter(|text| !text.is_empty());
+        let set = |field: &mut Option<String>| {
+            if field.is_none() {
+                *field = text.clone();
+            }
+        };
+        if element.ends_with(&["Units", "Unit"]) {
+            units.push(std::mem::take(&mut unit));
+        } else if element.ends_with(&["Unit", "UnitID"]) {
+            set(&mut unit.id);
+        } else if element.ends_with(&["Unit", "Gathering", "Country", "ISO3166Code"]) {
+            set(&mut unit.country_code);
+        } else if element.ends_with(&["Unit", "Gathering", "Country", "Name"]) {
+            set(&mut unit.country_name);
+        } else if element.ends_with(&["CoordinatesLatLong", "LatitudeDecimal"]) {
+            set(&mut unit.latitude);
+        } else if element.ends_with(&["CoordinatesLatLong", "LongitudeDecimal"]) {
+            set(&mut unit.longitude);
+        } else if element.ends_with(&["SiteCoordinates", "CoordinateErrorDistanceInMeters"]) {
+            set(&mut unit.uncertainty_in_meters);
+        } else if element.ends_with(&["Unit", "Gathering", "DateTime", "ISODateTimeBegin"]) {
+            set(&mut unit.date_begin);
+        } else if element.ends_with(&["Unit", "Gathering", "DateTime", "ISODateTimeEnd"]) {
+            set(&mut unit.date_end);
+        }
+        Ok(())
+    })?;
+    if units.is_empty() {
+        return Err(malformed("No ABCD units found"));
+    }
+    Ok(units)
+}
+
+// Checks the gathering of every unit of an ABCD document and returns the results by UnitID, in the
+// order of the units. Units without a UnitID are numbered
+#[instrument(skip_all)]
+pub async fn check_abcd(
+    file: Vec<u8>,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+) -> Result<Vec<BatchItemResult>, NagoyaError> {
+    let xml = String::from_utf8(file).map_err(malformed)?;
+    let units = parse_units(&xml)?;
+    event!(Level::DEBUG, "Checking {} units", units.len());
+    let (ids, checks) = units
+        .into_iter()
+        .enumerate()
+        .map(|(position, unit)| {
+            (
+                unit.id
+                    .clone()
+                    .unwrap_or_else(|| (position + 1).to_string()),
+                unit.into_record().into_check(),
+            )
+        })
+        .unzip();
+    let results = check_all(
+        checks,
+        implementing_countries,
+        state,
+        state.config.upload_max_records,
+    )
+    .await?;
+    Ok(item_results(ids, results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::tests::state;
+    use crate::country::CountryId;
+
+    const DOCUMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<biocase:response xmlns:biocase="http://www.biocase.org/schemas/protocol/1.3">
+  <biocase:content>
+    <abcd:DataSets xmlns:abcd="http://www.tdwg.org/schemas/abcd/2.06">
+      <abcd:DataSet>
+        <abcd:Units>
+          <abcd:Unit>
+            <abcd:SourceInstitutionID>B</abcd:SourceInstitutionID>
+            <abcd:UnitID>B 10 0154321</abcd:UnitID>
+            <abcd:Gathering>
+              <abcd:DateTime>
+                <abcd:ISODateTimeBegin>1998-03-02T10:00:00</abcd:ISODateTimeBegin>
+                <abcd:ISODateTimeEnd>1998-03-04</abcd:ISODateTimeEnd>
+              </abcd:DateTime>
+              <abcd:Country>
+                <abcd:Name>Brasil</abcd:Name>
+              </abcd:Country>
+            </abcd:Gathering>
+          </abcd:Unit>
+          <abcd:Unit>
+            <abcd:UnitID>B 10 0154322</abcd:UnitID>
+            <abcd:Gathering>
+              <abcd:Country>
+                <abcd:Name>Germany</abcd:Name>
+                <abcd:ISO3166Code>DE</abcd:ISO3166Code>
+              </abcd:Country>
+              <abcd:SiteCoordinateSets>
+                <abcd:SiteCoordinates>
+                  <abcd:CoordinatesLatLong>
+                    <abcd:LongitudeDecimal>0</abcd:LongitudeDecimal>
+                    <abcd:LatitudeDecimal>91</abcd:LatitudeDecimal>
+                  </abcd:CoordinatesLatLong>
+                  <abcd:CoordinateErrorDistanceInMeters>100</abcd:CoordinateErrorDistanceInMeters>
+                </abcd:SiteCoordinates>
+              </abcd:SiteCoordinateSets>
+            </abcd:Gathering>
+          </abcd:Unit>
+          <abcd:Unit>
+            <abcd:Gathering>
+              <abcd:Country><abcd:ISO3166Code>PE</abcd:ISO3166Code></abcd:Country>
+            </abcd:Gathering>
+          </abcd:Unit>
+        </abcd:Units>
+      </abcd:DataSet>
+    </abcd:DataSets>
+  </biocase:content>
+</biocase:response>"#;
+
+    #[test]
+    fn test_parse_units() {
+        let units = parse_units(DOCUMENT).unwrap();
+        assert_eq!(units.len(), 3);
+        assert_eq!(
+            units[1],
+            Unit {
+                id: Some(String::from("B 10 0154322")),
+                country_code: Some(String::from("DE")),
+                country_name: Some(String::from("Germany")),
+                latitude: Some(String::from("91")),
+                longitude: Some(String::from("0")),
+                uncertainty_in_meters: Some(String::from("100")),
+                ..Default::default()
+            }
+        );
+        let record = parse_units(DOCUMENT).unwrap().remove(0).into_record();
+        assert_eq!(record.country.as_deref(), Some("Brasil"));
+        assert_eq!(record.date.as_deref(), Some("1998-03-02/1998-03-04"));
+        assert!(matches!(
+            parse_units("<DataSets><DataSet/></DataSets>"),
+            Err(NagoyaError::MalformedUpload { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_check_abcd() {
+        let implementing_countries = ImplementingCountries {
+            countries: ["BRA".parse::<CountryId>().unwrap()].into(),
+            ..Default::default()
+        };
+        let results = check_abcd(
+            DOCUMENT.as_bytes().to_vec(),
+            &implementing_countries,
+            &state(10),
+        )
+        .await
+        .unwrap();
+        let outcome: Vec<(&str, Option<bool>, Option<&str>)> = results
+            .iter()
+            .map(|result| {
+                (
+                    result.id.as_str(),
+                    result.result.as_ref().map(|response| response.check_result),
+                    result.error.as_ref().map(|error| error.error),
+                )
+            })
+            .collect();
+        assert_eq!(
+            outcome,
+            vec![
+                ("B 10 0154321", Some(true), None),
+                ("B 10 0154322", None, Some("invalid_coordinates")),
+                ("3", Some(false), None),
+            ]
+        );
+    }
+}
@@ -3,13 +3,29 @@
 // SPDX-License-Identifier: LGPL-3.0-or-later
 
 use crate::ApiDoc;
+use crate::abcd::check_abcd;
+use crate::batch::{check_batch, run_check};
+use crate::csv_upload::stream_annotated_csv;
+use crate::dataset_metadata::check_dataset_metadata;
+use crate::dwca::annotate_dwca;
+use crate::insdc::parse_qualifiers;
 use crate::models::{
-    AppState, GenericResponse, ImplementingCountries, NagoyaCheckDataCC, NagoyaCheckDataGeo,
-    NagoyaError, NagoyaResponse,
+    AppState, BatchJobResponse, BatchResponse, CsvUpload, DatasetCheck, ErrorResponse, FileUpload,
+    GenericResponse, InsdcQualifiers, InsdcResponse, NagoyaCheckBatch, NagoyaCheckDataCC,
+    NagoyaCheckDataGeo, NagoyaError, NagoyaResponse, NormalizeRequest, NormalizedCountry,
+    RecordReport,
 };
-use crate::nagoya_check::{nagoya_check_cc, nagoya_check_geo};
+use crate::nagoya_check::{nagoya_check_country, nagoya_check_geo};
+use crate::normalization::normalize_country;
+use crate::rate_limit::Queueing;
+use crate::sample_metadata::check_sample_metadata;
+use crate::sequence_file::check_sequence_file;
+use crate::upload::read_file;
 use axum::Json;
-use axum::extract::State;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Multipart, Path, State};
+use axum::http::{StatusCode, header};
+use axum::response::IntoResponse;
 use utoipa::OpenApi;
 
 // Wrapper to ease testing of the main functionality
@@ -19,19 +35,23 @@ use utoipa::OpenApi;
     request_body = NagoyaCheckDataCC,
     responses(
         (status = 200, description = "Result of the compliance check", body = NagoyaResponse),
-        (status = 422, description = "Could not process input, possibly illegal country code"),
+        (status = 422, description = "Could not process input, possibly malformed request, illegal country or subdivision code or no country at all", body = ErrorResponse),
         (status = 502)
     )
 )]
 pub async fn nagoya_check_country_code(
-    State(implementing_countries): State<ImplementingCountries>,
-    Json(payload): Json<NagoyaCheckDataCC>,
-) -> Result<Json<NagoyaResponse>, axum::http::StatusCode> {
-    match nagoya_check_cc(payload.probe_country, &implementing_countries).await {
-        Ok(res) => Ok(res),
-        Err(NagoyaError::MalformedCountryCode) => Err(axum::http::StatusCode::UNPROCESSABLE_ENTITY),
-        Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    State(mut state): State<AppState>,
+    // Malformed country codes are rejected while parsing, so they are answered in the API's format
+    payload: Result<Json<NagoyaCheckDataCC>, JsonRejection>,
+) -> Result<Json<NagoyaResponse>, NagoyaError> {
+    let Json(payload) = payload?;
+    let implementing_countries = state.implementing_countries().await;
+    nagoya_check_country(
+        payload,
+        &implementing_countries,
+        state.subnational_regimes.as_deref(),
+    )
+    .await
 }
 
 #[utoipa::path(
@@ -40,30 +60,284 @@ pub async fn nagoya_check_country_code(
     request_body = NagoyaCheckDataGeo,
     responses(
         (status = 200, description ="Result of the compliance check", body = NagoyaResponse),
+        (status = 409, description = "Geocoders disagree in consensus mode with agreement required", body = ErrorResponse),
+        (status = 422, description = "Malformed request, invalid coordinates, event date or declared country, unsupported CRS, coordinates not within a country or consensus mode with fewer than two geocoders", body = ErrorResponse),
         (status = 500, description = "Internal Server Error"),
-        (status = 502, description = "Bad Gateway")
+        (status = 502, description = "Nominatim unreachable or answered with an error", body = ErrorResponse),
+        (status = 503, description = "Too many pending geocoding requests, see Retry-After", body = ErrorResponse),
+        (status = 504, description = "Nominatim timed out", body = ErrorResponse)
     )
 )]
 pub async fn nagoya_check_geocoordinates(
     //State(implementing_countries): State<ImplementingCountries>,
     //State(config): State<Config>,
     State(mut state): State<AppState>,
-    Json(payload): Json<NagoyaCheckDataGeo>,
-) -> Result<Json<NagoyaResponse>, axum::http::StatusCode> {
-    // TODO: Explicit error handling via match here?
-    // TODO: More granular error response, e.g. bc upstream failed
-    //Ok(nagoya_check_geo(payload.coordinates, &implementing_countries, &config).await?)
-    match nagoya_check_geo(
-        payload.coordinates,
-        &state.implementing_countries().await.clone(),
-        &state.config,
+    // Malformed country codes are rejected while parsing, so they are answered in the API's format
+    payload: Result<Json<NagoyaCheckDataGeo>, JsonRejection>,
+) -> Result<Json<NagoyaResponse>, NagoyaError> {
+    let Json(payload) = payload?;
+    let implementing_countries = state.implementing_countries().await;
+    nagoya_check_geo(payload, &implementing_countries, &state, Queueing::Reject).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/nagoya_check_batch",
+    request_body = NagoyaCheckBatch,
+    responses(
+        (status = 200, description = "Result or error per item, in the order of the items", body = BatchResponse),
+        (status = 413, description = "More items than BATCH_MAX_ITEMS", body = ErrorResponse),
+        (status = 502)
+    )
+)]
+pub async fn nagoya_check_batch(
+    State(mut state): State<AppState>,
+    Json(payload): Json<NagoyaCheckBatch>,
+) -> Result<Json<BatchResponse>, NagoyaError> {
+    let implementing_countries = state.implementing_countries().await;
+    let results = check_batch(
+        payload.items,
+        &implementing_countries,
+        &state,
+        state.config.batch_max_items,
     )
-    .await
-    {
-        Ok(res) => Ok(res),
-        Err(NagoyaError::UnresolvableCoordinates) => Err(axum::http::StatusCode::BAD_GATEWAY),
-        Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
+    .await?;
+    Ok(Json(BatchResponse { results }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/nagoya_check_batch/jobs",
+    request_body = NagoyaCheckBatch,
+    responses(
+        (status = 202, description = "Job accepted, poll the URL in the Location header for the results", body = BatchJobResponse),
+        (status = 413, description = "More items than BATCH_JOB_MAX_ITEMS", body = ErrorResponse)
+    )
+)]
+pub async fn submit_batch_job(
+    State(mut state): State<AppState>,
+    Json(payload): Json<NagoyaCheckBatch>,
+) -> Result<impl IntoResponse, NagoyaError> {
+    let max = state.config.batch_job_max_items;
+    if payload.items.len() > max {
+        return Err(NagoyaError::BatchTooLarge {
+            items: payload.items.len(),
+            max,
+        });
     }
+    let job = state.batch_jobs.submit(payload.items.len());
+    let job_id = job.job_id.clone();
+    let location = format!("/nagoya_check_batch/jobs/{job_id}");
+    // Checked in the background, so the connection is not held open while the items are geocoded
+    tokio::spawn(async move {
+        let implementing_countries = state.implementing_countries().await;
+        let results = check_batch(payload.items, &implementing_countries, &state, max).await;
+        state.batch_jobs.finish(&job_id, results);
+    });
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, location)],
+        Json(job),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/nagoya_check_batch/jobs/{job_id}",
+    params(("job_id" = String, Path, description = "Id returned when the job was submitted")),
+    responses(
+        (status = 200, description = "State of the job, with the result or error per item once it is done", body = BatchJobResponse),
+        (status = 404, description = "Unknown job, or its results expired after BATCH_JOB_RETENTION", body = ErrorResponse)
+    )
+)]
+pub async fn batch_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<BatchJobResponse>, NagoyaError> {
+    state.batch_jobs.get(&job_id).map(Json)
+}
+
+#[utoipa::path(
+    post,
+    path = "/nagoya_check_csv",
+    request_body(content = CsvUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "The uploaded CSV with the verdict, the resolved country and the reasons appended to each row, streamed while the upload is checked. Aborted if the file has more rows than UPLOAD_MAX_RECORDS or turns out malformed after the header line", content_type = "text/csv", body = String),
+        (status = 422, description = "Malformed upload or missing columns", body = ErrorResponse),
+        (status = 502)
+    )
+)]
+pub async fn nagoya_check_csv(
+    State(mut state): State<AppState>,
+    multipart: Multipart,
+) -> Result<impl IntoResponse, NagoyaError> {
+    let implementing_countries = state.implementing_countries().await;
+    let annotated = stream_annotated_csv(multipart, implementing_countries, state).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"nagoya_check.csv\"",
+            ),
+        ],
+        annotated,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/nagoya_check_dwca",
+    request_body(content = FileUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Zip with the annotations per occurrence (nagoya_annotations.txt, tab-separated) and a summary of the affected provider countries (nagoya_summary.json)", content_type = "application/zip", body = Vec<u8>),
+        (status = 413, description = "More occurrences than UPLOAD_MAX_RECORDS", body = ErrorResponse),
+        (status = 422, description = "Malformed archive or meta.xml, or no occurrences", body = ErrorResponse),
+        (status = 502)
+    )
+)]
+pub async fn nagoya_check_dwca(
+    State(mut state): State<AppState>,
+    multipart: Multipart,
+) -> Result<impl IntoResponse, NagoyaError> {
+    let upload = read_file(multipart).await?;
+    let implementing_countries = state.implementing_countries().await;
+    let annotated = annotate_dwca(upload.file, &implementing_countries, &state).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"nagoya_check.zip\"",
+            ),
+        ],
+        annotated,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/nagoya_check_abcd",
+    request_body(content = FileUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Result or error per unit, by UnitID in the order of the units", body = BatchResponse),
+        (status = 413, description = "More units than UPLOAD_MAX_RECORDS", body = ErrorResponse),
+        (status = 422, description = "Malformed XML or no ABCD units", body = ErrorResponse),
+        (status = 502)
+    )
+)]
+pub async fn nagoya_check_abcd(
+    State(mut state): State<AppState>,
+    multipart: Multipart,
+) -> Result<Json<BatchResponse>, NagoyaError> {
+    let upload = read_file(multipart).await?;
+    let implementing_countries = state.implementing_countries().await;
+    let results = check_abcd(upload.file, &implementing_countries, &state).await?;
+    Ok(Json(BatchResponse { results }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/nagoya_check_insdc",
+    request_body = InsdcQualifiers,
+    responses(
+        (status = 200, description = "Parsed qualifiers and the check result", body = InsdcResponse),
+        (status = 422, description = "Malformed lat_lon, unknown country or one mapping to several without lat_lon", body = ErrorResponse),
+        (status = 502)
+    )
+)]
+pub async fn nagoya_check_insdc(
+    State(mut state): State<AppState>,
+    Json(payload): Json<InsdcQualifiers>,
+) -> Result<Json<InsdcResponse>, NagoyaError> {
+    let implementing_countries = state.implementing_countries().await;
+    let (qualifiers, check) = parse_qualifiers(&payload, &implementing_countries.names)?;
+    let result = run_check(Ok(check), &implementing_countries, &state, Queueing::Reject).await?;
+    Ok(Json(InsdcResponse { qualifiers, result }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/nagoya_check_sequences",
+    request_body(content = FileUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Parsed source qualifiers and result or error per accession, with a summary of the affected provider countries", body = RecordReport),
+        (status = 413, description = "More entries than UPLOAD_MAX_RECORDS", body = ErrorResponse),
+        (status = 422, description = "Neither a GenBank or EMBL flat file nor FASTA", body = ErrorResponse),
+        (status = 502)
+    )
+)]
+pub async fn nagoya_check_sequences(
+    State(mut state): State<AppState>,
+    multipart: Multipart,
+) -> Result<Json<RecordReport>, NagoyaError> {
+    let upload = read_file(multipart).await?;
+    let implementing_countries = state.implementing_countries().await;
+    check_sequence_file(upload.file, &implementing_countries, &state)
+        .await
+        .map(Json)
+}
+
+#[utoipa::path(
+    post,
+    path = "/nagoya_check_samples",
+    request_body(content = FileUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Parsed MIxS fields and result or error per sample accession or name, with a summary of the affected provider countries", body = RecordReport),
+        (status = 413, description = "More samples than UPLOAD_MAX_RECORDS", body = ErrorResponse),
+        (status = 422, description = "Malformed BioSample XML or MIxS checklist", body = ErrorResponse),
+        (status = 502)
+    )
+)]
+pub async fn nagoya_check_samples(
+    State(mut state): State<AppState>,
+    multipart: Multipart,
+) -> Result<Json<RecordReport>, NagoyaError> {
+    let upload = read_file(multipart).await?;
+    let implementing_countries = state.implementing_countries().await;
+    check_sample_metadata(upload.file, &implementing_countries, &state)
+        .await
+        .map(Json)
+}
+
+#[utoipa::path(
+    post,
+    path = "/nagoya_check_dataset",
+    request_body(content = FileUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Whether the dataset needs a Nagoya notice, with the result or error per point, box or place", body = DatasetCheck),
+        (status = 413, description = "More locations than UPLOAD_MAX_RECORDS", body = ErrorResponse),
+        (status = 422, description = "Malformed document or neither geoLocations nor geographicCoverage", body = ErrorResponse),
+        (status = 502)
+    )
+)]
+pub async fn nagoya_check_dataset(
+    State(mut state): State<AppState>,
+    multipart: Multipart,
+) -> Result<Json<DatasetCheck>, NagoyaError> {
+    let upload = read_file(multipart).await?;
+    let implementing_countries = state.implementing_countries().await;
+    check_dataset_metadata(upload.file, &implementing_countries, &state)
+        .await
+        .map(Json)
+}
+
+#[utoipa::path(
+    post,
+    path = "/normalize",
+    request_body = NormalizeRequest,
+    responses(
+        (status = 200, description = "Canonical codes, name and sovereign state of the country", body = NormalizedCountry),
+        (status = 422, description = "Malformed code, unknown name or no country at all, e.g. an ocean", body = ErrorResponse),
+        (status = 502)
+    )
+)]
+pub async fn normalize(
+    State(mut state): State<AppState>,
+    Json(payload): Json<NormalizeRequest>,
+) -> Result<Json<NormalizedCountry>, NagoyaError> {
+    let implementing_countries = state.implementing_countries().await;
+    normalize_country(&payload.country, &implementing_countries.names).map(Json)
 }
 
 #[utoipa::path(
@@ -79,6 +353,20 @@ pub async fn health_check() -> Json<GenericResponse> {
     })
 }
 
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", body = String)
+    )
+)]
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
 #[utoipa::path(
     get,
     path = "/openapi.json",
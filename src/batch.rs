@@ -0,0 +1,356 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::models::{
+    AppState, BatchItem, BatchItemResult, CoordinateInput, CoordinateNotation, Coordinates,
+    CountryInput, CountrySummary, DatasetSummary, ImplementingCountries, NagoyaCheckDataCC,
+    NagoyaCheckDataGeo, NagoyaError, NagoyaResponse,
+};
+use crate::nagoya_check::{nagoya_check_country, nagoya_check_geo};
+use crate::rate_limit::Queueing;
+use axum::Json;
+use futures_util::{StreamExt, stream};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use tracing::{Level, event, instrument};
+
+// A single check of a batch, by country or by coordinates
+pub enum Check {
+    Country(NagoyaCheckDataCC),
+    Geo(NagoyaCheckDataGeo),
+}
+
+// Provenance of a record of an uploaded file, as found in its columns or elements
+#[derive(Default, Debug)]
+pub struct RecordFields {
+    pub country: Option<String>,
+    pub latitude: Option<String>,
+    pub longitude: Option<String>,
+    pub uncertainty_in_meters: Option<String>,
+    pub date: Option<String>,
+}
+
+impl RecordFields {
+    // Records with coordinates are checked by location, with the country cross-checked as the
+    // declared one; records without are checked by country
+    pub fn into_check(self) -> Result<Check, NagoyaError> {
+        match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => {
+                let uncertainty_in_meters = self
+                    .uncertainty_in_meters
+                    .map(|uncertainty| {
+                        uncertainty
+                            .parse::<f64>()
+                            .map_err(|_| NagoyaError::InvalidCoordinates {
+                                reason: format!("invalid uncertainty \"{uncertainty}\""),
+                            })
+                    })
+                    .transpose()?;
+                let coordinates = match (latitude.parse::<f64>(), longitude.parse::<f64>()) {
+                    (Ok(latitude), Ok(longitude)) => CoordinateInput::Decimal(Coordinates {
+                        latitude,
+                        longitude,
+                        uncertainty_in_meters,
+                    }),
+                    // e.g. 12°30'15"S in one column and 45°10'W in the other
+                    _ => CoordinateInput::Notation(CoordinateNotation {
+                        notation: format!("{latitude} {longitude}"),
+                        uncertainty_in_meters,
+                    }),
+                };
+                Ok(Check::Geo(NagoyaCheckDataGeo {
+                    coordinates,
+                    epsg: None,
+                    event_date: self.date,
+                    declared_country: self.country.map(CountryInput::try_from).transpose()?,
+                    consensus: false,
+                    require_agreement: None,
+                }))
+            }
+            (None, None) => Ok(Check::Country(NagoyaCheckDataCC {
+                probe_country: Some(CountryInput::try_from(
+                    self.country.ok_or(NagoyaError::MissingCountry)?,
+                )?),
+                countries: Vec::new(),
+            })),
+            _ => Err(NagoyaError::InvalidCoordinates {
+                reason: String::from("latitude or longitude missing"),
+            }),
+        }
+    }
+}
+
+#[instrument(skip_all, fields(items = items.len()))]
+pub async fn check_batch(
+    items: Vec<BatchItem>,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+    max_items: usize,
+) -> Result<Vec<BatchItemResult>, NagoyaError> {
+    let (ids, checks): (Vec<String>, Vec<Result<Check, NagoyaError>>) = items
+        .into_iter()
+        .map(|item| {
+            let check = if item.check.contains_key("coordinates") {
+                parse(item.check).map(Check::Geo)
+            } else {
+                parse(item.check).map(Check::Country)
+            };
+            (item.id, check)
+        })
+        .unzip();
+    let results = check_all(checks, implementing_countries, state, max_items).await?;
+    Ok(item_results(ids, results))
+}
+
+// Pairs the results with the ids of the items or records they belong to
+pub fn item_results(
+    ids: Vec<String>,
+    results: Vec<Result<NagoyaResponse, NagoyaError>>,
+) -> Vec<BatchItemResult> {
+    ids.into_iter()
+        .zip(results)
+        .map(|(id, result)| match result {
+            Ok(response) => BatchItemResult {
+                id,
+                result: Some(response),
+                error: None,
+            },
+            Err(error) => {
+                event!(Level::DEBUG, "Item \"{}\" failed: {}", &id, &error);
+                BatchItemResult {
+                    id,
+                    result: None,
+                    error: Some(error.error_response()),
+                }
+            }
+        })
+        .collect()
+}
+
+// Runs the checks with at most batch_concurrency in flight and returns the results in order.
+// Requests to Nominatim are rate limited globally anyway; the bound keeps a single large batch
+// from filling the queue and turning away everyone else
+pub async fn check_all(
+    checks: Vec<Result<Check, NagoyaError>>,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+    // Limit of the endpoint, as batches, batch jobs and uploads are limited separately
+    max_items: usize,
+) -> Result<Vec<Result<NagoyaResponse, NagoyaError>>, NagoyaError> {
+    if checks.len() > max_items {
+        return Err(NagoyaError::BatchTooLarge {
+            items: checks.len(),
+            max: max_items,
+        });
+    }
+    Ok(stream::iter(checks)
+        // Items of a batch wait for room in the Nominatim queue instead of failing when it is
+        // full, as clients cannot retry single items
+        .map(|check| run_check(check, implementing_countries, state, Queueing::Wait))
+        .buffered(state.config.batch_concurrency.max(1))
+        .collect()
+        .await)
+}
+
+pub async fn run_check(
+    check: Result<Check, NagoyaError>,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+    queueing: Queueing,
+) -> Result<NagoyaResponse, NagoyaError> {
+    let Json(response) = match check? {
+        Check::Country(request) => {
+            nagoya_check_country(
+                request,
+                implementing_countries,
+                state.subnational_regimes.as_deref(),
+            )
+            .await?
+        }
+        Check::Geo(request) => {
+            nagoya_check_geo(request, implementing_countries, state, queueing).await?
+        }
+    };
+    Ok(response)
+}
+
+// Counts the records per provider country, for a dataset-level view of the results
+pub fn summarize(results: &[Result<NagoyaResponse, NagoyaError>]) -> DatasetSummary {
+    let mut summary = DatasetSummary {
+        records: results.len(),
+        ..Default::default()
+    };
+    let mut countries: HashMap<&str, CountrySummary> = HashMap::new();
+    for result in results {
+        let Ok(response) = result else {
+            summary.failed += 1;
+            continue;
+        };
+        summary.positive += usize::from(response.check_result);
+        if let Some(country_code) = &response.country_code {
+            let country = countries
+                .entry(country_code)
+                .or_insert_with(|| CountrySummary {
+                    country_code: country_code.clone(),
+                    records: 0,
+                    positive: 0,
+                });
+            country.records += 1;
+            country.positive += usize::from(response.check_result);
+        }
+    }
+    summary.countries = countries.into_values().collect();
+    summary.countries.sort_by(|a, b| {
+        (b.positive > 0, b.records, &a.country_code).cmp(&(
+            a.positive > 0,
+            a.records,
+            &b.country_code,
+        ))
+    });
+    summary.affected_countries = summary
+        .countries
+        .iter()
+        .filter(|country| country.positive > 0)
+        .map(|country| country.country_code.clone())
+        .collect();
+    summary
+}
+
+fn parse<T: DeserializeOwned>(
+    check: serde_json::Map<String, serde_json::Value>,
+) -> Result<T, NagoyaError> {
+    serde_json::from_value(serde_json::Value::Object(check)).map_err(|error| {
+        NagoyaError::MalformedItem {
+            reason: error.to_string(),
+        }
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::country::CountryId;
+    use crate::external_data::NominatimClient;
+    use crate::geocode_cache::GeocodeCache;
+    use crate::models::{Config, GeoLayers};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    pub(crate) fn state(batch_max_items: usize) -> AppState {
+        AppState::new(
+            Config {
+                nominatim_host: String::from("http://localhost:0"),
+                nominatim_email: None,
+                nominatim_accept_language: None,
+                declared_country_tolerance: 5000.0,
+                consensus_geocoders: Vec::new(),
+                consensus_require_agreement: false,
+                batch_concurrency: 2,
+                batch_max_items,
+                batch_job_max_items: batch_max_items,
+                batch_job_retention: Duration::from_secs(60),
+                upload_max_records: batch_max_items,
+                max_extracted_size: 1_048_576,
+                server_host: String::from("127.0.0.1"),
+                server_port: 3125,
+            },
+            ImplementingCountries::default(),
+            Duration::from_secs(60),
+            GeoLayers::default(),
+            Arc::new(GeocodeCache::new(7, 5, Duration::from_secs(60), 0)),
+            NominatimClient::new(1.0, 1, Duration::from_secs(1)),
+            None,
+        )
+    }
+
+    fn items(json: &str) -> Vec<BatchItem> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check_batch() {
+        let implementing_countries = ImplementingCountries {
+            countries: ["BRA".parse::<CountryId>().unwrap()].into(),
+            ..Default::default()
+        };
+        let results = check_batch(
+            items(
+                r#"[
+                    {"id": "a", "probe_country": "BR"},
+                    {"id": "b", "probe_country": "XYZ"},
+                    {"id": "c", "coordinates": {"latitude": 91, "longitude": 0}},
+                    {"id": "d", "coordinates": "nowhere"},
+                    {"id": "e", "countries": [{"country_code": "DE", "role": "country_of_origin"}]}
+                ]"#,
+            ),
+            &implementing_countries,
+            &state(10),
+            10,
+        )
+        .await
+        .unwrap();
+        let outcome: Vec<(&str, Option<bool>, Option<&str>)> = results
+            .iter()
+            .map(|result| {
+                (
+                    result.id.as_str(),
+                    result.result.as_ref().map(|response| response.check_result),
+                    result.error.as_ref().map(|error| error.error),
+                )
+            })
+            .collect();
+        assert_eq!(
+            outcome,
+            vec![
+                ("a", Some(true), None),
+                ("b", None, Some("malformed_item")),
+                ("c", None, Some("invalid_coordinates")),
+                ("d", None, Some("malformed_item")),
+                ("e", Some(false), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_summarize() {
+        let response = |country_code: &str, check_result: bool| {
+            Ok(NagoyaResponse {
+                check_result,
+                country_code: Some(country_code.to_string()),
+                ..Default::default()
+            })
+        };
+        let summary = summarize(&[
+            response("DE", false),
+            response("DE", false),
+            response("BR", true),
+            Err(NagoyaError::MissingCountry),
+            response("PE", true),
+            response("BR", true),
+        ]);
+        assert_eq!(
+            (summary.records, summary.positive, summary.failed),
+            (6, 3, 1)
+        );
+        assert_eq!(summary.affected_countries, vec!["BR", "PE"]);
+        assert_eq!(summary.countries[2].country_code, "DE");
+        assert_eq!(summary.countries[2].records, 2);
+    }
+
+    #[tokio::test]
+    async fn test_batch_too_large() {
+        assert!(matches!(
+            check_batch(
+                items(
+                    r#"[{"id": "a", "probe_country": "BR"}, {"id": "b", "probe_country": "DE"}]"#
+                ),
+                &ImplementingCountries::default(),
+                &state(1),
+                1,
+            )
+            .await,
+            Err(NagoyaError::BatchTooLarge { items: 2, max: 1 })
+        ));
+    }
+}
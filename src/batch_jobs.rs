@@ -0,0 +1,175 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::models::{BatchItemResult, BatchJobResponse, BatchJobStatus, NagoyaError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+struct BatchJob {
+    response: BatchJobResponse,
+    finished: Option<Instant>,
+}
+
+// Batches checked in the background, so batches of tens of thousands of items do not hold a
+// connection open for hours. Clients submit the items and poll for the result, which is kept for
+// the retention time once the job has finished
+pub struct BatchJobs {
+    jobs: Mutex<HashMap<String, BatchJob>>,
+    retention: Duration,
+}
+
+impl BatchJobs {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            retention,
+        }
+    }
+
+    // Registers a running job and returns it. The id is random, as it is all that protects the
+    // results of one client from the others
+    pub fn submit(&self, items: usize) -> BatchJobResponse {
+        let response = BatchJobResponse {
+            job_id: Uuid::new_v4().to_string(),
+            status: BatchJobStatus::Running,
+            items,
+            results: None,
+            error: None,
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        self.remove_expired(&mut jobs);
+        jobs.insert(
+            response.job_id.clone(),
+            BatchJob {
+                response: response.clone(),
+                finished: None,
+            },
+        );
+        response
+    }
+
+    pub fn finish(&self, job_id: &str, result: Result<Vec<BatchItemResult>, NagoyaError>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(job_id) else {
+            return;
+        };
+        match result {
+            Ok(results) => {
+                job.response.status = BatchJobStatus::Done;
+                job.response.results = Some(Arc::new(results));
+            }
+            Err(error) => {
+                job.response.status = BatchJobStatus::Failed;
+                job.response.error = Some(Arc::new(error.error_response()));
+            }
+        }
+        job.finished = Some(Instant::now());
+    }
+
+    pub fn get(&self, job_id: &str) -> Result<BatchJobResponse, NagoyaError> {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.remove_expired(&mut jobs);
+        jobs.get(job_id)
+            .map(|job| job.response.clone())
+            .ok_or_else(|| NagoyaError::UnknownBatchJob {
+                job_id: job_id.to_string(),
+            })
+    }
+
+    // Finished jobs are dropped lazily, whenever a job is submitted or polled
+    fn remove_expired(&self, jobs: &mut HashMap<String, BatchJob>) {
+        jobs.retain(|_, job| {
+            job.finished
+                .is_none_or(|finished| finished.elapsed() < self.retention)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{batch_job, submit_batch_job};
+    use crate::models::NagoyaCheckBatch;
+    use axum::Json;
+    use axum::extract::{Path, State};
+    use axum::http::{StatusCode, header};
+    use axum::response::IntoResponse;
+
+    #[test]
+    fn test_batch_jobs() {
+        let jobs = BatchJobs::new(Duration::from_secs(60));
+        let job = jobs.submit(2);
+        assert_eq!(job.status, BatchJobStatus::Running);
+        assert_eq!(jobs.get(&job.job_id).unwrap().items, 2);
+
+        jobs.finish(&job.job_id, Ok(Vec::new()));
+        let finished = jobs.get(&job.job_id).unwrap();
+        assert_eq!(finished.status, BatchJobStatus::Done);
+        assert!(finished.results.is_some());
+
+        let failed = jobs.submit(1);
+        jobs.finish(&failed.job_id, Err(NagoyaError::MissingCountry));
+        assert_eq!(
+            jobs.get(&failed.job_id).unwrap().error.unwrap().error,
+            "missing_country"
+        );
+        assert!(matches!(
+            jobs.get("unknown"),
+            Err(NagoyaError::UnknownBatchJob { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_submit_batch_job() {
+        let state = crate::batch::tests::state(2);
+        let items = |json: &str| Json(serde_json::from_str::<NagoyaCheckBatch>(json).unwrap());
+
+        let too_large = submit_batch_job(
+            State(state.clone()),
+            items(r#"{"items": [{"id": "a"}, {"id": "b"}, {"id": "c"}]}"#),
+        )
+        .await;
+        assert!(matches!(
+            too_large.err(),
+            Some(NagoyaError::BatchTooLarge { items: 3, max: 2 })
+        ));
+
+        let response = submit_batch_job(
+            State(state.clone()),
+            items(r#"{"items": [{"id": "a", "probe_country": "DE"}]}"#),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let job_id = location.strip_prefix("/nagoya_check_batch/jobs/").unwrap();
+
+        let job = loop {
+            let Json(job) = batch_job(State(state.clone()), Path(job_id.to_string()))
+                .await
+                .unwrap();
+            if job.status != BatchJobStatus::Running {
+                break job;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(job.status, BatchJobStatus::Done);
+        assert_eq!(job.results.unwrap()[0].id, "a");
+    }
+
+    #[test]
+    fn test_retention() {
+        let jobs = BatchJobs::new(Duration::ZERO);
+        let running = jobs.submit(1);
+        let finished = jobs.submit(1);
+        jobs.finish(&finished.job_id, Ok(Vec::new()));
+        // Running jobs are kept however long they take
+        assert!(jobs.get(&running.job_id).is_ok());
+        assert!(jobs.get(&finished.job_id).is_err());
+    }
+}
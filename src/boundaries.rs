@@ -0,0 +1,393 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use geo::{BoundingRect, Centroid, Contains, Intersects, LinesIter, MultiPolygon, Point, Rect};
+use geojson::GeoJson;
+use std::error::Error;
+use tracing::{Level, event, instrument};
+
+// Mean earth radius in meters, good enough for the distances we care about
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+// Property names under which common country datasets (Natural Earth, datasets/geo-countries)
+// store the ISO 3166-1 alpha-2 code. Natural Earth uses -99 for some countries in ISO_A2, hence
+// ISO_A2_EH comes first
+const CODE_PROPERTIES: [&str; 4] = ["ISO_A2_EH", "ISO_A2", "iso_a2", "ISO3166-1-Alpha-2"];
+
+// Country polygons loaded from a local GeoJSON file. Used to answer questions Nominatim cannot,
+// e.g. which countries are within a radius around a point
+pub struct CountryBoundaries {
+    countries: Vec<CountryBoundary>,
+}
+
+struct CountryBoundary {
+    code2: String,
+    geometry: MultiPolygon<f64>,
+    bounding_rect: Rect<f64>,
+    centroid: Option<Point<f64>>,
+}
+
+impl CountryBoundaries {
+    #[instrument]
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_geojson(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn from_geojson(geojson: &str) -> Result<Self, Box<dyn Error>> {
+        let mut countries = Vec::new();
+        for feature in polygon_features(geojson)? {
+            let Some(code2) = CODE_PROPERTIES
+                .iter()
+                .filter_map(|key| feature.properties.get(*key)?.as_str())
+                .find(|code| code.len() == 2)
+                .map(str::to_uppercase)
+            else {
+                // Features without a usable code, e.g. disputed areas, are skipped
+                continue;
+            };
+            countries.push(CountryBoundary {
+                code2,
+                centroid: feature.geometry.centroid(),
+                geometry: feature.geometry,
+                bounding_rect: feature.bounding_rect,
+            });
+        }
+
+        event!(
+            Level::INFO,
+            "Loaded boundaries for {} countries",
+            countries.len()
+        );
+        Ok(Self { countries })
+    }
+
+    // Returns the alpha-2 code of the country containing the point, if any
+    pub fn country_at(&self, latitude: f64, longitude: f64) -> Option<&str> {
+        let point = Point::new(longitude, latitude);
+        self.countries
+            .iter()
+            .find(|country| {
+                country.bounding_rect.contains(&point) && country.geometry.contains(&point)
+            })
+            .map(|country| country.code2.as_str())
+    }
+
+    // Returns the alpha-2 codes of all countries within the given radius around the point
+    pub fn countries_within(&self, latitude: f64, longitude: f64, radius: f64) -> Vec<String> {
+        let point = Point::new(longitude, latitude);
+        let mut codes: Vec<String> = self
+            .countries
+            .iter()
+            .filter(|country| distance_to_rect(&point, &country.bounding_rect) <= radius)
+            .filter(|country| {
+                country.geometry.contains(&point)
+                    || distance_to_outline(&point, &country.geometry) <= radius
+            })
+            .map(|country| country.code2.clone())
+            .collect();
+        codes.sort();
+        codes.dedup();
+        codes
+    }
+
+    // Returns the alpha-2 codes of all countries overlapping the box. Boxes with west > east cross
+    // the antimeridian and are split there
+    pub fn countries_intersecting(
+        &self,
+        west: f64,
+        east: f64,
+        south: f64,
+        north: f64,
+    ) -> Vec<String> {
+        let rects = if west <= east {
+            vec![Rect::new((west, south), (east, north))]
+        } else {
+            vec![
+                Rect::new((west, south), (180.0, north)),
+                Rect::new((-180.0, south), (east, north)),
+            ]
+        };
+        let mut codes: Vec<String> = self
+            .countries
+            .iter()
+            .filter(|country| {
+                rects.iter().any(|rect| {
+                    rect.intersects(&country.bounding_rect) && rect.intersects(&country.geometry)
+                })
+            })
+            .map(|country| country.code2.clone())
+            .collect();
+        codes.sort();
+        codes.dedup();
+        codes
+    }
+
+    // Returns the alpha-2 code of a country whose centroid lies within the tolerance around the
+    // point. Georeferences pointing at a centroid are usually derived from the country name only
+    pub fn centroid_country(&self, latitude: f64, longitude: f64, tolerance: f64) -> Option<&str> {
+        self.countries
+            .iter()
+            .find(|country| {
+                country.centroid.is_some_and(|centroid| {
+                    haversine_distance(latitude, longitude, centroid.y(), centroid.x()) <= tolerance
+                })
+            })
+            .map(|country| country.code2.as_str())
+    }
+
+    // Distance in meters to the closest country other than the one containing the point. For
+    // points inside a country this is the distance to its nearest land border, for points at sea
+    // the distance to the closest coast
+    pub fn distance_to_border(&self, latitude: f64, longitude: f64) -> Option<f64> {
+        let point = Point::new(longitude, latitude);
+        let own_country = self.country_at(latitude, longitude);
+        let mut best: Option<f64> = None;
+        for country in self
+            .countries
+            .iter()
+            .filter(|country| Some(country.code2.as_str()) != own_country)
+        {
+            // The bounding box is a cheap lower bound; skip countries that cannot be closer
+            if best.is_some_and(|best| distance_to_rect(&point, &country.bounding_rect) >= best) {
+                continue;
+            }
+            let distance = distance_to_outline(&point, &country.geometry);
+            if best.is_none_or(|best| distance < best) {
+                best = Some(distance);
+            }
+        }
+        best
+    }
+}
+
+// A (multi-)polygon feature of a GeoJSON FeatureCollection, the common format of all local layers
+pub struct PolygonFeature {
+    pub properties: geojson::JsonObject,
+    pub geometry: MultiPolygon<f64>,
+    pub bounding_rect: Rect<f64>,
+}
+
+impl PolygonFeature {
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        let point = Point::new(longitude, latitude);
+        self.bounding_rect.contains(&point) && self.geometry.contains(&point)
+    }
+
+    // Whether the feature lies within the radius around the point
+    pub fn within(&self, latitude: f64, longitude: f64, radius: f64) -> bool {
+        let point = Point::new(longitude, latitude);
+        distance_to_rect(&point, &self.bounding_rect) <= radius
+            && (self.geometry.contains(&point)
+                || distance_to_outline(&point, &self.geometry) <= radius)
+    }
+
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties.get(key)?.as_str()
+    }
+}
+
+// Reads all polygon and multipolygon features, other geometries are skipped
+pub fn polygon_features(geojson: &str) -> Result<Vec<PolygonFeature>, Box<dyn Error>> {
+    let GeoJson::FeatureCollection(collection) = geojson.parse::<GeoJson>()? else {
+        return Err("Expected a GeoJSON FeatureCollection".into());
+    };
+    let mut features = Vec::new();
+    for feature in collection.features {
+        let Some(geometry) = feature.geometry else {
+            continue;
+        };
+        let geometry = match geo::Geometry::<f64>::try_from(geometry)? {
+            geo::Geometry::Polygon(polygon) => MultiPolygon::new(vec![polygon]),
+            geo::Geometry::MultiPolygon(multi_polygon) => multi_polygon,
+            _ => continue,
+        };
+        let Some(bounding_rect) = geometry.bounding_rect() else {
+            continue;
+        };
+        features.push(PolygonFeature {
+            properties: feature.properties.unwrap_or_default(),
+            geometry,
+            bounding_rect,
+        });
+    }
+    Ok(features)
+}
+
+// Great-circle distance in meters between two points
+pub fn haversine_distance(
+    latitude_a: f64,
+    longitude_a: f64,
+    latitude_b: f64,
+    longitude_b: f64,
+) -> f64 {
+    let d_latitude = (latitude_b - latitude_a).to_radians();
+    let d_longitude = (longitude_b - longitude_a).to_radians();
+    let a = (d_latitude / 2.0).sin().powi(2)
+        + latitude_a.to_radians().cos()
+            * latitude_b.to_radians().cos()
+            * (d_longitude / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+// Distance in meters from the point to the outline of a country. Segments are projected onto a
+// local equirectangular plane around the point, which is accurate enough for the border distances
+// relevant for coordinate uncertainty
+fn distance_to_outline(point: &Point<f64>, geometry: &MultiPolygon<f64>) -> f64 {
+    let scale_x = point.y().to_radians().cos() * EARTH_RADIUS.to_radians();
+    let scale_y = EARTH_RADIUS.to_radians();
+    let to_local = |coord: geo::Coord<f64>| {
+        (
+            wrap_longitude(coord.x - point.x()) * scale_x,
+            (coord.y - point.y()) * scale_y,
+        )
+    };
+    geometry
+        .lines_iter()
+        .map(|line| {
+            let (ax, ay) = to_local(line.start);
+            let (bx, by) = to_local(line.end);
+            let (dx, dy) = (bx - ax, by - ay);
+            let length_squared = dx * dx + dy * dy;
+            let t = if length_squared == 0.0 {
+                0.0
+            } else {
+                (-(ax * dx + ay * dy) / length_squared).clamp(0.0, 1.0)
+            };
+            (ax + t * dx).hypot(ay + t * dy)
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+// Lower bound of the distance in meters from the point to anything inside the rectangle
+fn distance_to_rect(point: &Point<f64>, rect: &Rect<f64>) -> f64 {
+    let dx = if (rect.min().x..=rect.max().x).contains(&point.x()) {
+        0.0
+    } else {
+        wrap_longitude(rect.min().x - point.x())
+            .abs()
+            .min(wrap_longitude(point.x() - rect.max().x).abs())
+    };
+    let dy = (rect.min().y - point.y())
+        .max(point.y() - rect.max().y)
+        .max(0.0);
+    // Use the shortest parallel involved so the result stays a lower bound
+    let latitude = point
+        .y()
+        .abs()
+        .max(rect.min().y.abs())
+        .max(rect.max().y.abs());
+    let scale_x = latitude.to_radians().cos() * EARTH_RADIUS.to_radians();
+    (dx * scale_x).hypot(dy * EARTH_RADIUS.to_radians())
+}
+
+// Longitude difference taken the short way round, so points near the antimeridian are close to
+// the countries on its other side
+fn wrap_longitude(difference: f64) -> f64 {
+    (difference + 180.0).rem_euclid(360.0) - 180.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two adjacent squares sharing the meridian at 10°E
+    const TESTDATA: &str = r#"
+    {
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "properties": { "ISO_A2": "AA" },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[9, 0], [10, 0], [10, 1], [9, 1], [9, 0]]]
+                }
+            },
+            {
+                "type": "Feature",
+                "properties": { "ISO_A2": "-99", "ISO_A2_EH": "BB" },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[10, 0], [11, 0], [11, 1], [10, 1], [10, 0]]]
+                }
+            }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn test_country_at() {
+        let boundaries = CountryBoundaries::from_geojson(TESTDATA).unwrap();
+        assert_eq!(boundaries.country_at(0.5, 9.5), Some("AA"));
+        assert_eq!(boundaries.country_at(0.5, 10.5), Some("BB"));
+        assert_eq!(boundaries.country_at(0.5, 12.0), None);
+    }
+
+    #[test]
+    fn test_centroid_country() {
+        let boundaries = CountryBoundaries::from_geojson(TESTDATA).unwrap();
+        assert_eq!(boundaries.centroid_country(0.5, 9.5, 1_000.0), Some("AA"));
+        assert_eq!(boundaries.centroid_country(0.9, 9.5, 1_000.0), None);
+    }
+
+    #[test]
+    fn test_countries_within() {
+        let boundaries = CountryBoundaries::from_geojson(TESTDATA).unwrap();
+        // Roughly 2.2 km west of the border
+        assert_eq!(boundaries.countries_within(0.5, 9.98, 1_000.0), vec!["AA"]);
+        assert_eq!(
+            boundaries.countries_within(0.5, 9.98, 10_000.0),
+            vec!["AA", "BB"]
+        );
+    }
+
+    #[test]
+    fn test_countries_intersecting() {
+        let boundaries = CountryBoundaries::from_geojson(TESTDATA).unwrap();
+        assert_eq!(
+            boundaries.countries_intersecting(9.2, 9.8, 0.2, 0.8),
+            vec!["AA"]
+        );
+        assert_eq!(
+            boundaries.countries_intersecting(9.5, 10.5, 0.2, 0.8),
+            vec!["AA", "BB"]
+        );
+        assert!(
+            boundaries
+                .countries_intersecting(12.0, 13.0, 0.2, 0.8)
+                .is_empty()
+        );
+        // Across the antimeridian, reaching BB only through its western part
+        assert_eq!(
+            boundaries.countries_intersecting(170.0, 10.5, 0.2, 0.8),
+            vec!["AA", "BB"]
+        );
+    }
+
+    #[test]
+    fn test_distance_to_border() {
+        let boundaries = CountryBoundaries::from_geojson(TESTDATA).unwrap();
+        let distance = boundaries.distance_to_border(0.5, 9.98).unwrap();
+        assert!((distance - 2_226.0).abs() < 5.0, "{distance}");
+    }
+
+    #[test]
+    fn test_antimeridian_distance() {
+        // A square just east of the antimeridian, like Fiji's easternmost islands
+        let boundaries = CountryBoundaries::from_geojson(
+            r#"{"type": "FeatureCollection", "features": [{"type": "Feature",
+            "properties": {"ISO_A2": "FJ"}, "geometry": {"type": "Polygon",
+            "coordinates": [[[-180, 0], [-179, 0], [-179, 1], [-180, 1], [-180, 0]]]}}]}"#,
+        )
+        .unwrap();
+        // Roughly 11 km west of it across the antimeridian
+        let distance = boundaries.distance_to_border(0.5, 179.9).unwrap();
+        assert!((distance - 11_119.0).abs() < 10.0, "{distance}");
+        assert_eq!(
+            boundaries.countries_within(0.5, 179.9, 20_000.0),
+            vec!["FJ"]
+        );
+        assert!(boundaries.countries_within(0.5, 179.9, 5_000.0).is_empty());
+    }
+}
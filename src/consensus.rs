@@ -0,0 +1,127 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::boundaries::CountryBoundaries;
+use crate::models::{ConsensusInfo, Coordinates, GeocoderAnswer};
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+// Backends able to resolve coordinates to a country
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum GeocoderBackend {
+    Nominatim,
+    // Offline lookup in the local country boundaries
+    Boundaries,
+}
+
+impl FromStr for GeocoderBackend {
+    type Err = String;
+
+    fn from_str(backend: &str) -> Result<Self, Self::Err> {
+        match backend.trim() {
+            "nominatim" => Ok(GeocoderBackend::Nominatim),
+            "boundaries" => Ok(GeocoderBackend::Boundaries),
+            _ => Err(format!("Unknown geocoder backend \"{backend}\"")),
+        }
+    }
+}
+
+impl Display for GeocoderBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeocoderBackend::Nominatim => write!(f, "nominatim"),
+            GeocoderBackend::Boundaries => write!(f, "boundaries"),
+        }
+    }
+}
+
+// Asks every backend for the country at the coordinates. Nominatim has been asked already for the
+// verdict, so its answer is passed in instead of sending a second request
+pub fn consensus(
+    backends: &[GeocoderBackend],
+    nominatim_country: &str,
+    coordinates: &Coordinates,
+    boundaries: Option<&CountryBoundaries>,
+) -> ConsensusInfo {
+    let answers: Vec<GeocoderAnswer> = backends
+        .iter()
+        .map(|backend| GeocoderAnswer {
+            geocoder: backend.to_string(),
+            country_code: match backend {
+                GeocoderBackend::Nominatim => Some(nominatim_country.to_uppercase()),
+                GeocoderBackend::Boundaries => boundaries
+                    .and_then(|boundaries| {
+                        boundaries.country_at(coordinates.latitude, coordinates.longitude)
+                    })
+                    .map(str::to_string),
+            },
+        })
+        .collect();
+    // A backend without an answer disagrees as well, e.g. coastal points missing the polygons. A
+    // single answer has nothing to agree with
+    let agreement = answers.len() >= 2
+        && answers
+            .windows(2)
+            .all(|pair| pair[0].country_code == pair[1].country_code);
+    ConsensusInfo { agreement, answers }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TESTDATA: &str = r#"
+    {
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "properties": { "ISO_A2": "AA" },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[9, 0], [10, 0], [10, 1], [9, 1], [9, 0]]]
+                }
+            }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn test_consensus() {
+        let boundaries = CountryBoundaries::from_geojson(TESTDATA).unwrap();
+        let backends = [GeocoderBackend::Nominatim, GeocoderBackend::Boundaries];
+        let coordinates = Coordinates {
+            latitude: 0.5,
+            longitude: 9.5,
+            uncertainty_in_meters: None,
+        };
+        assert!(consensus(&backends, "aa", &coordinates, Some(&boundaries)).agreement);
+        assert!(
+            !consensus(
+                &[GeocoderBackend::Nominatim],
+                "aa",
+                &coordinates,
+                Some(&boundaries)
+            )
+            .agreement
+        );
+
+        let info = consensus(&backends, "bb", &coordinates, Some(&boundaries));
+        assert!(!info.agreement);
+        assert_eq!(
+            info.answers,
+            vec![
+                GeocoderAnswer {
+                    geocoder: String::from("nominatim"),
+                    country_code: Some(String::from("BB")),
+                },
+                GeocoderAnswer {
+                    geocoder: String::from("boundaries"),
+                    country_code: Some(String::from("AA")),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,508 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::models::{CoordinateInput, Coordinates, NagoyaError};
+use tracing::{Level, event, instrument};
+
+// WGS84 ellipsoid as used by UTM and MGRS
+const WGS84_SEMI_MAJOR_AXIS: f64 = 6_378_137.0;
+const WGS84_FLATTENING: f64 = 1.0 / 298.257_223_563;
+const UTM_SCALE_FACTOR: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.0;
+const UTM_FALSE_NORTHING_SOUTH: f64 = 10_000_000.0;
+
+// Latitude bands C to X starting at 80°S, each 8° high except X, which covers 72°N to 84°N. I and O
+// are left out to avoid confusion with digits
+const LATITUDE_BANDS: &str = "CDEFGHJKLMNPQRSTUVWX";
+// Northing of the southern edge of each latitude band, rounded down to 100 km. Needed to place the
+// 100 km square of an MGRS reference, as the row letters repeat every 2000 km
+const BAND_MIN_NORTHING: [f64; 20] = [
+    1_100_000.0,
+    2_000_000.0,
+    2_800_000.0,
+    3_700_000.0,
+    4_600_000.0,
+    5_500_000.0,
+    6_400_000.0,
+    7_300_000.0,
+    8_200_000.0,
+    9_100_000.0,
+    0.0,
+    800_000.0,
+    1_700_000.0,
+    2_600_000.0,
+    3_500_000.0,
+    4_400_000.0,
+    5_300_000.0,
+    6_200_000.0,
+    7_000_000.0,
+    7_900_000.0,
+];
+const MGRS_COLUMN_LETTERS: [&str; 3] = ["ABCDEFGH", "JKLMNPQR", "STUVWXYZ"];
+const MGRS_ROW_LETTERS: &str = "ABCDEFGHJKLMNPQRSTUV";
+
+const OLC_ALPHABET: &str = "23456789CFGHJMPQRVWX";
+const OLC_SEPARATOR_POSITION: usize = 8;
+const OLC_PAIR_LENGTH: usize = 10;
+
+// Turns any supported notation into decimal WGS84 coordinates
+#[instrument]
+pub fn normalize(input: CoordinateInput) -> Result<Coordinates, NagoyaError> {
+    match input {
+        CoordinateInput::Decimal(coordinates) => Ok(coordinates),
+        CoordinateInput::Notation(notation) => {
+            let mut coordinates = parse(&notation.notation)?;
+            // An explicit uncertainty wins over one given in the notation, e.g. a geo URI
+            if notation.uncertainty_in_meters.is_some() {
+                coordinates.uncertainty_in_meters = notation.uncertainty_in_meters;
+            }
+            event!(
+                Level::DEBUG,
+                "Normalized \"{}\" to {}, {}",
+                &notation.notation,
+                coordinates.latitude,
+                coordinates.longitude
+            );
+            Ok(coordinates)
+        }
+    }
+}
+
+// Parses a single coordinate notation. The notation is detected from its shape, as label values
+// rarely come with a description of their format
+pub fn parse(notation: &str) -> Result<Coordinates, NagoyaError> {
+    let notation = notation.trim();
+    let upper = notation.to_uppercase();
+    if upper.starts_with("GEO:") {
+        parse_geo_uri(notation)
+    } else if upper.contains("POINT") {
+        parse_wkt(&upper)
+    } else if is_open_location_code(&upper) {
+        parse_open_location_code(&upper)
+    } else if let Some(coordinates) = parse_mgrs(&upper) {
+        coordinates
+    } else if let Some(coordinates) = parse_utm(&upper) {
+        coordinates
+    } else {
+        parse_degrees(&upper)
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> NagoyaError {
+    NagoyaError::InvalidCoordinates {
+        reason: reason.into(),
+    }
+}
+
+fn parse_number(value: &str) -> Result<f64, NagoyaError> {
+    value
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| invalid(format!("\"{value}\" is not a number")))
+}
+
+fn point(latitude: f64, longitude: f64, uncertainty_in_meters: Option<f64>) -> Coordinates {
+    Coordinates {
+        latitude,
+        longitude,
+        uncertainty_in_meters,
+    }
+}
+
+// RFC 5870, e.g. geo:13.4125,103.8667;u=35
+fn parse_geo_uri(notation: &str) -> Result<Coordinates, NagoyaError> {
+    let mut parts = notation[4..].split(';');
+    let position: Vec<&str> = parts.next().unwrap_or_default().split(',').collect();
+    if !(2..=3).contains(&position.len()) {
+        return Err(invalid("geo URI needs latitude and longitude"));
+    }
+    let mut uncertainty = None;
+    for parameter in parts {
+        let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+        match key.to_lowercase().as_str() {
+            "crs" if !value.eq_ignore_ascii_case("wgs84") => {
+                return Err(invalid(format!("Unsupported geo URI crs \"{value}\"")));
+            }
+            "u" => uncertainty = Some(parse_number(value)?),
+            _ => {}
+        }
+    }
+    Ok(point(
+        parse_number(position[0])?,
+        parse_number(position[1])?,
+        uncertainty,
+    ))
+}
+
+// POINT(lon lat), optionally with a Z/M suffix or as EWKT with an SRID prefix
+fn parse_wkt(notation: &str) -> Result<Coordinates, NagoyaError> {
+    if let Some((srid, _)) = notation.split_once(';')
+        && srid.trim() != "SRID=4326"
+    {
+        return Err(invalid(format!("Unsupported WKT {}", srid.trim())));
+    }
+    let (Some(start), Some(end)) = (notation.find('('), notation.rfind(')')) else {
+        return Err(invalid("WKT point needs its coordinates in parentheses"));
+    };
+    if end < start {
+        return Err(invalid("WKT point needs its coordinates in parentheses"));
+    }
+    let values: Vec<&str> = notation[start + 1..end].split_whitespace().collect();
+    if values.len() < 2 {
+        return Err(invalid("WKT point needs x and y"));
+    }
+    Ok(point(
+        parse_number(values[1])?,
+        parse_number(values[0])?,
+        None,
+    ))
+}
+
+fn is_open_location_code(notation: &str) -> bool {
+    notation.find('+') == Some(OLC_SEPARATOR_POSITION)
+        && notation
+            .chars()
+            .all(|c| c == '+' || c == '0' || OLC_ALPHABET.contains(c))
+}
+
+// Full Open Location Codes (plus codes) such as 8FVC9G8F+6X. Short codes need a reference
+// location and are not supported. The center of the code area is returned
+fn parse_open_location_code(notation: &str) -> Result<Coordinates, NagoyaError> {
+    let digits: Vec<usize> = notation
+        .chars()
+        .filter(|c| *c != '+' && *c != '0')
+        .map(|c| OLC_ALPHABET.find(c).unwrap_or_default())
+        .collect();
+    if digits.len() < 2 || digits.len() % 2 == 1 && digits.len() < OLC_PAIR_LENGTH {
+        return Err(invalid("Open Location Code is too short"));
+    }
+    let (mut latitude, mut longitude) = (-90.0, -180.0);
+    let mut resolution = 20.0;
+    let mut latitude_resolution = resolution;
+    let mut longitude_resolution = resolution;
+    for pair in digits[..digits.len().min(OLC_PAIR_LENGTH)].chunks(2) {
+        latitude += pair[0] as f64 * resolution;
+        longitude += pair[1] as f64 * resolution;
+        latitude_resolution = resolution;
+        longitude_resolution = resolution;
+        resolution /= 20.0;
+    }
+    // Digits after the pairs refine the area in a grid of 4 columns and 5 rows
+    for digit in digits.iter().skip(OLC_PAIR_LENGTH) {
+        latitude_resolution /= 5.0;
+        longitude_resolution /= 4.0;
+        latitude += (digit / 4) as f64 * latitude_resolution;
+        longitude += (digit % 4) as f64 * longitude_resolution;
+    }
+    Ok(point(
+        latitude + latitude_resolution / 2.0,
+        longitude + longitude_resolution / 2.0,
+        None,
+    ))
+}
+
+// Splits a grid zone designation like 32U into zone number and latitude band
+fn parse_grid_zone(designation: &str) -> Option<(u8, char)> {
+    let band = designation.chars().last()?;
+    let zone = designation[..designation.len() - band.len_utf8()]
+        .parse::<u8>()
+        .ok()?;
+    ((1..=60).contains(&zone) && LATITUDE_BANDS.contains(band)).then_some((zone, band))
+}
+
+// MGRS references such as 33UXP0500444996 or 33U XP 05004 44996
+fn parse_mgrs(notation: &str) -> Option<Result<Coordinates, NagoyaError>> {
+    let compact: String = notation.split_whitespace().collect();
+    let zone_length = compact.find(|c: char| c.is_ascii_alphabetic())? + 1;
+    let (zone, band) = parse_grid_zone(compact.get(..zone_length)?)?;
+    let mut square = compact[zone_length..].chars();
+    let (column, row) = (square.next()?, square.next()?);
+    if !column.is_ascii_alphabetic() || !row.is_ascii_alphabetic() {
+        return None;
+    }
+    let numerical = &compact[zone_length + 2..];
+    if numerical.len() % 2 == 1
+        || numerical.len() > 10
+        || !numerical.chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let Some(column_index) = MGRS_COLUMN_LETTERS[(zone as usize - 1) % 3].find(column) else {
+        return Some(Err(invalid(format!(
+            "Invalid MGRS column letter {column} for zone {zone}"
+        ))));
+    };
+    let Some(row_index) = MGRS_ROW_LETTERS.find(row) else {
+        return Some(Err(invalid(format!("Invalid MGRS row letter {row}"))));
+    };
+    // Even zones start their row letters at F
+    let row_index = (row_index + if zone % 2 == 0 { 15 } else { 0 }) % 20;
+
+    let precision = numerical.len() / 2;
+    let scale = 10f64.powi(5 - precision as i32);
+    let easting_in_square = numerical[..precision].parse::<f64>().unwrap_or(0.0) * scale;
+    let northing_in_square = numerical[precision..].parse::<f64>().unwrap_or(0.0) * scale;
+
+    let easting = (column_index + 1) as f64 * 100_000.0 + easting_in_square;
+    let band_index = LATITUDE_BANDS.find(band)?;
+    let mut northing = row_index as f64 * 100_000.0 + northing_in_square;
+    while northing < BAND_MIN_NORTHING[band_index] {
+        northing += 2_000_000.0;
+    }
+    // Center of the referenced square, matching the precision of the reference
+    let center = if precision < 5 { scale / 2.0 } else { 0.0 };
+    Some(Ok(utm_to_wgs84(
+        zone,
+        band >= 'N',
+        easting + center,
+        northing + center,
+    )))
+}
+
+// UTM coordinates given as zone with latitude band, easting and northing, e.g. 32U 456789 5432109
+fn parse_utm(notation: &str) -> Option<Result<Coordinates, NagoyaError>> {
+    let parts: Vec<&str> = notation
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|part| !part.is_empty())
+        .collect();
+    let [designation, easting, northing] = parts.as_slice() else {
+        return None;
+    };
+    let (zone, band) = parse_grid_zone(designation)?;
+    // Easting and northing are sometimes suffixed, e.g. 456448mE 5429127mN
+    let [easting, northing] =
+        [easting, northing].map(|value| value.trim_end_matches(['E', 'N']).trim_end_matches('M'));
+    let (easting, northing) = (easting.parse::<f64>().ok()?, northing.parse::<f64>().ok()?);
+    Some(Ok(utm_to_wgs84(zone, band >= 'N', easting, northing)))
+}
+
+// Inverse transverse mercator using the Krüger series, accurate to well below a meter within a
+// UTM zone
+pub fn utm_to_wgs84(zone: u8, northern: bool, easting: f64, northing: f64) -> Coordinates {
+    let n = WGS84_FLATTENING / (2.0 - WGS84_FLATTENING);
+    let a = WGS84_SEMI_MAJOR_AXIS / (1.0 + n) * (1.0 + n.powi(2) / 4.0 + n.powi(4) / 64.0);
+    let beta = [
+        n / 2.0 - 2.0 / 3.0 * n.powi(2) + 37.0 / 96.0 * n.powi(3),
+        n.powi(2) / 48.0 + n.powi(3) / 15.0,
+        17.0 / 480.0 * n.powi(3),
+    ];
+    let delta = [
+        2.0 * n - 2.0 / 3.0 * n.powi(2) - 2.0 * n.powi(3),
+        7.0 / 3.0 * n.powi(2) - 8.0 / 5.0 * n.powi(3),
+        56.0 / 15.0 * n.powi(3),
+    ];
+
+    let false_northing = if northern {
+        0.0
+    } else {
+        UTM_FALSE_NORTHING_SOUTH
+    };
+    let xi = (northing - false_northing) / (UTM_SCALE_FACTOR * a);
+    let eta = (easting - UTM_FALSE_EASTING) / (UTM_SCALE_FACTOR * a);
+    let (mut xi_prime, mut eta_prime) = (xi, eta);
+    for (j, beta) in beta.iter().enumerate() {
+        let k = 2.0 * (j + 1) as f64;
+        xi_prime -= beta * (k * xi).sin() * (k * eta).cosh();
+        eta_prime -= beta * (k * xi).cos() * (k * eta).sinh();
+    }
+    let chi = (xi_prime.sin() / eta_prime.cosh()).asin();
+    let mut latitude = chi;
+    for (j, delta) in delta.iter().enumerate() {
+        latitude += delta * (2.0 * (j + 1) as f64 * chi).sin();
+    }
+    let central_meridian = zone as f64 * 6.0 - 183.0;
+    let longitude = central_meridian + eta_prime.sinh().atan2(xi_prime.cos()).to_degrees();
+    point(latitude.to_degrees(), longitude, None)
+}
+
+// Degrees, minutes and seconds like 12°30'15"S 45°10'W as well as decimal degrees with or without
+// hemisphere letters like -12.504, 45.17 or N 49.01 E 8.41
+fn parse_degrees(notation: &str) -> Result<Coordinates, NagoyaError> {
+    // Each component is a list of numbers (degrees, minutes, seconds) and its hemisphere
+    let mut components: Vec<(Vec<f64>, Option<char>)> = Vec::new();
+    let mut current: (Vec<f64>, Option<char>) = (Vec::new(), None);
+    let mut number = String::new();
+
+    let flush_number =
+        |number: &mut String, current: &mut (Vec<f64>, Option<char>)| -> Result<(), NagoyaError> {
+            if !number.is_empty() {
+                current.0.push(parse_number(number)?);
+                number.clear();
+            }
+            Ok(())
+        };
+
+    for c in notation.chars() {
+        match c {
+            '0'..='9' | '.' => number.push(c),
+            '-' | '+' if number.is_empty() => number.push(c),
+            // O is east in German and Dutch (Ost, Oost) but west in Spanish, Portuguese and
+            // French (Oeste, Ouest), so it is rejected rather than guessed
+            'O' => {
+                return Err(invalid(
+                    "Hemisphere O is ambiguous between east and west, use E or W",
+                ));
+            }
+            'N' | 'S' | 'E' | 'W' => {
+                flush_number(&mut number, &mut current)?;
+                if current.0.is_empty() {
+                    // Prefixed hemisphere, e.g. N 49°
+                    current.1 = Some(c);
+                } else if current.1.is_some() {
+                    // A component with a prefixed hemisphere ends where the next one begins, e.g.
+                    // the W in N 49 W 8
+                    components.push(std::mem::replace(&mut current, (Vec::new(), Some(c))));
+                } else {
+                    current.1 = Some(c);
+                    components.push(std::mem::take(&mut current));
+                }
+            }
+            ',' | ';' => {
+                flush_number(&mut number, &mut current)?;
+                if !current.0.is_empty() {
+                    components.push(std::mem::take(&mut current));
+                }
+            }
+            // Degree, minute and second signs in their various forms as well as whitespace
+            _ if c.is_whitespace() || "°º˚'′’\"″”".contains(c) => {
+                flush_number(&mut number, &mut current)?;
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "Unexpected character '{c}' in coordinates"
+                )));
+            }
+        }
+    }
+    flush_number(&mut number, &mut current)?;
+    if !current.0.is_empty() {
+        components.push(current);
+    }
+
+    // Two plain decimal numbers separated by whitespace only
+    if components.len() == 1 && components[0].1.is_none() && components[0].0.len() == 2 {
+        let values = &components[0].0;
+        return Ok(point(values[0], values[1], None));
+    }
+    let [first, second] = components.as_slice() else {
+        return Err(invalid("Could not find latitude and longitude"));
+    };
+    let first_value = degrees_to_decimal(first)?;
+    let second_value = degrees_to_decimal(second)?;
+    match (first.1, second.1) {
+        (Some('E' | 'W'), Some('N' | 'S') | None) | (None, Some('N' | 'S')) => {
+            Ok(point(second_value, first_value, None))
+        }
+        (Some('E' | 'W'), Some('E' | 'W')) | (Some('N' | 'S'), Some('N' | 'S')) => {
+            Err(invalid("Coordinates need one latitude and one longitude"))
+        }
+        _ => Ok(point(first_value, second_value, None)),
+    }
+}
+
+fn degrees_to_decimal((values, hemisphere): &(Vec<f64>, Option<char>)) -> Result<f64, NagoyaError> {
+    if values.len() > 3 || values[1..].iter().any(|value| !(0.0..60.0).contains(value)) {
+        return Err(invalid("Minutes and seconds need to be between 0 and 60"));
+    }
+    let degrees = values[0];
+    let magnitude = degrees.abs()
+        + values.get(1).copied().unwrap_or(0.0) / 60.0
+        + values.get(2).copied().unwrap_or(0.0) / 3600.0;
+    let negative = degrees.is_sign_negative() || matches!(hemisphere, Some('S' | 'W'));
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(coordinates: Coordinates, latitude: f64, longitude: f64) {
+        assert!(
+            (coordinates.latitude - latitude).abs() < 1e-4
+                && (coordinates.longitude - longitude).abs() < 1e-4,
+            "{coordinates:?} is not close to {latitude}, {longitude}"
+        );
+    }
+
+    #[test]
+    fn test_parse_degrees() {
+        assert_close(
+            parse("12°30'15\"S 45°10'W").unwrap(),
+            -12.504_166,
+            -45.166_666,
+        );
+        assert_close(
+            parse("S 12 30 15, W 45 10").unwrap(),
+            -12.504_166,
+            -45.166_666,
+        );
+        assert_close(
+            parse("S 12 30 15 W 45 10").unwrap(),
+            -12.504_166,
+            -45.166_666,
+        );
+        assert_close(parse("N 49.01 E 8.41").unwrap(), 49.01, 8.41);
+        assert_close(parse("N 49 W 8").unwrap(), 49.0, -8.0);
+        assert_close(
+            parse("45°10'W 12°30'15\"S").unwrap(),
+            -12.504_166,
+            -45.166_666,
+        );
+        assert_close(parse("49.0093, 8.4037").unwrap(), 49.0093, 8.4037);
+        assert_close(parse("-12.5 45.25").unwrap(), -12.5, 45.25);
+        assert_close(parse("49.0093N 8.4037E").unwrap(), 49.0093, 8.4037);
+        assert!(parse("12°75'N 45°W").is_err());
+        assert!(parse("12°N 45°N").is_err());
+        assert!(parse("12°30'S 45°10'O").is_err());
+        assert!(parse("somewhere in the forest").is_err());
+    }
+
+    #[test]
+    fn test_parse_wkt() {
+        assert_close(parse("POINT(8.4037 49.0093)").unwrap(), 49.0093, 8.4037);
+        assert_close(
+            parse("SRID=4326;POINT Z (8.4037 49.0093 115)").unwrap(),
+            49.0093,
+            8.4037,
+        );
+        assert!(parse("SRID=25832;POINT(456789 5432109)").is_err());
+        assert!(parse("POINT)(").is_err());
+    }
+
+    #[test]
+    fn test_parse_geo_uri() {
+        let coordinates = parse("geo:13.4125,103.8667;u=35").unwrap();
+        assert_close(coordinates.clone(), 13.4125, 103.8667);
+        assert_eq!(coordinates.uncertainty_in_meters, Some(35.0));
+        assert!(parse("geo:13.4125,103.8667;crs=Moon-2011").is_err());
+    }
+
+    #[test]
+    fn test_parse_utm() {
+        // Karlsruhe Palace
+        assert_close(parse("32U 456448 5429127").unwrap(), 49.0135, 8.4044);
+        assert_close(parse("32U 456448mE 5429127mN").unwrap(), 49.0135, 8.4044);
+        // Sydney Opera House
+        assert_close(parse("56H 334901 6252289").unwrap(), -33.8568, 151.2153);
+    }
+
+    #[test]
+    fn test_parse_mgrs() {
+        assert_close(parse("32UMV5644829127").unwrap(), 49.0135, 8.4044);
+        assert_close(parse("56H LH 34900 52288").unwrap(), -33.8568, 151.2153);
+        assert!(parse("32UAV5644829127").is_err());
+        assert!(parse("32UXÉ1").is_err());
+        // Band X is 12° high, its northern edge at 84°N is still reached
+        let coordinates = parse("33XWP0000020000").unwrap();
+        assert!((83.9..84.0).contains(&coordinates.latitude));
+    }
+
+    #[test]
+    fn test_parse_open_location_code() {
+        // Example from the Open Location Code specification, Merlion in Singapore
+        assert_close(parse("6PH57VP3+PR6").unwrap(), 1.286_785, 103.854_503);
+        assert!(parse("8FVC0000+").is_ok());
+    }
+}
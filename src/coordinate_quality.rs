@@ -0,0 +1,188 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::boundaries::{CountryBoundaries, haversine_distance};
+use crate::models::{CoordinateQualityFlag, Coordinates, NagoyaError};
+use geojson::GeoJson;
+use std::error::Error;
+use tracing::{Level, event, instrument};
+
+// Georeferences derived from a country or capital name are rarely exact, thus the tolerance
+const REFERENCE_POINT_TOLERANCE: f64 = 5_000.0;
+
+// Capital cities loaded from a local GeoJSON file of points, e.g. the admin-0 capitals from
+// Natural Earth's populated places
+pub struct Capitals {
+    points: Vec<(f64, f64)>,
+}
+
+impl Capitals {
+    #[instrument]
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_geojson(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn from_geojson(geojson: &str) -> Result<Self, Box<dyn Error>> {
+        let GeoJson::FeatureCollection(collection) = geojson.parse::<GeoJson>()? else {
+            return Err("Capitals need to be a FeatureCollection".into());
+        };
+        let points: Vec<(f64, f64)> = collection
+            .features
+            .into_iter()
+            .filter_map(|feature| match feature.geometry?.value {
+                geojson::Value::Point(position) if position.len() >= 2 => {
+                    Some((position[1], position[0]))
+                }
+                _ => None,
+            })
+            .collect();
+        event!(Level::INFO, "Loaded {} capitals", points.len());
+        Ok(Self { points })
+    }
+
+    fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        self.points
+            .iter()
+            .any(|(capital_latitude, capital_longitude)| {
+                haversine_distance(latitude, longitude, *capital_latitude, *capital_longitude)
+                    <= REFERENCE_POINT_TOLERANCE
+            })
+    }
+}
+
+// Rejects coordinates which cannot describe a point on earth. Those would otherwise be sent to
+// Nominatim as is
+pub fn validate(coordinates: &Coordinates) -> Result<(), NagoyaError> {
+    let Coordinates {
+        latitude,
+        longitude,
+        uncertainty_in_meters,
+    } = *coordinates;
+    if !latitude.is_finite() || !longitude.is_finite() {
+        return Err(NagoyaError::InvalidCoordinates {
+            reason: String::from("latitude and longitude need to be finite numbers"),
+        });
+    }
+    if !(-90.0..=90.0).contains(&latitude) {
+        let hint = if (-90.0..=90.0).contains(&longitude) {
+            ", latitude and longitude are possibly swapped"
+        } else {
+            ""
+        };
+        return Err(NagoyaError::InvalidCoordinates {
+            reason: format!("latitude {latitude} is outside of [-90, 90]{hint}"),
+        });
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(NagoyaError::InvalidCoordinates {
+            reason: format!("longitude {longitude} is outside of [-180, 180]"),
+        });
+    }
+    if uncertainty_in_meters.is_some_and(|radius| !radius.is_finite() || radius < 0.0) {
+        return Err(NagoyaError::InvalidCoordinates {
+            reason: String::from("uncertainty_in_meters needs to be a non-negative number"),
+        });
+    }
+    Ok(())
+}
+
+// Data quality issues in the style of GBIF's occurrence issues. Those do not change the result of
+// the check, but point curators at georeferences which likely need fixing
+pub fn quality_flags(
+    coordinates: &Coordinates,
+    boundaries: Option<&CountryBoundaries>,
+    capitals: Option<&Capitals>,
+) -> Vec<CoordinateQualityFlag> {
+    let (latitude, longitude) = (coordinates.latitude, coordinates.longitude);
+    let mut flags = Vec::new();
+    if latitude == 0.0 && longitude == 0.0 {
+        flags.push(CoordinateQualityFlag::ZeroCoordinate);
+    }
+    if let Some(boundaries) = boundaries {
+        // A point in the sea which would be on land with latitude and longitude swapped
+        if longitude.abs() <= 90.0
+            && boundaries.country_at(latitude, longitude).is_none()
+            && boundaries.country_at(longitude, latitude).is_some()
+        {
+            flags.push(CoordinateQualityFlag::PresumedSwappedCoordinate);
+        }
+        if boundaries
+            .centroid_country(latitude, longitude, REFERENCE_POINT_TOLERANCE)
+            .is_some()
+        {
+            flags.push(CoordinateQualityFlag::CountryCentroid);
+        }
+    }
+    if capitals.is_some_and(|capitals| capitals.contains(latitude, longitude)) {
+        flags.push(CoordinateQualityFlag::CapitalCoordinate);
+    }
+    if decimal_places(latitude) <= 1 && decimal_places(longitude) <= 1 {
+        flags.push(CoordinateQualityFlag::LowPrecision);
+    }
+    flags
+}
+
+// Number of decimal places in the shortest representation of the value
+fn decimal_places(value: f64) -> usize {
+    let value = value.to_string();
+    value
+        .split_once('.')
+        .map_or(0, |(_, decimals)| decimals.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coordinates(latitude: f64, longitude: f64) -> Coordinates {
+        Coordinates {
+            latitude,
+            longitude,
+            uncertainty_in_meters: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&coordinates(49.01, 8.41)).is_ok());
+        assert!(validate(&coordinates(f64::NAN, 8.41)).is_err());
+        assert!(validate(&coordinates(49.01, 180.5)).is_err());
+        assert_eq!(
+            validate(&coordinates(200.0, 8.41)).unwrap_err(),
+            NagoyaError::InvalidCoordinates {
+                reason: String::from(
+                    "latitude 200 is outside of [-90, 90], latitude and longitude are possibly swapped"
+                )
+            }
+        );
+    }
+
+    #[test]
+    fn test_quality_flags() {
+        assert_eq!(
+            quality_flags(&coordinates(0.0, 0.0), None, None),
+            vec![
+                CoordinateQualityFlag::ZeroCoordinate,
+                CoordinateQualityFlag::LowPrecision
+            ]
+        );
+        assert_eq!(
+            quality_flags(&coordinates(49.0, 8.4), None, None),
+            vec![CoordinateQualityFlag::LowPrecision]
+        );
+        assert!(quality_flags(&coordinates(49.0093, 8.4037), None, None).is_empty());
+
+        let capitals = Capitals::from_geojson(
+            r#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "properties": {"name": "Berlin"},
+                 "geometry": {"type": "Point", "coordinates": [13.3989, 52.5218]}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            quality_flags(&coordinates(52.52, 13.405), None, Some(&capitals)),
+            vec![CoordinateQualityFlag::CapitalCoordinate]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::country_names::CountryNames;
+use crate::models::{CountryInput, NagoyaError, NameCandidate, NameResolution};
+use rust_iso3166::CountryCode;
+use std::str::FromStr;
+
+// Validated ISO 3166-1 country, parsed from an alpha-2, alpha-3 or numeric code
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CountryId(CountryCode);
+
+impl CountryId {
+    pub fn alpha2(&self) -> &'static str {
+        self.0.alpha2
+    }
+
+    pub fn alpha3(&self) -> &'static str {
+        self.0.alpha3
+    }
+
+    pub fn numeric(&self) -> String {
+        format!("{:03}", self.0.numeric)
+    }
+
+    // ISO 3166-1 short name, without the footnote markers of the ISO tables
+    pub fn name(&self) -> &'static str {
+        self.0
+            .name
+            .split_once('[')
+            .map_or(self.0.name, |(name, _)| name)
+    }
+}
+
+impl FromStr for CountryId {
+    type Err = NagoyaError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let code = code.trim();
+        let malformed = |reason: &str| NagoyaError::MalformedCountryCode {
+            input: code.to_string(),
+            reason: reason.to_string(),
+        };
+        let country = if !code.is_empty() && code.chars().all(|c| c.is_ascii_digit()) {
+            // Numeric codes lose their leading zeros in spreadsheets, e.g. 76 for Brazil
+            let numeric = code
+                .parse::<i32>()
+                .ok()
+                .filter(|_| code.len() <= 3)
+                .ok_or_else(|| malformed("Numeric codes have at most three digits"))?;
+            rust_iso3166::from_numeric(numeric).ok_or_else(|| malformed("Unknown numeric code"))?
+        } else if code.chars().all(|c| c.is_ascii_alphabetic()) {
+            match code.len() {
+                2 => rust_iso3166::from_alpha2(&code.to_uppercase())
+                    .ok_or_else(|| malformed("Unknown alpha-2 code"))?,
+                3 => rust_iso3166::from_alpha3(&code.to_uppercase())
+                    .ok_or_else(|| malformed("Unknown alpha-3 code"))?,
+                _ => return Err(malformed("Alphabetic codes have two or three letters")),
+            }
+        } else {
+            return Err(malformed(
+                "Expected an alpha-2, alpha-3 or numeric ISO 3166-1 code",
+            ));
+        };
+        Ok(Self(country))
+    }
+}
+
+// Country a request refers to, after validating codes and resolving names
+#[derive(Debug)]
+pub struct ResolvedCountry {
+    pub country: CountryId,
+    // ISO 3166-2 code, if a subdivision was given
+    pub subdivision: Option<String>,
+    pub name_resolution: Option<NameResolution>,
+}
+
+pub fn resolve_country(
+    input: &CountryInput,
+    names: &CountryNames,
+) -> Result<ResolvedCountry, NagoyaError> {
+    match input {
+        CountryInput::Subdivision { country, code } => Ok(ResolvedCountry {
+            country: *country,
+            subdivision: Some(code.clone()),
+            name_resolution: None,
+        }),
+        CountryInput::Country(country) => Ok(ResolvedCountry {
+            country: *country,
+            subdivision: None,
+            name_resolution: None,
+        }),
+        CountryInput::Name(name) => {
+            let resolution = names.resolve(name)?;
+            // A verdict for the best match alone would silently pick e.g. one of the two Congos
+            if !resolution.alternatives.is_empty() {
+                return Err(NagoyaError::AmbiguousCountryName {
+                    name: name.clone(),
+                    alternatives: std::iter::once(NameCandidate {
+                        country_code: resolution.country_code,
+                        matched_name: resolution.matched_name,
+                        confidence: resolution.confidence,
+                    })
+                    .chain(resolution.alternatives)
+                    .collect(),
+                });
+            }
+            Ok(ResolvedCountry {
+                country: resolution.country_code.parse()?,
+                subdivision: None,
+                name_resolution: Some(resolution),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        let brazil = "BRA".parse::<CountryId>().unwrap();
+        assert_eq!("br".parse::<CountryId>(), Ok(brazil));
+        assert_eq!("076".parse::<CountryId>(), Ok(brazil));
+        assert_eq!("76".parse::<CountryId>(), Ok(brazil));
+        assert_eq!(brazil.alpha2(), "BR");
+        assert_eq!(brazil.numeric(), "076");
+        assert_eq!(
+            "BQ".parse::<CountryId>().unwrap().name(),
+            "Bonaire, Sint Eustatius and Saba"
+        );
+    }
+
+    #[test]
+    fn test_resolve_country() {
+        let names = CountryNames::default();
+        let resolve =
+            |input: &str| resolve_country(&CountryInput::try_from(input.to_string())?, &names);
+        let queensland = resolve("au-qld").unwrap();
+        assert_eq!(queensland.country.alpha2(), "AU");
+        assert_eq!(queensland.subdivision.as_deref(), Some("AU-QLD"));
+        assert_eq!(resolve("076").unwrap().country.alpha2(), "BR");
+        let germany = resolve("Germany").unwrap();
+        assert_eq!(germany.country.alpha2(), "DE");
+        assert!(germany.name_resolution.is_some());
+        assert!(matches!(
+            resolve("AU-XX"),
+            Err(NagoyaError::MalformedCountryCode { .. })
+        ));
+        assert!(matches!(
+            resolve("0076"),
+            Err(NagoyaError::MalformedCountryCode { .. })
+        ));
+    }
+
+    #[test]
+    fn test_country_input() {
+        let input = |input: &str| CountryInput::try_from(input.to_string());
+        assert_eq!(
+            input(" br "),
+            Ok(CountryInput::Country("BRA".parse().unwrap()))
+        );
+        assert!(matches!(input("Guinea-Bissau"), Ok(CountryInput::Name(_))));
+        assert!(matches!(
+            input("au-qld"),
+            Ok(CountryInput::Subdivision { code, .. }) if code == "AU-QLD"
+        ));
+        // Malformed codes fail when the request is parsed
+        let error = NagoyaError::from(
+            axum::Json::<crate::models::NagoyaCheckDataCC>::from_bytes(
+                br#"{"probe_country": "XYZ"}"#,
+            )
+            .err()
+            .unwrap(),
+        );
+        assert!(matches!(error, NagoyaError::MalformedRequest { .. }));
+        assert!(
+            error
+                .to_string()
+                .contains("Malformed country code \"XYZ\": Unknown alpha-3 code")
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        for (input, reason) in [
+            ("XYZ", "Unknown alpha-3 code"),
+            ("999", "Unknown numeric code"),
+            ("0076", "Numeric codes have at most three digits"),
+            ("D", "Alphabetic codes have two or three letters"),
+            (
+                "D3",
+                "Expected an alpha-2, alpha-3 or numeric ISO 3166-1 code",
+            ),
+        ] {
+            assert_eq!(
+                input.parse::<CountryId>(),
+                Err(NagoyaError::MalformedCountryCode {
+                    input: input.to_string(),
+                    reason: reason.to_string(),
+                })
+            );
+        }
+    }
+}
@@ -0,0 +1,358 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::models::{NagoyaError, NameCandidate, NameResolution};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use tracing::{Level, event, instrument};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+// Names below this similarity are not considered a match
+const FUZZY_THRESHOLD: f64 = 0.8;
+// Matches this close to the best one are reported as alternatives
+const ALTERNATIVE_MARGIN: f64 = 0.1;
+
+// Names shared by several countries, which cannot be resolved without further context
+const AMBIGUOUS_NAMES: [(&str, &[&str]); 3] = [
+    ("Congo", &["COG", "COD"]),
+    ("Korea", &["KOR", "PRK"]),
+    ("Virgin Islands", &["VGB", "VIR"]),
+];
+
+// Common names neither ISO 3166 nor the ABSCH knows, mostly in languages other than the six UN
+// languages of the ABSCH, as often found on labels of European collections
+const COMMON_NAMES: [(&str, &str); 48] = [
+    ("Ägypten", "EGY"),
+    ("Argentinien", "ARG"),
+    ("Äthiopien", "ETH"),
+    ("Australien", "AUS"),
+    ("Belgien", "BEL"),
+    ("België", "BEL"),
+    ("Brasilien", "BRA"),
+    ("Burma", "MMR"),
+    ("Czech Republic", "CZE"),
+    ("Dänemark", "DNK"),
+    ("Danmark", "DNK"),
+    ("Deutschland", "DEU"),
+    ("East Timor", "TLS"),
+    ("Elfenbeinküste", "CIV"),
+    ("Frankreich", "FRA"),
+    ("Great Britain", "GBR"),
+    ("Griechenland", "GRC"),
+    ("Großbritannien", "GBR"),
+    ("Holland", "NLD"),
+    ("Indien", "IND"),
+    ("Indonesien", "IDN"),
+    ("Italia", "ITA"),
+    ("Italien", "ITA"),
+    ("Ivory Coast", "CIV"),
+    ("Kamerun", "CMR"),
+    ("Kanada", "CAN"),
+    ("Kenia", "KEN"),
+    ("Kolumbien", "COL"),
+    ("Kuba", "CUB"),
+    ("Madagaskar", "MDG"),
+    ("Marokko", "MAR"),
+    ("Mexiko", "MEX"),
+    ("Nederland", "NLD"),
+    ("Neuseeland", "NZL"),
+    ("Norge", "NOR"),
+    ("Norwegen", "NOR"),
+    ("Österreich", "AUT"),
+    ("Philippinen", "PHL"),
+    ("Polen", "POL"),
+    ("Polska", "POL"),
+    ("Russland", "RUS"),
+    ("Schweden", "SWE"),
+    ("Schweiz", "CHE"),
+    ("Spanien", "ESP"),
+    ("Südafrika", "ZAF"),
+    ("Sverige", "SWE"),
+    ("Tschechien", "CZE"),
+    ("USA", "USA"),
+];
+
+// Normalized name to the alpha-3 codes and the original names carrying it
+pub type NameIndex = HashMap<String, BTreeMap<String, String>>;
+
+// Resolves country names as found on labels to alpha-3 codes. Knows the English ISO 3166 names,
+// the multilingual names of the ABSCH country data, a few common names and a configurable alias
+// table
+#[derive(Clone, Debug)]
+pub struct CountryNames {
+    names: NameIndex,
+    aliases: NameIndex,
+}
+
+impl Default for CountryNames {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+// Alias table loaded from a JSON file, mapping a name to one or several country codes, e.g.
+// {"Ivory Coast": "CI", "Rep. of Congo": "COG", "Congo": ["COG", "COD"]}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AliasTarget {
+    Single(String),
+    Several(Vec<String>),
+}
+
+impl CountryNames {
+    // Takes (name, alpha-3 code) pairs, e.g. from the ABSCH country data
+    pub fn new(names: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut country_names = Self {
+            names: HashMap::new(),
+            aliases: HashMap::new(),
+        };
+        for country in rust_iso3166::ALL {
+            country_names.insert_name(country.name, country.alpha3);
+        }
+        for (name, alpha3) in names {
+            country_names.insert_name(&name, &alpha3);
+        }
+        for (name, alpha3) in COMMON_NAMES {
+            country_names.insert_name(name, alpha3);
+        }
+        for (name, codes) in AMBIGUOUS_NAMES {
+            for alpha3 in codes {
+                country_names.insert_name(name, alpha3);
+            }
+        }
+        country_names
+    }
+
+    fn insert_name(&mut self, name: &str, alpha3: &str) {
+        let normalized = normalize_name(name);
+        if normalized.is_empty() {
+            return;
+        }
+        self.names
+            .entry(normalized)
+            .or_default()
+            .entry(alpha3.to_uppercase())
+            .or_insert_with(|| name.to_string());
+    }
+
+    #[instrument]
+    pub fn load_aliases(path: &str) -> Result<NameIndex, Box<dyn Error>> {
+        Self::parse_aliases(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse_aliases(json: &str) -> Result<NameIndex, Box<dyn Error>> {
+        let table: HashMap<String, AliasTarget> = serde_json::from_str(json)?;
+        let mut aliases = NameIndex::new();
+        for (name, target) in table {
+            let codes = match target {
+                AliasTarget::Single(code) => vec![code],
+                AliasTarget::Several(codes) => codes,
+            };
+            for code in codes {
+                let alpha3 = match code.len() {
+                    2 => rust_iso3166::from_alpha2(&code.to_uppercase()),
+                    3 => rust_iso3166::from_alpha3(&code.to_uppercase()),
+                    _ => None,
+                }
+                .ok_or_else(|| format!("Unknown country code \"{code}\" for alias \"{name}\""))?
+                .alpha3;
+                aliases
+                    .entry(normalize_name(&name))
+                    .or_default()
+                    .insert(alpha3.to_string(), name.clone());
+            }
+        }
+        event!(Level::INFO, "Loaded {} country aliases", aliases.len());
+        Ok(aliases)
+    }
+
+    pub fn set_aliases(&mut self, aliases: NameIndex) {
+        self.aliases = aliases;
+    }
+
+    pub fn aliases(&self) -> &NameIndex {
+        &self.aliases
+    }
+
+    // Exact matches of the normalized name win, otherwise the most similar names are taken. The
+    // confidence is the best match's share among the near ties, scaled by its similarity
+    pub fn resolve(&self, name: &str) -> Result<NameResolution, NagoyaError> {
+        let normalized = normalize_name(name);
+        let unknown = || NagoyaError::UnknownCountryName {
+            name: name.to_string(),
+        };
+        if normalized.is_empty() {
+            return Err(unknown());
+        }
+        let exact: Vec<(&str, f64, &str)> = self
+            .aliases
+            .get(&normalized)
+            .into_iter()
+            .chain(self.names.get(&normalized))
+            .flatten()
+            .map(|(alpha3, matched_name)| (alpha3.as_str(), 1.0, matched_name.as_str()))
+            .collect();
+        let matches = if exact.is_empty() {
+            self.aliases
+                .iter()
+                .chain(self.names.iter())
+                .map(|(candidate, countries)| {
+                    (
+                        strsim::normalized_damerau_levenshtein(&normalized, candidate),
+                        countries,
+                    )
+                })
+                .filter(|(score, _)| *score >= FUZZY_THRESHOLD)
+                .flat_map(|(score, countries)| {
+                    countries.iter().map(move |(alpha3, matched_name)| {
+                        (alpha3.as_str(), score, matched_name.as_str())
+                    })
+                })
+                .collect()
+        } else {
+            exact
+        };
+        let mut best_per_country: BTreeMap<&str, (f64, &str)> = BTreeMap::new();
+        for (alpha3, score, matched_name) in matches {
+            let best = best_per_country
+                .entry(alpha3)
+                .or_insert((score, matched_name));
+            if score > best.0 {
+                *best = (score, matched_name);
+            }
+        }
+
+        let mut candidates: Vec<NameCandidate> = best_per_country
+            .into_iter()
+            .map(|(alpha3, (score, matched_name))| NameCandidate {
+                country_code: alpha3.to_string(),
+                matched_name: matched_name.to_string(),
+                confidence: score,
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        let Some(best) = candidates.first() else {
+            return Err(unknown());
+        };
+        let best_score = best.confidence;
+        candidates.retain(|candidate| best_score - candidate.confidence <= ALTERNATIVE_MARGIN);
+        let total: f64 = candidates
+            .iter()
+            .map(|candidate| candidate.confidence)
+            .sum();
+        let best = candidates.remove(0);
+        Ok(NameResolution {
+            input: name.to_string(),
+            confidence: round(best_score * best_score / total),
+            country_code: best.country_code,
+            matched_name: best.matched_name,
+            alternatives: candidates
+                .into_iter()
+                .map(|candidate| NameCandidate {
+                    confidence: round(candidate.confidence * candidate.confidence / total),
+                    ..candidate
+                })
+                .collect(),
+        })
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+// Case- and accent-insensitive form of a name with punctuation removed and common abbreviations
+// expanded, e.g. "Rep. of the Congo" and "Republic of Congo" end up the same
+pub fn normalize_name(name: &str) -> String {
+    let folded: String = name
+        .nfd()
+        .filter(|character| !is_combining_mark(*character))
+        .flat_map(char::to_lowercase)
+        .map(|character| {
+            if character.is_alphanumeric() {
+                character
+            } else {
+                ' '
+            }
+        })
+        .collect();
+    folded
+        .split_whitespace()
+        .filter(|token| *token != "the")
+        .map(|token| match token {
+            "rep" => "republic",
+            "dem" => "democratic",
+            "st" => "saint",
+            token => token,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn country_names() -> CountryNames {
+        let mut names = CountryNames::new(vec![
+            (String::from("Brasil"), String::from("BRA")),
+            (String::from("Deutschland"), String::from("DEU")),
+            (String::from("Republic of the Congo"), String::from("COG")),
+        ]);
+        names.set_aliases(
+            CountryNames::parse_aliases(r#"{"Ivory Coast": "CI", "Elfenbeinküste": "CIV"}"#)
+                .unwrap(),
+        );
+        names
+    }
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(normalize_name("Côte d'Ivoire"), "cote d ivoire");
+        assert_eq!(normalize_name("  Rep. of the CONGO "), "republic of congo");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let names = country_names();
+        let resolve = |name: &str| names.resolve(name).unwrap();
+        assert_eq!(resolve("brasil").country_code, "BRA");
+        assert_eq!(resolve("COTE D'IVOIRE").country_code, "CIV");
+        assert_eq!(resolve("Ivory Coast").country_code, "CIV");
+        assert_eq!(resolve("Elfenbeinkuste").country_code, "CIV");
+        assert_eq!(resolve("Rep. of Congo").country_code, "COG");
+        assert_eq!(resolve("Germany").confidence, 1.0);
+
+        // Typos are resolved with a lower confidence
+        let resolution = resolve("Deutchland");
+        assert_eq!(resolution.country_code, "DEU");
+        assert!(resolution.confidence < 1.0 && resolution.confidence >= FUZZY_THRESHOLD);
+
+        assert!(matches!(
+            names.resolve("Atlantis"),
+            Err(NagoyaError::UnknownCountryName { .. })
+        ));
+    }
+
+    #[test]
+    fn test_common_names() {
+        let names = CountryNames::default();
+        assert_eq!(names.resolve("Deutschland").unwrap().country_code, "DEU");
+        assert_eq!(names.resolve("Österreich").unwrap().country_code, "AUT");
+        assert_eq!(names.resolve("Holland").unwrap().confidence, 1.0);
+    }
+
+    #[test]
+    fn test_ambiguous_name() {
+        let resolution = country_names().resolve("Congo").unwrap();
+        assert_eq!(resolution.confidence, 0.5);
+        assert_eq!(resolution.alternatives.len(), 1);
+        assert_ne!(
+            resolution.country_code,
+            resolution.alternatives[0].country_code
+        );
+    }
+}
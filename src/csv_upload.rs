@@ -0,0 +1,592 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::batch::{RecordFields, check_all};
+use crate::models::{AppState, CsvColumns, ImplementingCountries, NagoyaError, NagoyaResponse};
+use crate::upload::malformed;
+use axum::body::{Body, Bytes};
+use axum::extract::Multipart;
+use csv::{ByteRecord, WriterBuilder};
+use csv_core::ReadRecordResult;
+use futures_util::{Stream, StreamExt, stream};
+use std::collections::VecDeque;
+use std::pin::pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{Level, event, instrument};
+
+// Rows checked at once. Bounds the memory of an upload regardless of its size, while keeping
+// BATCH_CONCURRENCY checks in flight most of the time
+const CHUNK_ROWS: usize = 256;
+
+// Columns appended to the uploaded CSV
+pub const RESULT_COLUMNS: [&str; 3] = [
+    "nagoya_check_result",
+    "nagoya_country_code",
+    "nagoya_reasons",
+];
+
+// Values of the result columns: the verdict, the resolved country and the reasons or the error
+pub fn result_fields(result: Result<NagoyaResponse, NagoyaError>) -> [String; 3] {
+    match result {
+        Ok(response) => [
+            response.check_result.to_string(),
+            response.country_code.unwrap_or_default(),
+            response.reasons.join("; "),
+        ],
+        Err(error) => [String::new(), String::new(), error.to_string()],
+    }
+}
+
+// Reads the form fields up to the file and checks it while it is read. The columns need to precede
+// the file, as they are needed to check its first chunk
+async fn annotate_upload(
+    mut multipart: Multipart,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+    output: &mpsc::Sender<Bytes>,
+) -> Result<(), NagoyaError> {
+    let mut columns = CsvColumns::default();
+    let mut annotated = false;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|error| malformed(error.body_text()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        if annotated {
+            return Err(malformed(format!(
+                "Field \"{name}\" given after the file, it needs to precede it"
+            )));
+        }
+        if name == "file" {
+            let file = field.map(|chunk| chunk.map_err(|error| malformed(error.body_text())));
+            annotate_csv(&columns, file, implementing_countries, state, output).await?;
+            annotated = true;
+            continue;
+        }
+        let value = Some(
+            field
+                .text()
+                .await
+                .map_err(|error| malformed(error.body_text()))?,
+        )
+        .filter(|value| !value.trim().is_empty());
+        match name.as_str() {
+            "country_column" => columns.country_column = value,
+            "latitude_column" => columns.latitude_column = value,
+            "longitude_column" => columns.longitude_column = value,
+            "date_column" => columns.date_column = value,
+            "id_column" => columns.id_column = value,
+            "delimiter" => columns.delimiter = value,
+            _ => return Err(malformed(format!("Unknown field \"{name}\""))),
+        }
+    }
+    if !annotated {
+        return Err(malformed("No file given"));
+    }
+    Ok(())
+}
+
+// Streams the annotated CSV while the upload is still being read and checked. Errors up to the
+// header line are returned as such, later ones (e.g. too many rows) abort the response, as its
+// status has been sent by then
+pub async fn stream_annotated_csv(
+    multipart: Multipart,
+    implementing_countries: Arc<ImplementingCountries>,
+    state: AppState,
+) -> Result<Body, NagoyaError> {
+    let (sender, mut receiver) = mpsc::channel(4);
+    let task = tokio::spawn(async move {
+        annotate_upload(multipart, &implementing_countries, &state, &sender).await
+    });
+    // The header line is only sent once the columns are found
+    let Some(header) = receiver.recv().await else {
+        return Err(outcome(task)
+            .await
+            .err()
+            .unwrap_or(NagoyaError::GenericInternalServerError));
+    };
+    let rows = stream::unfold(Some((receiver, task)), |pending| async move {
+        let (mut receiver, task) = pending?;
+        match receiver.recv().await {
+            Some(rows) => Some((Ok(rows), Some((receiver, task)))),
+            None => match outcome(task).await {
+                Ok(()) => None,
+                Err(error) => {
+                    event!(Level::INFO, "Aborted annotating a CSV upload: {}", error);
+                    Some((Err(error), None))
+                }
+            },
+        }
+    });
+    Ok(Body::from_stream(
+        stream::once(async { Ok(header) }).chain(rows),
+    ))
+}
+
+async fn outcome(task: JoinHandle<Result<(), NagoyaError>>) -> Result<(), NagoyaError> {
+    task.await
+        .unwrap_or(Err(NagoyaError::GenericInternalServerError))
+}
+
+// Incremental CSV parser, as the csv crate only reads from blocking readers. Records may span
+// chunks of the upload, e.g. if a quoted field contains a line break
+struct RecordParser {
+    reader: csv_core::Reader,
+    fields: Vec<u8>,
+    ends: Vec<usize>,
+    fields_len: usize,
+    ends_len: usize,
+}
+
+impl RecordParser {
+    fn new(delimiter: u8) -> Self {
+        Self {
+            reader: csv_core::ReaderBuilder::new().delimiter(delimiter).build(),
+            fields: vec![0; 1024],
+            ends: vec![0; 16],
+            fields_len: 0,
+            ends_len: 0,
+        }
+    }
+
+    // Parses a chunk, appending the records it completes
+    fn push(&mut self, chunk: &[u8], records: &mut VecDeque<ByteRecord>) {
+        self.parse(chunk, false, records);
+    }
+
+    // Parses the last record, if the file does not end with a line break
+    fn finish(&mut self, records: &mut VecDeque<ByteRecord>) {
+        self.parse(&[], true, records);
+    }
+
+    fn parse(&mut self, mut input: &[u8], end: bool, records: &mut VecDeque<ByteRecord>) {
+        // csv_core takes empty input as the end of the file
+        while end || !input.is_empty() {
+            let (result, read, written, ended) = self.reader.read_record(
+                input,
+                &mut self.fields[self.fields_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[read..];
+            self.fields_len += written;
+            self.ends_len += ended;
+            match result {
+                ReadRecordResult::InputEmpty | ReadRecordResult::End => return,
+                ReadRecordResult::OutputFull => self.fields.resize(self.fields.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => {
+                    let mut record = ByteRecord::new();
+                    let mut start = 0;
+                    for &end in &self.ends[..self.ends_len] {
+                        record.push_field(&self.fields[start..end]);
+                        start = end;
+                    }
+                    records.push_back(record);
+                    self.fields_len = 0;
+                    self.ends_len = 0;
+                }
+            }
+        }
+    }
+}
+
+// Parses the next chunk of the file, returning whether it has ended
+async fn pull(
+    file: &mut (impl Stream<Item = Result<Bytes, NagoyaError>> + Unpin),
+    parser: &mut RecordParser,
+    records: &mut VecDeque<ByteRecord>,
+) -> Result<bool, NagoyaError> {
+    match file.next().await.transpose()? {
+        Some(chunk) => {
+            parser.push(&chunk, records);
+            Ok(false)
+        }
+        None => {
+            parser.finish(records);
+            Ok(true)
+        }
+    }
+}
+
+fn write(records: Vec<ByteRecord>, delimiter: u8) -> Result<Bytes, NagoyaError> {
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_writer(Vec::new());
+    for record in records {
+        writer.write_byte_record(&record).map_err(malformed)?;
+    }
+    writer
+        .into_inner()
+        .map(Bytes::from)
+        .map_err(|error| malformed(error.error()))
+}
+
+// The delimiter occurring most often in the header line, as spreadsheets export with commas,
+// semicolons or tabs depending on the locale
+fn detect_delimiter(file: &[u8]) -> u8 {
+    let header = file.split(|byte| *byte == b'\n').next().unwrap_or_default();
+    // The last of equally frequent delimiters wins, so a single column falls back to commas
+    [b'\t', b';', b',']
+        .into_iter()
+        .max_by_key(|delimiter| header.iter().filter(|byte| *byte == delimiter).count())
+        .unwrap_or(b',')
+}
+
+// Position of a column, which needs to exist if it was given explicitly
+fn column(
+    headers: &ByteRecord,
+    given: &Option<String>,
+    default: &str,
+) -> Result<Option<usize>, NagoyaError> {
+    let name = given.as_deref().unwrap_or(default).trim();
+    let position = headers.iter().position(|header| {
+        String::from_utf8_lossy(header)
+            .trim()
+            .eq_ignore_ascii_case(name)
+    });
+    if position.is_none() && given.is_some() {
+        return Err(malformed(format!("Column \"{name}\" not found")));
+    }
+    Ok(position)
+}
+
+fn cell(record: &ByteRecord, position: Option<usize>) -> Option<String> {
+    position
+        .and_then(|position| record.get(position))
+        .map(|value| String::from_utf8_lossy(value).trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+// Coordinates with a decimal comma, as exported by spreadsheets in many locales. Only possible if
+// the comma is not the delimiter, other notations are left as they are
+fn coordinate_cell(record: &ByteRecord, position: Option<usize>, delimiter: u8) -> Option<String> {
+    let value = cell(record, position)?;
+    let decimal = value.replacen(',', ".", 1);
+    if delimiter != b',' && value.matches(',').count() == 1 && decimal.parse::<f64>().is_ok() {
+        Some(decimal)
+    } else {
+        Some(value)
+    }
+}
+
+// Checks the rows of the CSV in chunks of CHUNK_ROWS while it is read and sends each chunk to the
+// output with the verdict, the resolved country and the reasons (or the error) appended to each row.
+// The header line goes first, once the columns are found
+#[instrument(skip_all)]
+pub async fn annotate_csv(
+    columns: &CsvColumns,
+    file: impl Stream<Item = Result<Bytes, NagoyaError>>,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+    output: &mpsc::Sender<Bytes>,
+) -> Result<(), NagoyaError> {
+    let mut file = pin!(file);
+    // The header line is needed to detect the delimiter
+    let mut head = Vec::new();
+    let mut ended = false;
+    while !ended && !head.contains(&b'\n') {
+        match file.next().await.transpose()? {
+            Some(chunk) => head.extend_from_slice(&chunk),
+            None => ended = true,
+        }
+    }
+    let head = head.strip_prefix("\u{feff}".as_bytes()).unwrap_or(&head);
+    let delimiter = match columns.delimiter.as_deref() {
+        Some("\\t" | "tab") => b'\t',
+        Some(delimiter) if delimiter.len() == 1 => delimiter.as_bytes()[0],
+        Some(delimiter) => return Err(malformed(format!("Invalid delimiter \"{delimiter}\""))),
+        None => detect_delimiter(head),
+    };
+    let mut parser = RecordParser::new(delimiter);
+    let mut records = VecDeque::new();
+    parser.push(head, &mut records);
+    if ended {
+        parser.finish(&mut records);
+    }
+    while records.is_empty() && !ended {
+        ended = pull(&mut file, &mut parser, &mut records).await?;
+    }
+    let Some(headers) = records.pop_front() else {
+        return Err(malformed("Empty file"));
+    };
+    let country = column(&headers, &columns.country_column, "country")?;
+    let latitude = column(&headers, &columns.latitude_column, "latitude")?;
+    let longitude = column(&headers, &columns.longitude_column, "longitude")?;
+    let date = column(&headers, &columns.date_column, "date")?;
+    let id = column(&headers, &columns.id_column, "id")?;
+    if country.is_none() && (latitude.is_none() || longitude.is_none()) {
+        return Err(malformed(
+            "Neither a country column nor latitude and longitude columns found",
+        ));
+    }
+    let width = headers.len();
+    let mut header = headers;
+    header.extend(RESULT_COLUMNS);
+    send(output, write(vec![header], delimiter)?).await?;
+
+    let max_records = state.config.upload_max_records;
+    let mut rows = 0;
+    loop {
+        while records.len() < CHUNK_ROWS && !ended {
+            ended = pull(&mut file, &mut parser, &mut records).await?;
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        let chunk: Vec<ByteRecord> = records.drain(..records.len().min(CHUNK_ROWS)).collect();
+        if rows + chunk.len() > max_records {
+            return Err(NagoyaError::BatchTooLarge {
+                items: rows + chunk.len(),
+                max: max_records,
+            });
+        }
+        let checks = chunk
+            .iter()
+            .map(|record| {
+                RecordFields {
+                    country: cell(record, country),
+                    latitude: coordinate_cell(record, latitude, delimiter),
+                    longitude: coordinate_cell(record, longitude, delimiter),
+                    uncertainty_in_meters: None,
+                    date: cell(record, date),
+                }
+                .into_check()
+            })
+            .collect();
+        let results = check_all(checks, implementing_countries, state, max_records).await?;
+
+        let mut annotated = Vec::with_capacity(chunk.len());
+        for (mut record, result) in chunk.into_iter().zip(results) {
+            rows += 1;
+            // Short rows are padded, so the appended columns line up with the header
+            while record.len() < width {
+                record.push_field(b"");
+            }
+            if let Err(error) = &result {
+                event!(
+                    Level::DEBUG,
+                    "Row {} failed: {}",
+                    cell(&record, id).unwrap_or_else(|| rows.to_string()),
+                    error
+                );
+            }
+            record.extend(result_fields(result));
+            annotated.push(record);
+        }
+        send(output, write(annotated, delimiter)?).await?;
+    }
+}
+
+async fn send(output: &mpsc::Sender<Bytes>, rows: Bytes) -> Result<(), NagoyaError> {
+    // Fails only if the client went away, which ends the check
+    output
+        .send(rows)
+        .await
+        .map_err(|_| NagoyaError::GenericInternalServerError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::tests::state;
+    use crate::country::CountryId;
+    use axum::body::to_bytes;
+    use axum::extract::FromRequest;
+    use axum::http::{Request, header};
+
+    #[test]
+    fn test_detect_delimiter() {
+        assert_eq!(detect_delimiter(b"id;country;date\n1;BR;2020"), b';');
+        assert_eq!(detect_delimiter(b"id\tcountry\n1\tBR"), b'\t');
+        assert_eq!(detect_delimiter(b"country\nBR"), b',');
+    }
+
+    // Annotates the file fed in chunks of the given size, so records span chunks
+    async fn annotate(
+        file: &[u8],
+        chunk_size: usize,
+        columns: CsvColumns,
+        state: &AppState,
+    ) -> (String, Result<(), NagoyaError>) {
+        let implementing_countries = ImplementingCountries {
+            countries: ["BRA".parse::<CountryId>().unwrap()].into(),
+            ..Default::default()
+        };
+        let chunks = file
+            .chunks(chunk_size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        let (sender, mut receiver) = mpsc::channel(1);
+        let annotation = async {
+            // Dropped once done, which ends the collection
+            let sender = sender;
+            annotate_csv(
+                &columns,
+                stream::iter(chunks),
+                &implementing_countries,
+                state,
+                &sender,
+            )
+            .await
+        };
+        let collect = async {
+            let mut annotated = Vec::new();
+            while let Some(rows) = receiver.recv().await {
+                annotated.extend_from_slice(&rows);
+            }
+            annotated
+        };
+        let (result, annotated) = tokio::join!(annotation, collect);
+        (String::from_utf8(annotated).unwrap(), result)
+    }
+
+    #[tokio::test]
+    async fn test_annotate_csv() {
+        let file = "\u{feff}ID;Land;Latitude;Longitude\nA1;Brasil\nA2;DE;;\nA3;;91;0\nA4;;;\nA5;;91,5;0,0\n\"A6\n\";\"BR\"";
+        for chunk_size in [3, 1024] {
+            let (annotated, result) = annotate(
+                file.as_bytes(),
+                chunk_size,
+                CsvColumns {
+                    country_column: Some(String::from("land")),
+                    ..Default::default()
+                },
+                &state(10),
+            )
+            .await;
+            result.unwrap();
+            let lines: Vec<&str> = annotated.lines().collect();
+            assert_eq!(
+                lines[0],
+                "ID;Land;Latitude;Longitude;nagoya_check_result;nagoya_country_code;nagoya_reasons"
+            );
+            assert_eq!(
+                lines[1],
+                "A1;Brasil;;;true;BR;BR is party to the Nagoya Protocol"
+            );
+            assert_eq!(
+                lines[2],
+                "A2;DE;;;false;DE;DE is not party to the Nagoya Protocol"
+            );
+            assert!(lines[3].starts_with("A3;;91;0;;;Invalid coordinates"));
+            assert!(lines[4].ends_with(";;;Neither a probe country nor a list of countries given"));
+            // Decimal commas are read as such, as the comma is not the delimiter
+            assert!(
+                lines[5]
+                    .starts_with("A5;;91,5;0,0;;;Invalid coordinates: latitude 91.5 is outside")
+            );
+            // A quoted line break and a last row without one
+            assert_eq!(lines[6], "\"A6");
+            assert_eq!(
+                lines[7],
+                "\";BR;;;true;BR;BR is party to the Nagoya Protocol"
+            );
+        }
+
+        let (annotated, result) = annotate(
+            b"id,country\n1,BR\n",
+            1024,
+            CsvColumns {
+                latitude_column: Some(String::from("lat")),
+                ..Default::default()
+            },
+            &state(10),
+        )
+        .await;
+        assert!(annotated.is_empty());
+        assert!(matches!(result, Err(NagoyaError::MalformedUpload { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_annotate_csv_in_chunks() {
+        let rows = CHUNK_ROWS * 2 + 1;
+        let file = format!("id,country\n{}", "x,BR\n".repeat(rows));
+        let (annotated, result) =
+            annotate(file.as_bytes(), 100, CsvColumns::default(), &state(rows)).await;
+        result.unwrap();
+        assert_eq!(annotated.lines().count(), rows + 1);
+        assert!(
+            annotated
+                .lines()
+                .skip(1)
+                .all(|line| line.starts_with("x,BR,true,BR"))
+        );
+
+        // Rows past the limit fail the upload after the chunks within it have been sent
+        let (annotated, result) = annotate(
+            file.as_bytes(),
+            100,
+            CsvColumns::default(),
+            &state(CHUNK_ROWS),
+        )
+        .await;
+        assert_eq!(annotated.lines().count(), CHUNK_ROWS + 1);
+        assert!(matches!(
+            result,
+            Err(NagoyaError::BatchTooLarge {
+                max: CHUNK_ROWS,
+                ..
+            })
+        ));
+    }
+
+    async fn multipart(fields: &[(&str, &str)]) -> Multipart {
+        let body: String = fields
+            .iter()
+            .map(|(name, value)| {
+                format!("--boundary\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n")
+            })
+            .chain(["--boundary--\r\n".to_string()])
+            .collect();
+        let request = Request::builder()
+            .header(
+                header::CONTENT_TYPE,
+                "multipart/form-data; boundary=boundary",
+            )
+            .body(Body::from(body))
+            .unwrap();
+        Multipart::from_request(request, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_annotated_csv() {
+        let implementing_countries = Arc::new(ImplementingCountries::default());
+        let body = stream_annotated_csv(
+            multipart(&[("country_column", "land"), ("file", "id,land\n1,DE\n")]).await,
+            implementing_countries.clone(),
+            state(10),
+        )
+        .await
+        .unwrap();
+        let annotated = to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(
+            annotated,
+            "id,land,nagoya_check_result,nagoya_country_code,nagoya_reasons\n1,DE,false,DE,DE is not party to the Nagoya Protocol\n"
+        );
+
+        // The columns are needed before the file is read
+        let error = stream_annotated_csv(
+            multipart(&[("file", "id,land\n1,DE\n"), ("country_column", "land")]).await,
+            implementing_countries.clone(),
+            state(10),
+        )
+        .await
+        .err();
+        assert!(matches!(error, Some(NagoyaError::MalformedUpload { .. })));
+
+        // Trailing fields abort the response, as it has started by then
+        let body = stream_annotated_csv(
+            multipart(&[("file", "country\nDE\n"), ("delimiter", ",")]).await,
+            implementing_countries,
+            state(10),
+        )
+        .await
+        .unwrap();
+        assert!(to_bytes(body, usize::MAX).await.is_err());
+    }
+}
@@ -0,0 +1,692 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::batch::{Check, check_all, summarize};
+use crate::boundaries::CountryBoundaries;
+use crate::country_names::CountryNames;
+use crate::models::{
+    AppState, BoxMethod, CandidateCountry, CoordinateInput, Coordinates, CountryInput,
+    DatasetCheck, GeoLocation, ImplementingCountries, LocationResult, NagoyaCheckDataCC,
+    NagoyaCheckDataGeo, NagoyaError, NagoyaResponse,
+};
+use crate::normalization::normalize_country;
+use crate::upload::malformed;
+use crate::xml::walk;
+use serde_json::Value;
+use tracing::{Level, event, instrument};
+
+// A single DataCite geoLocation or EML geographicCoverage. The place only counts if no coordinates
+// are given, as it usually describes them
+#[derive(Default)]
+struct Coverage {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    west: Option<f64>,
+    east: Option<f64>,
+    south: Option<f64>,
+    north: Option<f64>,
+    place: Option<String>,
+}
+
+impl Coverage {
+    fn into_locations(self) -> Result<Vec<GeoLocation>, NagoyaError> {
+        let mut locations = Vec::new();
+        if let (Some(latitude), Some(longitude)) = (self.latitude, self.longitude) {
+            locations.push(GeoLocation::Point {
+                latitude,
+                longitude,
+            });
+        }
+        match (self.west, self.east, self.south, self.north) {
+            // EML gives points as boxes without extent
+            (Some(west), Some(east), Some(south), Some(north))
+                if west == east && south == north =>
+            {
+                locations.push(GeoLocation::Point {
+                    latitude: south,
+                    longitude: west,
+                })
+            }
+            (Some(west), Some(east), Some(south), Some(north)) => {
+                locations.push(GeoLocation::Box {
+                    west,
+                    east,
+                    south,
+                    north,
+                })
+            }
+            (None, None, None, None) => {}
+            _ => return Err(malformed("Bounding box without all four bounds")),
+        }
+        if locations.is_empty()
+            && let Some(name) = self.place
+        {
+            locations.push(GeoLocation::Place { name });
+        }
+        Ok(locations)
+    }
+}
+
+fn number(value: &str, name: &str) -> Result<f64, NagoyaError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| malformed(format!("Invalid {name} \"{value}\"")))
+}
+
+// Locations of DataCite XML, also with the space-separated points and boxes of schema version 3,
+// and of EML geographicCoverage
+fn xml_locations(xml: &str) -> Result<Vec<GeoLocation>, NagoyaError> {
+    let mut locations = Vec::new();
+    let mut coverage = Coverage::default();
+    walk(xml, |element| {
+        let text = element.text.as_str();
+        let name = element.path.last().map_or("", String::as_str);
+        let value = || number(text, name).map(Some);
+        if element.ends_with(&["geoLocations", "geoLocation"])
+            || element.ends_with(&["coverage", "geographicCoverage"])
+        {
+            locations.extend(std::mem::take(&mut coverage).into_locations()?);
+        } else if element.ends_with(&["geoLocationPoint", "pointLatitude"]) {
+            coverage.latitude = value()?;
+        } else if element.ends_with(&["geoLocationPoint", "pointLongitude"]) {
+            coverage.longitude = value()?;
+        } else if element.ends_with(&["geoLocationBox", "westBoundLongitude"])
+            || element.ends_with(&["boundingCoordinates", "westBoundingCoordinate"])
+        {
+            coverage.west = value()?;
+        } else if element.ends_with(&["geoLocationBox", "eastBoundLongitude"])
+            || element.ends_with(&["boundingCoordinates", "eastBoundingCoordinate"])
+        {
+            coverage.east = value()?;
+        } else if element.ends_with(&["geoLocationBox", "southBoundLatitude"])
+            || element.ends_with(&["boundingCoordinates", "southBoundingCoordinate"])
+        {
+            coverage.south = value()?;
+        } else if element.ends_with(&["geoLocationBox", "northBoundLatitude"])
+            || element.ends_with(&["boundingCoordinates", "northBoundingCoordinate"])
+        {
+            coverage.north = value()?;
+        } else if element.ends_with(&["geoLocation", "geoLocationPlace"])
+            || element.ends_with(&["geographicCoverage", "geographicDescription"])
+        {
+            coverage.place = Some(text.to_string()).filter(|place| !place.is_empty());
+        } else if element.ends_with(&["geoLocation", "geoLocationPoint"]) && !text.is_empty() {
+            // "latitude longitude"
+            let values: Vec<&str> = text.split_whitespace().collect();
+            let [latitude, longitude] = values[..] else {
+                return Err(malformed(format!("Invalid geoLocationPoint \"{text}\"")));
+            };
+            coverage.latitude = Some(number(latitude, name)?);
+            coverage.longitude = Some(number(longitude, name)?);
+        } else if element.ends_with(&["geoLocation", "geoLocationBox"]) && !text.is_empty() {
+            // "south west north east"
+            let values: Vec<&str> = text.split_whitespace().collect();
+            let [south, west, north, east] = values[..] else {
+                return Err(malformed(format!("Invalid geoLocationBox \"{text}\"")));
+            };
+            coverage.south = Some(number(south, name)?);
+            coverage.west = Some(number(west, name)?);
+            coverage.north = Some(number(north, name)?);
+            coverage.east = Some(number(east, name)?);
+        }
+        Ok(())
+    })?;
+    Ok(locations)
+}
+
+// Locations of DataCite JSON, either plain or as returned by the DataCite REST API with the
+// geoLocations nested in data.attributes
+fn json_locations(json: &str) -> Result<Vec<GeoLocation>, NagoyaError> {
+    let document: Value = serde_json::from_str(json).map_err(malformed)?;
+    let geo_locations = [
+        "/geoLocations",
+        "/attributes/geoLocations",
+        "/data/attributes/geoLocations",
+    ]
+    .iter()
+    .find_map(|pointer| document.pointer(pointer))
+    .and_then(Value::as_array)
+    .cloned()
+    .unwrap_or_default();
+    let field = |object: &Value, name: &str| -> Result<Option<f64>, NagoyaError> {
+        match object.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::Number(value)) => Ok(value.as_f64()),
+            Some(Value::String(value)) if value.trim().is_empty() => Ok(None),
+            Some(Value::String(value)) => number(value, name).map(Some),
+            Some(value) => Err(malformed(format!("Invalid {name} {value}"))),
+        }
+    };
+    let mut locations = Vec::new();
+    for geo_location in &geo_locations {
+        let point = geo_location.get("geoLocationPoint").unwrap_or(&Value::Null);
+        let bounds = geo_location.get("geoLocationBox").unwrap_or(&Value::Null);
+        let coverage = Coverage {
+            latitude: field(point, "pointLatitude")?,
+            longitude: field(point, "pointLongitude")?,
+            west: field(bounds, "westBoundLongitude")?,
+            east: field(bounds, "eastBoundLongitude")?,
+            south: field(bounds, "southBoundLatitude")?,
+            north: field(bounds, "northBoundLatitude")?,
+            place: geo_location
+                .get("geoLocationPlace")
+                .and_then(Value::as_str)
+                .map(|place| place.trim().to_string())
+                .filter(|place| !place.is_empty()),
+        };
+        locations.extend(coverage.into_locations()?);
+    }
+    Ok(locations)
+}
+
+// Corners, midpoints of the edges and center of a box, checked if no country boundaries are
+// loaded. Boxes with west > east cross the antimeridian
+fn sample_points(west: f64, east: f64, south: f64, north: f64) -> Vec<Coordinates> {
+    let width = if east >= west {
+        east - west
+    } else {
+        east - west + 360.0
+    };
+    let mut middle = west + width / 2.0;
+    if middle > 180.0 {
+        middle -= 360.0;
+    }
+    [south, (south + north) / 2.0, north]
+        .into_iter()
+        .flat_map(|latitude| {
+            [west, middle, east].map(|longitude| Coordinates {
+                latitude,
+                longitude,
+                uncertainty_in_meters: None,
+            })
+        })
+        .collect()
+}
+
+fn geo(coordinates: Coordinates) -> Check {
+    Check::Geo(NagoyaCheckDataGeo {
+        coordinates: CoordinateInput::Decimal(coordinates),
+        epsg: None,
+        event_date: None,
+        declared_country: None,
+        consensus: false,
+        require_agreement: None,
+    })
+}
+
+fn country(alpha2: String) -> Result<Check, NagoyaError> {
+    Ok(Check::Country(NagoyaCheckDataCC {
+        probe_country: Some(CountryInput::try_from(alpha2)?),
+        countries: Vec::new(),
+    }))
+}
+
+// Checks of a location, a single one for points and places and one per covered country or sample
+// point for boxes, along with the method used for boxes
+fn location_checks(
+    location: &GeoLocation,
+    names: &CountryNames,
+    boundaries: Option<&CountryBoundaries>,
+) -> (Vec<Result<Check, NagoyaError>>, Option<BoxMethod>) {
+    match location {
+        GeoLocation::Point {
+            latitude,
+            longitude,
+        } => (
+            vec![Ok(geo(Coordinates {
+                latitude: *latitude,
+                longitude: *longitude,
+                uncertainty_in_meters: None,
+            }))],
+            None,
+        ),
+        GeoLocation::Box {
+            west,
+            east,
+            south,
+            north,
+        } => match boundaries {
+            Some(boundaries) => {
+                let codes = boundaries.countries_intersecting(*west, *east, *south, *north);
+                if codes.is_empty() {
+                    (
+                        vec![Err(NagoyaError::UnresolvableCoordinates {
+                            quality_flags: Vec::new(),
+                        })],
+                        Some(BoxMethod::Boundaries),
+                    )
+                } else {
+                    (
+                        codes.into_iter().map(country).collect(),
+                        Some(BoxMethod::Boundaries),
+                    )
+                }
+            }
+            None => (
+                sample_points(*west, *east, *south, *north)
+                    .into_iter()
+                    .map(|coordinates| Ok(geo(coordinates)))
+                    .collect(),
+                Some(BoxMethod::Sampled),
+            ),
+        },
+        GeoLocation::Place { name } => (vec![place_check(name, names)], None),
+    }
+}
+
+fn place_check(name: &str, names: &CountryNames) -> Result<Check, NagoyaError> {
+    let normalized = normalize_country(name, names)?;
+    let country_id = normalized.country.ok_or_else(|| NagoyaError::NotACountry {
+        input: name.to_string(),
+        reason: format!(
+            "May refer to {}",
+            normalized
+                .candidates
+                .iter()
+                .map(|candidate| candidate.alpha2.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    })?;
+    country(country_id.alpha2)
+}
+
+// A box needs a notice if any country it covers does, so the countries become candidates. Sample
+// points at sea resolve to no country and are left out, any other failure leaves the box unchecked
+fn combine_box(
+    results: Vec<Result<NagoyaResponse, NagoyaError>>,
+) -> Result<NagoyaResponse, NagoyaError> {
+    let mut candidates: Vec<CandidateCountry> = Vec::new();
+    for result in results {
+        let response = match result {
+            Ok(response) => response,
+            Err(NagoyaError::UnresolvableCoordinates { .. }) => continue,
+            Err(error) => return Err(error),
+        };
+        let Some(country_code) = response.country_code else {
+            continue;
+        };
+        // Subnational regimes may apply to a part of the country only
+        match candidates
+            .iter_mut()
+            .find(|candidate| candidate.country_code == country_code)
+        {
+            Some(candidate) => candidate.check_result |= response.check_result,
+            None => candidates.push(CandidateCountry {
+                country_code,
+                check_result: response.check_result,
+            }),
+        }
+    }
+    candidates.sort_by(|a, b| a.country_code.cmp(&b.country_code));
+    match candidates.as_slice() {
+        [] => Err(NagoyaError::UnresolvableCoordinates {
+            quality_flags: Vec::new(),
+        }),
+        [candidate] => Ok(NagoyaResponse {
+            check_result: candidate.check_result,
+            country_code: Some(candidate.country_code.clone()),
+            ..Default::default()
+        }),
+        _ => Ok(NagoyaResponse {
+            check_result: candidates.iter().any(|candidate| candidate.check_result),
+            ambiguous_location: true,
+            candidate_countries: candidates,
+            ..Default::default()
+        }),
+    }
+}
+
+// Checks the points, boxes and places of DataCite JSON or XML or EML metadata and decides whether
+// the dataset needs a Nagoya notice
+#[instrument(skip_all)]
+pub async fn check_dataset_metadata(
+    file: Vec<u8>,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+) -> Result<DatasetCheck, NagoyaError> {
+    let content = String::from_utf8(file).map_err(malformed)?;
+    let content = content.trim_start_matches('\u{feff}').trim_start();
+    let locations = if content.starts_with('{') {
+        json_locations(content)?
+    } else {
+        xml_locations(content)?
+    };
+    if locations.is_empty() {
+        return Err(malformed(
+            "Neither DataCite geoLocations nor EML geographicCoverage found",
+        ));
+    }
+    event!(Level::DEBUG, "Checking {} locations", locations.len());
+
+    let (checks, methods): (Vec<_>, Vec<_>) = locations
+        .iter()
+        .map(|location| {
+            location_checks(
+                location,
+                &implementing_countries.names,
+                state.layers.boundaries.as_deref(),
+            )
+        })
+        .unzip();
+    let counts: Vec<usize> = checks.iter().map(Vec::len).collect();
+    let mut responses = check_all(
+        checks.into_iter().flatten().collect(),
+        implementing_countries,
+        state,
+        state.config.upload_max_records,
+    )
+    .await?
+    .into_iter();
+    let results: Vec<Result<NagoyaResponse, NagoyaError>> = counts
+        .iter()
+        .zip(&methods)
+        .map(|(count, method)| {
+            let mut location_responses: Vec<_> = responses.by_ref().take(*count).collect();
+            if method.is_some() {
+                combine_box(location_responses)
+            } else {
+                location_responses
+                    .pop()
+                    .unwrap_or(Err(NagoyaError::GenericInternalServerError))
+            }
+        })
+        .collect();
+    let summary = summarize(&results);
+    let mut notice_countries: Vec<String> = results
+        .iter()
+        .flatten()
+        .flat_map(|response| {
+            response
+                .country_code
+                .iter()
+                .filter(|_| response.check_result)
+                .chain(
+                    response
+                        .candidate_countries
+                        .iter()
+                        .filter(|candidate| candidate.check_result)
+                        .map(|candidate| &candidate.country_code),
+                )
+                .cloned()
+        })
+        .collect();
+    notice_countries.sort();
+    notice_countries.dedup();
+    let locations = locations
+        .into_iter()
+        .zip(methods)
+        .zip(results)
+        .map(|((location, method), result)| match result {
+            Ok(response) => LocationResult {
+                location,
+                method,
+                result: Some(response),
+                error: None,
+            },
+            Err(error) => LocationResult {
+                location,
+                method,
+                result: None,
+                error: Some(error.error_response()),
+            },
+        })
+        .collect();
+    // Failed locations may lie in a party as well, so without a positive one the notice is open
+    let nagoya_notice = if summary.positive > 0 || !notice_countries.is_empty() {
+        Some(true)
+    } else if summary.failed > 0 {
+        None
+    } else {
+        Some(false)
+    };
+    Ok(DatasetCheck {
+        nagoya_notice,
+        notice_countries,
+        locations,
+        summary,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::tests::state;
+    use crate::country::CountryId;
+
+    #[test]
+    fn test_datacite_xml() {
+        let locations = xml_locations(
+            r#"<resource xmlns="http://datacite.org/schema/kernel-4">
+  <geoLocations>
+    <geoLocation>
+      <geoLocationPlace>Manaus, Brazil</geoLocationPlace>
+      <geoLocationPoint>
+        <pointLongitude>-60.02</pointLongitude>
+        <pointLatitude>-3.10</pointLatitude>
+      </geoLocationPoint>
+    </geoLocation>
+    <geoLocation>
+      <geoLocationBox>
+        <westBoundLongitude>5.87</westBoundLongitude>
+        <eastBoundLongitude>15.04</eastBoundLongitude>
+        <southBoundLatitude>47.27</southBoundLatitude>
+        <northBoundLatitude>55.06</northBoundLatitude>
+      </geoLocationBox>
+    </geoLocation>
+    <geoLocation>
+      <geoLocationPlace>Peru</geoLocationPlace>
+    </geoLocation>
+    <geoLocation>
+      <geoLocationPoint>31.233 -67.302</geoLocationPoint>
+    </geoLocation>
+  </geoLocations>
+</resource>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            locations,
+            vec![
+                GeoLocation::Point {
+                    latitude: -3.10,
+                    longitude: -60.02
+                },
+                GeoLocation::Box {
+                    west: 5.87,
+                    east: 15.04,
+                    south: 47.27,
+                    north: 55.06
+                },
+                GeoLocation::Place {
+                    name: String::from("Peru")
+                },
+                GeoLocation::Point {
+                    latitude: 31.233,
+                    longitude: -67.302
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_eml() {
+        let locations = xml_locations(
+            r#"<eml:eml xmlns:eml="https://eml.ecoinformatics.org/eml-2.2.0">
+  <dataset>
+    <coverage>
+      <geographicCoverage>
+        <geographicDescription>Amazon basin</geographicDescription>
+        <boundingCoordinates>
+          <westBoundingCoordinate>-80</westBoundingCoordinate>
+          <eastBoundingCoordinate>-50</eastBoundingCoordinate>
+          <northBoundingCoordinate>5</northBoundingCoordinate>
+          <southBoundingCoordinate>-20</southBoundingCoordinate>
+        </boundingCoordinates>
+      </geographicCoverage>
+      <geographicCoverage>
+        <geographicDescription>Station</geographicDescription>
+        <boundingCoordinates>
+          <westBoundingCoordinate>13.4</westBoundingCoordinate>
+          <eastBoundingCoordinate>13.4</eastBoundingCoordinate>
+          <northBoundingCoordinate>52.5</northBoundingCoordinate>
+          <southBoundingCoordinate>52.5</southBoundingCoordinate>
+        </boundingCoordinates>
+      </geographicCoverage>
+    </coverage>
+  </dataset>
+</eml:eml>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            locations,
+            vec![
+                GeoLocation::Box {
+                    west: -80.0,
+                    east: -50.0,
+                    south: -20.0,
+                    north: 5.0
+                },
+                GeoLocation::Point {
+                    latitude: 52.5,
+                    longitude: 13.4
+                },
+            ]
+        );
+        assert!(matches!(
+            xml_locations(
+                "<eml><coverage><geographicCoverage><boundingCoordinates><westBoundingCoordinate>1</westBoundingCoordinate></boundingCoordinates></geographicCoverage></coverage></eml>"
+            ),
+            Err(NagoyaError::MalformedUpload { .. })
+        ));
+    }
+
+    #[test]
+    fn test_datacite_json() {
+        let locations = json_locations(
+            r#"{"data": {"id": "10.5072/example", "attributes": {"geoLocations": [
+                {"geoLocationPoint": {"pointLatitude": "-3.10", "pointLongitude": -60.02}},
+                {"geoLocationPlace": "Germany", "geoLocationBox": {}},
+                {"geoLocationPlace": "Peru"}
+            ]}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            locations,
+            vec![
+                GeoLocation::Point {
+                    latitude: -3.10,
+                    longitude: -60.02
+                },
+                GeoLocation::Place {
+                    name: String::from("Germany")
+                },
+                GeoLocation::Place {
+                    name: String::from("Peru")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sample_points() {
+        let points = sample_points(170.0, -170.0, -10.0, 10.0);
+        assert_eq!(points.len(), 9);
+        assert_eq!((points[4].latitude, points[4].longitude), (0.0, 180.0));
+        let points = sample_points(-80.0, -50.0, -20.0, 5.0);
+        assert_eq!((points[0].latitude, points[0].longitude), (-20.0, -80.0));
+        assert_eq!((points[4].latitude, points[4].longitude), (-7.5, -65.0));
+        assert_eq!((points[8].latitude, points[8].longitude), (5.0, -50.0));
+    }
+
+    #[test]
+    fn test_combine_box() {
+        let response = |country_code: &str, check_result| {
+            Ok(NagoyaResponse {
+                check_result,
+                country_code: Some(String::from(country_code)),
+                ..Default::default()
+            })
+        };
+        let combined = combine_box(vec![
+            response("DE", false),
+            Err(NagoyaError::UnresolvableCoordinates {
+                quality_flags: Vec::new(),
+            }),
+            response("PE", true),
+            response("DE", false),
+        ])
+        .unwrap();
+        assert!(combined.check_result);
+        assert!(combined.ambiguous_location);
+        assert_eq!(
+            combined.candidate_countries,
+            vec![
+                CandidateCountry {
+                    country_code: String::from("DE"),
+                    check_result: false
+                },
+                CandidateCountry {
+                    country_code: String::from("PE"),
+                    check_result: true
+                },
+            ]
+        );
+        let combined = combine_box(vec![response("DE", false)]).unwrap();
+        assert_eq!(combined.country_code.as_deref(), Some("DE"));
+        assert_eq!(
+            combine_box(vec![
+                response("PE", true),
+                Err(NagoyaError::ExternalResourceTimeout)
+            ])
+            .unwrap_err(),
+            NagoyaError::ExternalResourceTimeout
+        );
+        assert_eq!(
+            combine_box(vec![Err(NagoyaError::UnresolvableCoordinates {
+                quality_flags: Vec::new()
+            })])
+            .unwrap_err(),
+            NagoyaError::UnresolvableCoordinates {
+                quality_flags: Vec::new()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_dataset_metadata() {
+        let implementing_countries = ImplementingCountries {
+            countries: ["PER".parse::<CountryId>().unwrap()].into(),
+            ..Default::default()
+        };
+        let state = state(10);
+        let check = |json: &str| {
+            check_dataset_metadata(json.as_bytes().to_vec(), &implementing_countries, &state)
+        };
+        let result = check(
+            r#"{"geoLocations": [{"geoLocationPlace": "Germany"}, {"geoLocationPlace": "Peru"}, {"geoLocationPlace": "Atlantis"}]}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.nagoya_notice, Some(true));
+        assert_eq!(result.notice_countries, vec!["PE"]);
+        assert_eq!(result.summary.failed, 1);
+        assert!(result.locations[2].error.is_some());
+
+        let result = check(r#"{"geoLocations": [{"geoLocationPlace": "Germany"}]}"#)
+            .await
+            .unwrap();
+        assert_eq!(result.nagoya_notice, Some(false));
+        let result = check(
+            r#"{"geoLocations": [{"geoLocationPlace": "Germany"}, {"geoLocationPlace": "Atlantis"}]}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.nagoya_notice, None);
+        assert!(matches!(
+            check(r#"{"titles": []}"#).await,
+            Err(NagoyaError::MalformedUpload { .. })
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::boundaries::{PolygonFeature, polygon_features};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use tracing::{Level, event, instrument};
+
+// How to decide on the country whose ABS measures apply within a disputed area. Nominatim follows
+// OSM's on-the-ground convention, which is not necessarily the institution's legal position
+#[derive(Clone, Debug, PartialEq)]
+pub enum DisputedAreaPolicy {
+    // Measures of every claimant are to be respected
+    AllClaimants,
+    // Only the measures of the administering country are respected
+    DeFacto,
+    // The country named by a reference, e.g. the UN's or the own government's position
+    Reference(String),
+}
+
+impl FromStr for DisputedAreaPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "all_claimants" => Ok(DisputedAreaPolicy::AllClaimants),
+            "de_facto" => Ok(DisputedAreaPolicy::DeFacto),
+            _ => match policy.strip_prefix("reference:") {
+                Some(reference) if !reference.is_empty() => {
+                    Ok(DisputedAreaPolicy::Reference(reference.to_string()))
+                }
+                _ => Err(format!("Unknown disputed area policy \"{policy}\"")),
+            },
+        }
+    }
+}
+
+impl Display for DisputedAreaPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisputedAreaPolicy::AllClaimants => write!(f, "all_claimants"),
+            DisputedAreaPolicy::DeFacto => write!(f, "de_facto"),
+            DisputedAreaPolicy::Reference(reference) => write!(f, "reference:{reference}"),
+        }
+    }
+}
+
+// Disputed areas loaded from a local GeoJSON file. Each feature needs the properties `name`,
+// `claimants` (list of alpha-2 codes) and `administered_by` (alpha-2 code) and may name the country
+// according to several references in `references`, e.g. {"UN": "MA"}
+pub struct DisputedAreas {
+    pub policy: DisputedAreaPolicy,
+    areas: Vec<DisputedArea>,
+}
+
+pub struct DisputedArea {
+    pub name: String,
+    pub claimants: Vec<String>,
+    pub administered_by: Option<String>,
+    pub references: HashMap<String, String>,
+    feature: PolygonFeature,
+}
+
+impl DisputedAreas {
+    #[instrument]
+    pub fn from_file(path: &str, policy: DisputedAreaPolicy) -> Result<Self, Box<dyn Error>> {
+        Self::from_geojson(&std::fs::read_to_string(path)?, policy)
+    }
+
+    pub fn from_geojson(geojson: &str, policy: DisputedAreaPolicy) -> Result<Self, Box<dyn Error>> {
+        let mut areas = Vec::new();
+        for feature in polygon_features(geojson)? {
+            let codes = |key: &str| -> Vec<String> {
+                feature
+                    .properties
+                    .get(key)
+                    .and_then(|value| value.as_array())
+                    .map(|values| {
+                        values
+                            .iter()
+                            .filter_map(|value| value.as_str())
+                            .map(str::to_uppercase)
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            };
+            let claimants = codes("claimants");
+            if claimants.is_empty() {
+                return Err("Every disputed area needs at least one claimant".into());
+            }
+            let references = feature
+                .properties
+                .get("references")
+                .and_then(|value| value.as_object())
+                .map(|references| {
+                    references
+                        .iter()
+                        .filter_map(|(reference, code)| {
+                            Some((reference.clone(), code.as_str()?.to_uppercase()))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            areas.push(DisputedArea {
+                name: feature.property("name").unwrap_or("unnamed").to_string(),
+                administered_by: feature.property("administered_by").map(str::to_uppercase),
+                claimants,
+                references,
+                feature,
+            });
+        }
+        event!(Level::INFO, "Loaded {} disputed areas", areas.len());
+        Ok(Self { policy, areas })
+    }
+
+    pub fn area_at(&self, latitude: f64, longitude: f64) -> Option<&DisputedArea> {
+        self.areas
+            .iter()
+            .find(|area| area.feature.contains(latitude, longitude))
+    }
+}
+
+impl DisputedArea {
+    // Countries whose measures apply according to the policy. Falls back to all claimants if the
+    // policy cannot be applied to this area, as that is the cautious choice
+    pub fn applicable_countries(&self, policy: &DisputedAreaPolicy) -> Vec<String> {
+        let country = match policy {
+            DisputedAreaPolicy::AllClaimants => None,
+            DisputedAreaPolicy::DeFacto => self.administered_by.clone(),
+            DisputedAreaPolicy::Reference(reference) => self.references.get(reference).cloned(),
+        };
+        country.map_or_else(|| self.claimants.clone(), |country| vec![country])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TESTDATA: &str = r#"
+    {
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "properties": {
+                    "name": "Test Area",
+                    "claimants": ["aa", "BB"],
+                    "administered_by": "AA",
+                    "references": {"UN": "BB"}
+                },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[9, 0], [10, 0], [10, 1], [9, 1], [9, 0]]]
+                }
+            }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn test_policy_from_str() {
+        assert_eq!(
+            "reference:UN".parse::<DisputedAreaPolicy>(),
+            Ok(DisputedAreaPolicy::Reference(String::from("UN")))
+        );
+        assert!("reference:".parse::<DisputedAreaPolicy>().is_err());
+        assert!("whatever".parse::<DisputedAreaPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_applicable_countries() {
+        let areas =
+            DisputedAreas::from_geojson(TESTDATA, DisputedAreaPolicy::AllClaimants).unwrap();
+        assert!(areas.area_at(5.0, 5.0).is_none());
+        let area = areas.area_at(0.5, 9.5).unwrap();
+        assert_eq!(area.name, "Test Area");
+        assert_eq!(
+            area.applicable_countries(&DisputedAreaPolicy::AllClaimants),
+            vec!["AA", "BB"]
+        );
+        assert_eq!(
+            area.applicable_countries(&DisputedAreaPolicy::DeFacto),
+            vec!["AA"]
+        );
+        assert_eq!(
+            area.applicable_countries(&DisputedAreaPolicy::Reference(String::from("UN"))),
+            vec!["BB"]
+        );
+        assert_eq!(
+            area.applicable_countries(&DisputedAreaPolicy::Reference(String::from("EU"))),
+            vec!["AA", "BB"]
+        );
+    }
+}
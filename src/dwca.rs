@@ -0,0 +1,554 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::batch::{RecordFields, check_all, summarize};
+use crate::csv_upload::{RESULT_COLUMNS, result_fields};
+use crate::models::{AppState, DatasetSummary, ImplementingCountries, NagoyaError, NagoyaResponse};
+use crate::upload::malformed;
+use crate::xml::walk;
+use csv::{ReaderBuilder, WriterBuilder};
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use tokio::task::spawn_blocking;
+use tracing::{Level, event, instrument};
+use zip::ZipArchive;
+use zip::write::{SimpleFileOptions, ZipWriter};
+
+const ANNOTATIONS_FILE: &str = "nagoya_annotations.txt";
+const SUMMARY_FILE: &str = "nagoya_summary.json";
+
+// Core or extension of an archive as described in meta.xml, cf. the Darwin Core text guide
+#[derive(Debug, PartialEq)]
+struct DataFile {
+    // Local name of the row type, e.g. Occurrence or Event
+    row_type: String,
+    locations: Vec<String>,
+    delimiter: u8,
+    quote: Option<u8>,
+    ignore_header_lines: usize,
+    // Column of the id in the core or of the coreid in an extension
+    id: Option<usize>,
+    fields: Vec<Field>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Field {
+    // Local name of the term, e.g. countryCode for http://rs.tdwg.org/dwc/terms/countryCode
+    term: String,
+    index: Option<usize>,
+    // Value for all rows, used if the column is missing or empty
+    default: Option<String>,
+}
+
+impl DataFile {
+    fn value(&self, row: &[String], term: &str) -> Option<String> {
+        let field = self.fields.iter().find(|field| field.term == term)?;
+        field
+            .index
+            .and_then(|index| row.get(index))
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(String::from)
+            .or_else(|| field.default.clone())
+    }
+
+    fn id(&self, row: &[String]) -> Option<String> {
+        self.id
+            .and_then(|index| row.get(index))
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+fn local_name(term: &str) -> String {
+    term.rsplit(['/', '#', ':'])
+        .next()
+        .unwrap_or(term)
+        .to_string()
+}
+
+// Delimiters are given escaped in meta.xml, e.g. "\t" for tabs. An empty one means none
+fn separator(value: &str) -> Result<Option<u8>, NagoyaError> {
+    match value {
+        "" => Ok(None),
+        "\\t" => Ok(Some(b'\t')),
+        value if value.len() == 1 => Ok(Some(value.as_bytes()[0])),
+        value => Err(malformed(format!(
+            "Unsupported separator \"{value}\" in meta.xml"
+        ))),
+    }
+}
+
+fn index(attributes: &HashMap<String, String>) -> Result<Option<usize>, NagoyaError> {
+    attributes
+        .get("index")
+        .map(|index| {
+            index
+                .trim()
+                .parse()
+                .map_err(|_| malformed(format!("Invalid index \"{index}\" in meta.xml")))
+        })
+        .transpose()
+}
+
+fn data_file(attributes: &HashMap<String, String>) -> Result<DataFile, NagoyaError> {
+    Ok(DataFile {
+        row_type: local_name(attributes.get("rowType").map_or("", String::as_str)),
+        locations: Vec::new(),
+        delimiter: separator(
+            attributes
+                .get("fieldsTerminatedBy")
+                .map_or(",", String::as_str),
+        )?
+        .ok_or_else(|| malformed("Empty fieldsTerminatedBy in meta.xml"))?,
+        quote: separator(
+            attributes
+                .get("fieldsEnclosedBy")
+                .map_or("\"", String::as_str),
+        )?,
+        ignore_header_lines: attributes
+            .get("ignoreHeaderLines")
+            .map_or(Ok(0), |lines| lines.trim().parse())
+            .map_err(|_| malformed("Invalid ignoreHeaderLines in meta.xml"))?,
+        id: None,
+        fields: Vec::new(),
+    })
+}
+
+// Returns the core followed by the extensions
+fn parse_meta(xml: &str) -> Result<Vec<DataFile>, NagoyaError> {
+    let mut files = Vec::new();
+    // Children are visited before the core or extension they belong to, so they are collected
+    // until it is closed
+    let mut locations = Vec::new();
+    let mut id = None;
+    let mut fields = Vec::new();
+    walk(xml, |element| {
+        let in_file = element
+            .path
+            .iter()
+            .any(|name| name == "core" || name == "extension");
+        match element.path.last().map(String::as_str) {
+            Some("location") if in_file => locations.push(element.text),
+            Some("id" | "coreid") if in_file => id = index(&element.attributes)?,
+            Some("field") if in_file => fields.push(Field {
+                term: local_name(element.attributes.get("term").map_or("", String::as_str)),
+                index: index(&element.attributes)?,
+                default: element.attributes.get("default").cloned(),
+            }),
+            Some(name @ ("core" | "extension")) => {
+                let file = DataFile {
+                    locations: std::mem::take(&mut locations),
+                    id: id.take(),
+                    fields: std::mem::take(&mut fields),
+                    ..data_file(&element.attributes)?
+                };
+                // The core comes first, wherever it is in meta.xml
+                if name == "core" {
+                    files.insert(0, file);
+                } else {
+                    files.push(file);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+    if files.is_empty() {
+        return Err(malformed("No core in meta.xml"));
+    }
+    Ok(files)
+}
+
+struct Archive {
+    zip: ZipArchive<Cursor<Vec<u8>>>,
+    // Directory of meta.xml, archives are often zipped with their enclosing directory
+    directory: String,
+    // Bytes that may still be extracted, so a small upload cannot unpack to gigabytes
+    remaining: u64,
+}
+
+impl Archive {
+    fn read(&mut self, name: &str) -> Result<Vec<u8>, NagoyaError> {
+        let file = self
+            .zip
+            .by_name(&format!("{}{name}", self.directory))
+            .map_err(|_| malformed(format!("{name} missing in archive")))?;
+        let too_large = || malformed(format!("{name} exceeds the maximum extracted size"));
+        if file.size() > self.remaining {
+            return Err(too_large());
+        }
+        // The size in the header may be forged, so the extraction is limited as well
+        let mut content = Vec::new();
+        file.take(self.remaining + 1)
+            .read_to_end(&mut content)
+            .map_err(malformed)?;
+        if content.len() as u64 > self.remaining {
+            return Err(too_large());
+        }
+        self.remaining -= content.len() as u64;
+        Ok(content)
+    }
+
+    fn rows(&mut self, file: &DataFile) -> Result<Vec<Vec<String>>, NagoyaError> {
+        let mut rows = Vec::new();
+        for location in &file.locations {
+            let content = self.read(location)?;
+            let content = content
+                .strip_prefix("\u{feff}".as_bytes())
+                .unwrap_or(&content);
+            let mut reader = ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .delimiter(file.delimiter)
+                .quoting(file.quote.is_some())
+                .quote(file.quote.unwrap_or(b'"'))
+                .from_reader(content);
+            for record in reader.byte_records().skip(file.ignore_header_lines) {
+                let record = record.map_err(malformed)?;
+                rows.push(
+                    record
+                        .iter()
+                        .map(|value| String::from_utf8_lossy(value).to_string())
+                        .collect(),
+                );
+            }
+        }
+        Ok(rows)
+    }
+}
+
+fn open(file: Vec<u8>, max_extracted_size: u64) -> Result<Archive, NagoyaError> {
+    let zip = ZipArchive::new(Cursor::new(file)).map_err(malformed)?;
+    let meta = zip
+        .file_names()
+        .filter(|name| name.rsplit('/').next() == Some("meta.xml"))
+        .min_by_key(|name| name.len())
+        .ok_or_else(|| malformed("meta.xml missing in archive"))?;
+    let directory = meta.trim_end_matches("meta.xml").to_string();
+    Ok(Archive {
+        zip,
+        directory,
+        remaining: max_extracted_size,
+    })
+}
+
+// Provenance of an occurrence, falling back to the event it belongs to for terms the occurrence
+// does not give itself
+fn record_fields(
+    occurrence: (&DataFile, &[String]),
+    event: Option<(&DataFile, &[String])>,
+) -> RecordFields {
+    let value = |term: &str| {
+        occurrence
+            .0
+            .value(occurrence.1, term)
+            .or_else(|| event.and_then(|(file, row)| file.value(row, term)))
+    };
+    RecordFields {
+        country: value("countryCode").or_else(|| value("country")),
+        latitude: value("decimalLatitude"),
+        longitude: value("decimalLongitude"),
+        uncertainty_in_meters: value("coordinateUncertaintyInMeters"),
+        date: value("eventDate"),
+    }
+}
+
+// Ids and provenance of every occurrence, from an occurrence core or from an occurrence extension
+// of an event core
+fn read_occurrences(
+    file: Vec<u8>,
+    max_extracted_size: u64,
+) -> Result<(Vec<Option<String>>, Vec<RecordFields>), NagoyaError> {
+    let mut archive = open(file, max_extracted_size)?;
+    let meta = String::from_utf8(archive.read("meta.xml")?).map_err(malformed)?;
+    let files = parse_meta(&meta)?;
+    let core = &files[0];
+    let core_rows = archive.rows(core)?;
+
+    Ok(if core.row_type == "Occurrence" {
+        core_rows
+            .iter()
+            .map(|row| {
+                (
+                    core.value(row, "occurrenceID").or_else(|| core.id(row)),
+                    record_fields((core, row), None),
+                )
+            })
+            .unzip()
+    } else {
+        let extension = files[1..]
+            .iter()
+            .find(|file| file.row_type == "Occurrence")
+            .ok_or_else(|| malformed("Neither an occurrence core nor an occurrence extension"))?;
+        let events: HashMap<String, &Vec<String>> = core_rows
+            .iter()
+            .filter_map(|row| Some((core.id(row)?, row)))
+            .collect();
+        archive
+            .rows(extension)?
+            .iter()
+            .map(|row| {
+                let event = extension
+                    .id(row)
+                    .and_then(|id| events.get(&id))
+                    .map(|event| (core, event.as_slice()));
+                (
+                    extension
+                        .value(row, "occurrenceID")
+                        .or_else(|| extension.id(row)),
+                    record_fields((extension, row), event),
+                )
+            })
+            .unzip()
+    })
+}
+
+// Zip with the per-record annotations as tab-separated text and the summary as JSON
+fn write_annotations(
+    ids: Vec<Option<String>>,
+    results: Vec<Result<NagoyaResponse, NagoyaError>>,
+    summary: DatasetSummary,
+) -> Result<Vec<u8>, NagoyaError> {
+    let mut annotations = WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_writer(Vec::new());
+    annotations
+        .write_record(["id"].into_iter().chain(RESULT_COLUMNS))
+        .map_err(malformed)?;
+    for (row, (id, result)) in ids.into_iter().zip(results).enumerate() {
+        let id = id.unwrap_or_else(|| (row + 1).to_string());
+        let [check_result, country_code, reasons] = result_fields(result);
+        annotations
+            .write_record([&id, &check_result, &country_code, &reasons])
+            .map_err(malformed)?;
+    }
+    let annotations = annotations
+        .into_inner()
+        .map_err(|error| malformed(error.error()))?;
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    zip.start_file(ANNOTATIONS_FILE, options)
+        .map_err(malformed)?;
+    zip.write_all(&annotations).map_err(malformed)?;
+    zip.start_file(SUMMARY_FILE, options).map_err(malformed)?;
+    serde_json::to_writer_pretty(&mut zip, &summary).map_err(malformed)?;
+    Ok(zip.finish().map_err(malformed)?.into_inner())
+}
+
+// Checks every occurrence of a Darwin Core Archive. Returns a zip with the per-record annotations
+// and a summary of the affected provider countries. Unzipping and zipping run on the blocking
+// thread pool, as large archives would stall the other requests of a worker
+#[instrument(skip_all)]
+pub async fn annotate_dwca(
+    file: Vec<u8>,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+) -> Result<Vec<u8>, NagoyaError> {
+    let max_extracted_size = state.config.max_extracted_size;
+    // The blocking tasks only fail if they panic
+    let (ids, records) = spawn_blocking(move || read_occurrences(file, max_extracted_size))
+        .await
+        .map_err(|_| NagoyaError::GenericInternalServerError)??;
+    event!(Level::DEBUG, "Checking {} occurrences", records.len());
+
+    let checks = records.into_iter().map(RecordFields::into_check).collect();
+    let results = check_all(
+        checks,
+        implementing_countries,
+        state,
+        state.config.upload_max_records,
+    )
+    .await?;
+    let summary = summarize(&results);
+    spawn_blocking(move || write_annotations(ids, results, summary))
+        .await
+        .map_err(|_| NagoyaError::GenericInternalServerError)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::tests::state;
+    use crate::country::CountryId;
+
+    const OCCURRENCE_META: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<archive xmlns="http://rs.tdwg.org/dwc/text/" metadata="eml.xml">
+  <core encoding="UTF-8" fieldsTerminatedBy="\t" linesTerminatedBy="\n" fieldsEnclosedBy="" ignoreHeaderLines="1" rowType="http://rs.tdwg.org/dwc/terms/Occurrence">
+    <files>
+      <location>occurrence.txt</location>
+    </files>
+    <id index="0" />
+    <field index="1" term="http://rs.tdwg.org/dwc/terms/countryCode"/>
+    <field index="2" term="http://rs.tdwg.org/dwc/terms/decimalLatitude"/>
+    <field index="3" term="http://rs.tdwg.org/dwc/terms/decimalLongitude"/>
+    <field term="http://rs.tdwg.org/dwc/terms/basisOfRecord" default="PreservedSpecimen"/>
+  </core>
+</archive>"#;
+
+    fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn unpack(file: Vec<u8>) -> (String, String) {
+        let mut archive = ZipArchive::new(Cursor::new(file)).unwrap();
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+        (read(ANNOTATIONS_FILE), read(SUMMARY_FILE))
+    }
+
+    #[test]
+    fn test_parse_meta() {
+        let files = parse_meta(OCCURRENCE_META).unwrap();
+        assert_eq!(files.len(), 1);
+        let core = &files[0];
+        assert_eq!(core.row_type, "Occurrence");
+        assert_eq!(core.locations, vec!["occurrence.txt"]);
+        let escaped = OCCURRENCE_META.replace("occurrence.txt", "r&amp;d occurrences.txt");
+        assert_eq!(
+            parse_meta(&escaped).unwrap()[0].locations,
+            vec!["r&d occurrences.txt"]
+        );
+        assert_eq!((core.delimiter, core.quote), (b'\t', None));
+        assert_eq!((core.ignore_header_lines, core.id), (1, Some(0)));
+        let row: Vec<String> = ["o1", "BR", "", ""].map(String::from).to_vec();
+        assert_eq!(core.value(&row, "countryCode"), Some(String::from("BR")));
+        assert_eq!(core.value(&row, "decimalLatitude"), None);
+        assert_eq!(
+            core.value(&row, "basisOfRecord"),
+            Some(String::from("PreservedSpecimen"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_occurrence_core() {
+        let implementing_countries = ImplementingCountries {
+            countries: ["BRA".parse::<CountryId>().unwrap()].into(),
+            ..Default::default()
+        };
+        let file = archive(&[
+            ("dataset/meta.xml", OCCURRENCE_META),
+            (
+                "dataset/occurrence.txt",
+                "id\tcountryCode\tdecimalLatitude\tdecimalLongitude\no1\tBR\t\t\no2\tDE\t\t\no3\t\t91\t0\n",
+            ),
+        ]);
+        let (annotations, summary) = unpack(
+            annotate_dwca(file, &implementing_countries, &state(10))
+                .await
+                .unwrap(),
+        );
+        let lines: Vec<&str> = annotations.lines().collect();
+        assert_eq!(
+            lines[0],
+            "id\tnagoya_check_result\tnagoya_country_code\tnagoya_reasons"
+        );
+        assert_eq!(lines[1], "o1\ttrue\tBR\tBR is party to the Nagoya Protocol");
+        assert_eq!(
+            lines[2],
+            "o2\tfalse\tDE\tDE is not party to the Nagoya Protocol"
+        );
+        assert!(lines[3].starts_with("o3\t\t\tInvalid coordinates"));
+        let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+        assert_eq!(summary["records"], 3);
+        assert_eq!(summary["failed"], 1);
+        assert_eq!(summary["affected_countries"], serde_json::json!(["BR"]));
+    }
+
+    #[tokio::test]
+    async fn test_event_core() {
+        let meta = r#"<archive xmlns="http://rs.tdwg.org/dwc/text/">
+  <extension fieldsTerminatedBy="," ignoreHeaderLines="1" rowType="http://rs.tdwg.org/dwc/terms/Occurrence">
+    <files><location>occurrence.csv</location></files>
+    <coreid index="0"/>
+    <field index="1" term="http://rs.tdwg.org/dwc/terms/occurrenceID"/>
+    <field index="2" term="http://rs.tdwg.org/dwc/terms/countryCode"/>
+  </extension>
+  <core fieldsTerminatedBy="," ignoreHeaderLines="1" rowType="http://rs.tdwg.org/dwc/terms/Event">
+    <files><location>event.csv</location></files>
+    <id index="0"/>
+    <field index="1" term="http://rs.tdwg.org/dwc/terms/countryCode"/>
+  </core>
+</archive>"#;
+        let file = archive(&[
+            ("meta.xml", meta),
+            ("event.csv", "id,countryCode\ne1,BR\ne2,\"PE\"\n"),
+            (
+                "occurrence.csv",
+                "eventID,occurrenceID,countryCode\ne1,o1,\ne1,o2,DE\ne2,o3,\ne3,o4,\n",
+            ),
+        ]);
+        let implementing_countries = ImplementingCountries {
+            countries: ["BRA".parse::<CountryId>().unwrap()].into(),
+            ..Default::default()
+        };
+        let (annotations, summary) = unpack(
+            annotate_dwca(file, &implementing_countries, &state(10))
+                .await
+                .unwrap(),
+        );
+        let country_codes: Vec<&str> = annotations
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(2).unwrap())
+            .collect();
+        assert_eq!(country_codes, vec!["BR", "DE", "PE", ""]);
+        let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+        assert_eq!(
+            (
+                &summary["records"],
+                &summary["positive"],
+                &summary["failed"]
+            ),
+            (
+                &serde_json::json!(4),
+                &serde_json::json!(1),
+                &serde_json::json!(1)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_malformed_archive() {
+        for file in [
+            b"not a zip".to_vec(),
+            archive(&[("occurrence.txt", "id\n")]),
+            archive(&[("meta.xml", OCCURRENCE_META)]),
+        ] {
+            assert!(matches!(
+                annotate_dwca(file, &ImplementingCountries::default(), &state(10)).await,
+                Err(NagoyaError::MalformedUpload { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_max_extracted_size() {
+        let file = archive(&[("meta.xml", OCCURRENCE_META), ("occurrence.txt", "id\n")]);
+        let mut archive = open(file.clone(), OCCURRENCE_META.len() as u64).unwrap();
+        assert!(archive.read("meta.xml").is_ok());
+        // The limit holds for the whole archive
+        assert!(matches!(
+            archive.read("occurrence.txt"),
+            Err(NagoyaError::MalformedUpload { .. })
+        ));
+        assert!(matches!(
+            open(file, 10).unwrap().read("meta.xml"),
+            Err(NagoyaError::MalformedUpload { .. })
+        ));
+    }
+}
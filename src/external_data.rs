@@ -2,12 +2,17 @@
 //
 // SPDX-License-Identifier: LGPL-3.0-or-later
 
+use crate::country::CountryId;
+use crate::country_names::CountryNames;
 use crate::models::{
-    Config, Coordinates, ImplementingCountries, NagoyaCountryInfo, NagoyaError, NominatimResponse,
+    Config, Coordinates, ImplementingCountries, NagoyaCountryInfo, NagoyaError, NominatimAddress,
+    NominatimResponse, ResolvedLocation,
 };
+use crate::rate_limit::{Queueing, RateLimiter};
 use reqwest::Client;
 use std::collections::HashSet;
 use std::error::Error;
+use std::time::Duration;
 use tracing::{Level, event, instrument, span};
 
 #[instrument]
@@ -27,52 +32,131 @@ async fn fetch_absch_treaty_info() -> Result<String, NagoyaError> {
         .map_err(|_| NagoyaError::UnparsableExternalResponse)
 }
 
-#[instrument]
+// Client shared by all requests to Nominatim. The usage policy of the public instance allows at
+// most one request per second, self-hosted instances need protection as well
+pub struct NominatimClient {
+    client: Client,
+    rate_limiter: RateLimiter,
+}
+
+impl NominatimClient {
+    pub fn new(requests_per_second: f64, queue_size: usize, timeout: Duration) -> Self {
+        const APP_USER_AGENT: &str =
+            concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
+        Self {
+            client: Client::builder()
+                .user_agent(APP_USER_AGENT) // API requires UA for interaction
+                .timeout(timeout)
+                .build()
+                .expect("Could not build HTTP client"),
+            rate_limiter: RateLimiter::new(requests_per_second, queue_size),
+        }
+    }
+}
+
+#[instrument(skip(nominatim))]
 pub async fn fetch_country_code_by_coordinates(
     config: &Config,
+    nominatim: &NominatimClient,
     coordinates: Coordinates,
+    queueing: Queueing,
     //) -> Result<String, Box<dyn Error + Send + Sync>> {
-) -> Result<String, NagoyaError> {
+) -> Result<ResolvedLocation, NagoyaError> {
     let span = span!(Level::DEBUG, "Resolving coordinates to country code");
     let _enter = span.enter();
+    let mut query = vec![
+        ("lat", coordinates.latitude.to_string()),
+        ("lon", coordinates.longitude.to_string()),
+        ("format", String::from("json")),
+    ];
+    // The public instance asks heavy users to identify themselves
+    if let Some(email) = &config.nominatim_email {
+        query.push(("email", email.clone()));
+    }
+    if let Some(accept_language) = &config.nominatim_accept_language {
+        query.push(("accept-language", accept_language.clone()));
+    }
     let request = format!(
-        "{host}{endpoint}?lat={lat}&lon={lon}&format=json",
+        "{host}{endpoint}",
         //env_map.get("NOMINATIM_HOST").unwrap(),
         host = config.nominatim_host,
         endpoint = "/reverse",
-        lat = coordinates.latitude,
-        lon = coordinates.longitude
     );
 
-    const APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
-    let client = Client::builder()
-        .user_agent(APP_USER_AGENT) // API requires UA for interaction
-        .build()
-        .map_err(|_| NagoyaError::GenericInternalServerError)?;
-    let nominatim_res = client
+    match queueing {
+        Queueing::Reject => nominatim.rate_limiter.acquire().await?,
+        Queueing::Wait => nominatim.rate_limiter.acquire_waiting().await,
+    }
+    // Only outages of Nominatim itself end up as 502/504, anything it answers is parsed below
+    let upstream_error = |error: reqwest::Error| {
+        event!(Level::WARN, "Nominatim request failed: {}", error);
+        if error.is_timeout() {
+            NagoyaError::ExternalResourceTimeout
+        } else {
+            NagoyaError::UnreachableExternalResource
+        }
+    };
+    let nominatim_res = nominatim
+        .client
         .get(request)
+        .query(&query)
         .send()
         .await
-        .map_err(|_| NagoyaError::UnreachableExternalResource)?
+        .map_err(upstream_error)?
+        .error_for_status()
+        .map_err(upstream_error)?
         .text()
         .await
-        .map_err(|_| NagoyaError::UnparsableExternalResponse)?;
-    let nominatim_json: NominatimResponse = serde_json::from_str(&nominatim_res)
-        .map_err(|_| NagoyaError::UnparsableExternalResponse)?;
-    // TODO: Handle for failed to parse
-    // If reverse lookup is not possible, nominatim returns {"error":"Unable to geocode"}
-    // with Status 200
-    // Is Unresolvable Coordinates
+        .map_err(upstream_error)?;
+    let location = parse_nominatim_response(&nominatim_res)?;
 
     event!(
         Level::DEBUG,
-        "Resolved {}, {} to \"{}\"",
+        "Resolved {}, {} to \"{}\" ({:?})",
         &coordinates.latitude,
         &coordinates.longitude,
-        &nominatim_json.address.country_code
+        &location.country_code,
+        &location.subdivision
     );
 
-    Ok(nominatim_json.address.country_code) // returns a code 2, needs to be converted to a code 3
+    Ok(location) // returns a code 2, needs to be converted to a code 3
+}
+
+// Extracts the country code from a reverse geocoding response. Both an explicit error and a place
+// without a country mean that the coordinates cannot be resolved to a country
+fn parse_nominatim_response(nominatim_res: &str) -> Result<ResolvedLocation, NagoyaError> {
+    match serde_json::from_str::<NominatimResponse>(nominatim_res) {
+        Ok(NominatimResponse::Place {
+            address:
+                NominatimAddress {
+                    country_code: Some(country_code),
+                    iso3166_2_lvl3,
+                    iso3166_2_lvl4,
+                    iso3166_2_lvl5,
+                    iso3166_2_lvl6,
+                },
+        }) => Ok(ResolvedLocation {
+            country_code,
+            // Prefer states and provinces, which is where subnational ABS regimes are found
+            subdivision: iso3166_2_lvl4
+                .or(iso3166_2_lvl3)
+                .or(iso3166_2_lvl5)
+                .or(iso3166_2_lvl6),
+        }),
+        Ok(NominatimResponse::Place { .. }) => {
+            event!(Level::DEBUG, "Nominatim returned a place without country");
+            Err(NagoyaError::UnresolvableCoordinates {
+                quality_flags: Vec::new(),
+            })
+        }
+        Ok(NominatimResponse::Error { error }) => {
+            event!(Level::DEBUG, "Nominatim could not geocode: {}", error);
+            Err(NagoyaError::UnresolvableCoordinates {
+                quality_flags: Vec::new(),
+            })
+        }
+        Err(_) => Err(NagoyaError::UnparsableExternalResponse),
+    }
 }
 
 #[instrument]
@@ -84,21 +168,41 @@ fn get_nagoya_treaty_info(absch_json: &str) -> Result<HashSet<NagoyaCountryInfo>
 #[instrument]
 pub async fn get_implementing_countries() -> Result<ImplementingCountries, Box<dyn Error>> {
     // TODO: Use Caching
-    // TODO: Instead of strings, use the data model provided by the iso3166 crate (or return a fitting error)
 
     // Get JSON from ABSCH (if not in cache; cache duration in config)
     let nagoya_country_info = get_nagoya_treaty_info(&fetch_absch_treaty_info().await?)?;
 
+    // Entries without an ISO 3166-1 code, i.e. the EU, are skipped, for the parties as well as
+    // for the names
+    let iso_entries: Vec<(CountryId, &NagoyaCountryInfo)> = nagoya_country_info
+        .iter()
+        .filter_map(|country| match country.code3.parse() {
+            Ok(country_id) => Some((country_id, country)),
+            Err(error) => {
+                event!(Level::INFO, "Skipping ABSCH entry: {}", error);
+                None
+            }
+        })
+        .collect();
+
     // Get List of implementing countries from the struct. Assumed that those are the countries which
     // are party to the contract
-    let code3_entries: Vec<String> = nagoya_country_info
+    let code3_entries: Vec<CountryId> = iso_entries
         .iter()
-        .filter(|country| country.treaties.nagoya.party_date.is_some())
-        .map(|country| country.code3.clone())
+        .filter(|(_, country)| country.treaties.nagoya.party_date.is_some())
+        .map(|(country_id, _)| *country_id)
         .collect();
 
+    let names = iso_entries.iter().flat_map(|(country_id, country)| {
+        country
+            .name
+            .values()
+            .map(|name| (name.clone(), country_id.alpha3().to_string()))
+    });
+
     let countries = ImplementingCountries {
         countries: HashSet::from_iter(code3_entries),
+        names: CountryNames::new(names),
     };
 
     Ok(countries)
@@ -109,6 +213,7 @@ pub async fn get_implementing_countries() -> Result<ImplementingCountries, Box<d
 mod tests {
     use super::*;
     use crate::models::{Treaties, Treaty};
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_get_nagoya_treaty_info() {
@@ -117,6 +222,7 @@ mod tests {
             {
                 "code2": "AD",
                 "code3": "AND",
+                "name": { "en": "Andorra", "es": "Andorra", "fr": "Andorre" },
                 "treaties": {
                     "XXVII8":   { "party": "2015-05-05" },
                     "XXVII8a":  { "party": null },
@@ -129,6 +235,11 @@ mod tests {
         let country_data = NagoyaCountryInfo {
             code3: String::from("AND"),
             code2: String::from("AD"),
+            name: BTreeMap::from([
+                (String::from("en"), String::from("Andorra")),
+                (String::from("es"), String::from("Andorra")),
+                (String::from("fr"), String::from("Andorre")),
+            ]),
             treaties: Treaties {
                 nagoya: Treaty {
                     party_date: Some(String::from("2025-10-12")),
@@ -140,4 +251,48 @@ mod tests {
             HashSet::from_iter(vec!(country_data))
         );
     }
+
+    #[test]
+    fn test_parse_nominatim_response() {
+        assert_eq!(
+            parse_nominatim_response(
+                r#"{"place_id": 1, "address": {"state": "Baden-Württemberg", "country_code": "de"}}"#
+            ),
+            Ok(ResolvedLocation {
+                country_code: String::from("de"),
+                subdivision: None,
+            })
+        );
+        assert_eq!(
+            parse_nominatim_response(
+                r#"{"place_id": 1, "address": {"state": "Queensland", "ISO3166-2-lvl4": "AU-QLD", "country_code": "au"}}"#
+            ),
+            Ok(ResolvedLocation {
+                country_code: String::from("au"),
+                subdivision: Some(String::from("AU-QLD")),
+            })
+        );
+        assert_eq!(
+            parse_nominatim_response(r#"{"error": "Unable to geocode"}"#),
+            Err(NagoyaError::UnresolvableCoordinates {
+                quality_flags: Vec::new(),
+            })
+        );
+        assert_eq!(
+            parse_nominatim_response(r#"{"place_id": 1, "address": {"ocean": "Atlantic Ocean"}}"#),
+            Err(NagoyaError::UnresolvableCoordinates {
+                quality_flags: Vec::new(),
+            })
+        );
+        assert_eq!(
+            parse_nominatim_response(r#"{"place_id": 1}"#),
+            Err(NagoyaError::UnresolvableCoordinates {
+                quality_flags: Vec::new(),
+            })
+        );
+        assert_eq!(
+            parse_nominatim_response("<html>Bad Gateway</html>"),
+            Err(NagoyaError::UnparsableExternalResponse)
+        );
+    }
 }
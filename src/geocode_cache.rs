@@ -0,0 +1,279 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::models::ResolvedLocation;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{Level, event, instrument};
+
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+// Caches reverse geocoding results per geohash cell, so repeated lookups for the same site do not
+// hit Nominatim again. Cells are cached at a fine precision, or at a coarse precision if the cell
+// is far enough from any border to be sure the whole cell lies within a single country. As the
+// distance to subdivision borders is unknown, coarse cells only hold the country
+pub struct GeocodeCache {
+    precision: usize,
+    coarse_precision: usize,
+    ttl: Duration,
+    capacity: usize,
+    cells: Mutex<CachedCells>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CachedCells {
+    entries: HashMap<String, CachedCell>,
+    // Insertion order for evicting the oldest cells first
+    #[serde(skip)]
+    order: VecDeque<(String, u64)>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedCell {
+    country_code: String,
+    #[serde(default)]
+    subdivision: Option<String>,
+    // Set on coarse cells of results with a subdivision, which is then only in the fine cell
+    #[serde(default)]
+    subdivision_omitted: bool,
+    // Seconds since the unix epoch, as the cache may be persisted across restarts
+    inserted: u64,
+}
+
+impl GeocodeCache {
+    pub fn new(precision: usize, coarse_precision: usize, ttl: Duration, capacity: usize) -> Self {
+        Self {
+            precision,
+            coarse_precision: coarse_precision.min(precision),
+            ttl,
+            capacity,
+            cells: Mutex::new(CachedCells::default()),
+        }
+    }
+
+    // Fills the cache with cells persisted by a previous run. Expired cells are dropped
+    #[instrument(skip(self))]
+    pub fn load(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut persisted: CachedCells = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let now = now();
+        persisted
+            .entries
+            .retain(|_, cell| now.saturating_sub(cell.inserted) <= self.ttl.as_secs());
+        let mut entries: Vec<(String, CachedCell)> = persisted.entries.into_iter().collect();
+        entries.sort_by_key(|(_, cell)| cell.inserted);
+        for (key, cell) in entries {
+            self.insert_cell(key, cell);
+        }
+        event!(
+            Level::INFO,
+            "Loaded {} cached cells",
+            self.cells.lock().unwrap().entries.len()
+        );
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub fn persist(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let serialized = serde_json::to_string(&*self.cells.lock().unwrap())?;
+        // Write to a temporary file first so a crash does not leave a truncated cache behind
+        let temporary_path = format!("{path}.tmp");
+        std::fs::write(&temporary_path, serialized)?;
+        std::fs::rename(temporary_path, path)?;
+        Ok(())
+    }
+
+    // Coarse cells without the subdivision count as a miss for countries whose subdivisions matter,
+    // e.g. those with subnational regimes
+    pub fn get(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        subdivision_needed: impl Fn(&str) -> bool,
+    ) -> Option<ResolvedLocation> {
+        let now = now();
+        let cells = self.cells.lock().unwrap();
+        [self.precision, self.coarse_precision]
+            .iter()
+            .filter_map(|precision| cells.entries.get(&geohash(latitude, longitude, *precision)))
+            .filter(|cell| now.saturating_sub(cell.inserted) <= self.ttl.as_secs())
+            .find(|cell| !cell.subdivision_omitted || !subdivision_needed(&cell.country_code))
+            .map(|cell| ResolvedLocation {
+                country_code: cell.country_code.clone(),
+                subdivision: cell.subdivision.clone(),
+            })
+    }
+
+    // The distance to the nearest border decides on the cell size. Without it, the result is
+    // cached at the fine precision. A result with a subdivision far from a border is cached twice,
+    // the country in the coarse cell and the subdivision in the fine one
+    pub fn insert(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        location: &ResolvedLocation,
+        border_distance: Option<f64>,
+    ) {
+        let cell = CachedCell {
+            country_code: location.country_code.clone(),
+            subdivision: location.subdivision.clone(),
+            subdivision_omitted: false,
+            inserted: now(),
+        };
+        match border_distance {
+            Some(distance) if distance > cell_diagonal(latitude, self.coarse_precision) => {
+                self.insert_cell(
+                    geohash(latitude, longitude, self.coarse_precision),
+                    CachedCell {
+                        subdivision: None,
+                        subdivision_omitted: location.subdivision.is_some(),
+                        ..cell.clone()
+                    },
+                );
+                if location.subdivision.is_some() {
+                    self.insert_cell(geohash(latitude, longitude, self.precision), cell);
+                }
+            }
+            Some(distance) if distance <= cell_diagonal(latitude, self.precision) => {
+                // The cell might contain a border, caching it would return a wrong country for
+                // parts of it
+            }
+            _ => self.insert_cell(geohash(latitude, longitude, self.precision), cell),
+        }
+    }
+
+    fn insert_cell(&self, key: String, cell: CachedCell) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut cells = self.cells.lock().unwrap();
+        let now = now();
+        // Drop expired and superseded cells from the front, then the oldest ones if still full
+        while let Some((key, inserted)) = cells.order.front().cloned() {
+            let expired = now.saturating_sub(inserted) > self.ttl.as_secs();
+            let current = cells
+                .entries
+                .get(&key)
+                .is_some_and(|cell| cell.inserted == inserted);
+            if current && !expired && cells.entries.len() < self.capacity {
+                break;
+            }
+            cells.order.pop_front();
+            if current {
+                cells.entries.remove(&key);
+            }
+        }
+        cells.order.push_back((key.clone(), cell.inserted));
+        cells.entries.insert(key, cell);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Standard geohash, interleaving longitude and latitude bits starting with longitude
+pub fn geohash(latitude: f64, longitude: f64, precision: usize) -> String {
+    let (mut latitude_range, mut longitude_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = String::with_capacity(precision);
+    let mut even_bit = true;
+    for _ in 0..precision {
+        let mut index = 0;
+        for _ in 0..5 {
+            let (range, value) = if even_bit {
+                (&mut longitude_range, longitude)
+            } else {
+                (&mut latitude_range, latitude)
+            };
+            let middle = (range.0 + range.1) / 2.0;
+            index <<= 1;
+            if value >= middle {
+                index |= 1;
+                range.0 = middle;
+            } else {
+                range.1 = middle;
+            }
+            even_bit = !even_bit;
+        }
+        hash.push(GEOHASH_ALPHABET[index] as char);
+    }
+    hash
+}
+
+// Approximate diagonal of a geohash cell in meters
+fn cell_diagonal(latitude: f64, precision: usize) -> f64 {
+    let bits = 5 * precision as i32;
+    let longitude_bits = (bits + 1) / 2;
+    let latitude_bits = bits / 2;
+    let height = 180.0 / 2f64.powi(latitude_bits) * METERS_PER_DEGREE;
+    let width = 360.0 / 2f64.powi(longitude_bits) * METERS_PER_DEGREE * latitude.to_radians().cos();
+    height.hypot(width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(country_code: &str) -> ResolvedLocation {
+        ResolvedLocation {
+            country_code: country_code.to_string(),
+            subdivision: None,
+        }
+    }
+
+    #[test]
+    fn test_geohash() {
+        // Reference values from geohash.org
+        assert_eq!(geohash(57.64911, 10.40744, 11), "u4pruydqqvj");
+        assert_eq!(geohash(-25.382708, -49.265506, 6), "6gkzwg");
+    }
+
+    #[test]
+    fn test_cell_size_depends_on_border_distance() {
+        let cache = GeocodeCache::new(7, 4, Duration::from_secs(60), 10);
+        // Far from any border, the coarse cell covers nearby points as well
+        cache.insert(49.0135, 8.4044, &location("de"), Some(50_000.0));
+        assert_eq!(cache.get(49.02, 8.41, |_| false), Some(location("de")));
+
+        // Close to a border only the fine cell is cached
+        cache.insert(47.5596, 7.5886, &location("ch"), Some(300.0));
+        assert_eq!(cache.get(47.5596, 7.5886, |_| false), Some(location("ch")));
+        assert_eq!(cache.get(47.57, 7.6, |_| false), None);
+
+        // Within a fine cell of the border nothing is cached at all
+        cache.insert(47.5615, 7.5935, &location("ch"), Some(50.0));
+        assert_eq!(cache.get(47.5615, 7.5935, |_| false), None);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let cache = GeocodeCache::new(7, 7, Duration::from_secs(60), 2);
+        cache.insert(1.0, 1.0, &location("aa"), None);
+        cache.insert(2.0, 2.0, &location("bb"), None);
+        cache.insert(3.0, 3.0, &location("cc"), None);
+        assert_eq!(cache.get(1.0, 1.0, |_| false), None);
+        assert_eq!(cache.get(2.0, 2.0, |_| false), Some(location("bb")));
+        assert_eq!(cache.get(3.0, 3.0, |_| false), Some(location("cc")));
+    }
+
+    #[test]
+    fn test_subdivision_cached_at_fine_precision() {
+        let cache = GeocodeCache::new(7, 4, Duration::from_secs(60), 10);
+        let queensland = ResolvedLocation {
+            country_code: String::from("au"),
+            subdivision: Some(String::from("AU-QLD")),
+        };
+        cache.insert(-27.4698, 153.0251, &queensland, Some(50_000.0));
+        assert_eq!(cache.get(-27.4698, 153.0251, |_| true), Some(queensland));
+        // Nearby only the country is known, which is enough unless its subdivisions matter
+        assert_eq!(cache.get(-27.48, 153.03, |_| false), Some(location("au")));
+        assert_eq!(cache.get(-27.48, 153.03, |country| country == "au"), None);
+    }
+}
@@ -0,0 +1,250 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::boundaries::{PolygonFeature, polygon_features};
+use crate::models::{HistoricalSovereign, NagoyaError};
+use std::error::Error;
+use tracing::{Level, event, instrument};
+
+// Calendar date as (year, month, day), ordered chronologically
+pub type Date = (i32, u32, u32);
+
+// Historical state boundaries loaded from a local GeoJSON file, e.g. CShapes. Every feature is a
+// state's territory during a period, given either as CShapes properties (`cntry_name`, `gwcode`,
+// `gwsyear`, `gwsmonth`, `gwsday`, `gweyear`, ...) or as `name`, `code`, `start` and `end` dates
+pub struct HistoricalBorders {
+    periods: Vec<TerritoryPeriod>,
+}
+
+struct TerritoryPeriod {
+    name: String,
+    code: Option<String>,
+    start: Date,
+    end: Date,
+    feature: PolygonFeature,
+}
+
+impl HistoricalBorders {
+    #[instrument]
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_geojson(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn from_geojson(geojson: &str) -> Result<Self, Box<dyn Error>> {
+        let mut periods = Vec::new();
+        for feature in polygon_features(geojson)? {
+            let Some(name) = ["cntry_name", "name"]
+                .iter()
+                .find_map(|key| feature.property(key))
+                .map(str::to_string)
+            else {
+                continue;
+            };
+            let code =
+                ["code", "gwcode"]
+                    .iter()
+                    .find_map(|key| match feature.properties.get(*key)? {
+                        serde_json::Value::String(code) => Some(code.clone()),
+                        serde_json::Value::Number(code) => Some(code.to_string()),
+                        _ => None,
+                    });
+            let (Some(start), Some(end)) = (
+                period_bound(&feature, "start", "gws"),
+                period_bound(&feature, "end", "gwe"),
+            ) else {
+                return Err(format!("Missing period of historical territory \"{name}\"").into());
+            };
+            periods.push(TerritoryPeriod {
+                name,
+                code,
+                start,
+                end,
+                feature,
+            });
+        }
+        event!(
+            Level::INFO,
+            "Loaded {} historical territories",
+            periods.len()
+        );
+        Ok(Self { periods })
+    }
+
+    // States the location belonged to at some point within the period of the event
+    pub fn sovereigns_at(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        (start, end): (Date, Date),
+    ) -> Vec<HistoricalSovereign> {
+        let mut sovereigns: Vec<HistoricalSovereign> = self
+            .periods
+            .iter()
+            .filter(|period| period.start <= end && start <= period.end)
+            .filter(|period| period.feature.contains(latitude, longitude))
+            .map(|period| HistoricalSovereign {
+                name: period.name.clone(),
+                code: period.code.clone(),
+                valid_from: format_date(period.start),
+                valid_until: format_date(period.end),
+            })
+            .collect();
+        sovereigns.sort_by(|a, b| a.valid_from.cmp(&b.valid_from));
+        sovereigns
+    }
+}
+
+// Reads a period bound from an ISO date property or from CShapes' year/month/day properties
+fn period_bound(feature: &PolygonFeature, key: &str, cshapes_prefix: &str) -> Option<Date> {
+    if let Some(date) = feature.property(key) {
+        // A partial start date covers its first day, a partial end date its last one
+        return parse_event_date(date)
+            .ok()
+            .map(|(first, last)| if key == "start" { first } else { last });
+    }
+    let part = |suffix: &str| {
+        feature
+            .properties
+            .get(&format!("{cshapes_prefix}{suffix}"))?
+            .as_i64()
+    };
+    Some((
+        part("year")? as i32,
+        part("month")? as u32,
+        part("day")? as u32,
+    ))
+}
+
+fn format_date((year, month, day): Date) -> String {
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+// Parses a Darwin Core style event date into the first and last day it covers. Supports full
+// dates, months and years as well as intervals of those, e.g. "1985", "1985-06" or
+// "1991-06-01/1991-07-15"
+pub fn parse_event_date(event_date: &str) -> Result<(Date, Date), NagoyaError> {
+    let invalid = || NagoyaError::InvalidEventDate {
+        event_date: event_date.to_string(),
+    };
+    let (start, end) = event_date
+        .trim()
+        .split_once('/')
+        .unwrap_or((event_date.trim(), event_date.trim()));
+    let start = parse_partial_date(start).ok_or_else(invalid)?.0;
+    let end = parse_partial_date(end).ok_or_else(invalid)?.1;
+    if start > end {
+        return Err(invalid());
+    }
+    Ok((start, end))
+}
+
+// First and last day of a full or partial date, times are ignored
+fn parse_partial_date(date: &str) -> Option<(Date, Date)> {
+    let date = date.split_once('T').map_or(date, |(date, _)| date);
+    let mut parts = date.split('-');
+    let year = parts.next()?.parse::<i32>().ok()?;
+    let month = parts.next().map(str::parse::<u32>).transpose().ok()?;
+    let day = parts.next().map(str::parse::<u32>).transpose().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    match (month, day) {
+        (None, None) => Some(((year, 1, 1), (year, 12, 31))),
+        (Some(month @ 1..=12), None) => {
+            Some(((year, month, 1), (year, month, days_in_month(year, month))))
+        }
+        (Some(month @ 1..=12), Some(day)) if day >= 1 && day <= days_in_month(year, month) => {
+            Some(((year, month, day), (year, month, day)))
+        }
+        _ => None,
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Yugoslavia until Slovenia's independence in 1991, Slovenia afterwards
+    const TESTDATA: &str = r#"
+    {
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "properties": {
+                    "cntry_name": "Yugoslavia", "gwcode": 345,
+                    "gwsyear": 1946, "gwsmonth": 1, "gwsday": 1,
+                    "gweyear": 1991, "gwemonth": 6, "gweday": 24
+                },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[13, 45], [17, 45], [17, 47], [13, 47], [13, 45]]]
+                }
+            },
+            {
+                "type": "Feature",
+                "properties": {
+                    "name": "Slovenia", "code": "SI", "start": "1991-06-25", "end": "2019-12-31"
+                },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[13, 45], [17, 45], [17, 47], [13, 47], [13, 45]]]
+                }
+            }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn test_parse_event_date() {
+        assert_eq!(parse_event_date("1985"), Ok(((1985, 1, 1), (1985, 12, 31))));
+        assert_eq!(
+            parse_event_date("1984-02"),
+            Ok(((1984, 2, 1), (1984, 2, 29)))
+        );
+        assert_eq!(
+            parse_event_date("1991-06-01T10:00/1991-07"),
+            Ok(((1991, 6, 1), (1991, 7, 31)))
+        );
+        assert!(parse_event_date("1991-13").is_err());
+        assert!(parse_event_date("1991-02-30").is_err());
+        assert!(parse_event_date("1992/1991").is_err());
+        assert!(parse_event_date("last summer").is_err());
+    }
+
+    #[test]
+    fn test_sovereigns_at() {
+        let borders = HistoricalBorders::from_geojson(TESTDATA).unwrap();
+        let sovereigns = borders.sovereigns_at(46.05, 14.5, parse_event_date("1985").unwrap());
+        assert_eq!(sovereigns.len(), 1);
+        assert_eq!(sovereigns[0].name, "Yugoslavia");
+        assert_eq!(sovereigns[0].code, Some(String::from("345")));
+        assert_eq!(sovereigns[0].valid_until, "1991-06-24");
+
+        // The year of independence overlaps both states
+        let sovereigns = borders.sovereigns_at(46.05, 14.5, parse_event_date("1991").unwrap());
+        assert_eq!(
+            sovereigns
+                .iter()
+                .map(|sovereign| sovereign.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Yugoslavia", "Slovenia"]
+        );
+
+        assert!(
+            borders
+                .sovereigns_at(0.0, 0.0, parse_event_date("1985").unwrap())
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,363 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::batch::{Check, check_all, summarize};
+use crate::country_names::CountryNames;
+use crate::models::{
+    AppState, CoordinateInput, Coordinates, CountryInput, ImplementingCountries, InsdcQualifiers,
+    NagoyaCheckDataCC, NagoyaCheckDataGeo, NagoyaError, ParsedQualifiers, RecordReport,
+    RecordResult,
+};
+use crate::normalization::normalize_country;
+use tracing::{Level, event, instrument};
+
+// Missing values of the INSDC vocabulary. Values starting with "missing:" give the reason, e.g.
+// "missing: control sample"
+const MISSING_VALUES: [&str; 5] = [
+    "missing",
+    "not applicable",
+    "not collected",
+    "not provided",
+    "restricted access",
+];
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+// Value of a qualifier given bare or as in the flat file, e.g. /lat_lon="3.10 S 60.02 W"
+pub fn qualifier_value(name: &str, raw: &str) -> Option<String> {
+    let value = raw.trim();
+    let value = value
+        .strip_prefix('/')
+        .and_then(|value| value.strip_prefix(name))
+        .and_then(|value| value.strip_prefix('='))
+        .unwrap_or(value)
+        .trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+        // Quotes within values are doubled in flat files
+        .replace("\"\"", "\"");
+    Some(value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+// Sets a qualifier by its name, also with hyphens as in FASTA modifiers. The first value given wins
+pub fn set_qualifier(qualifiers: &mut InsdcQualifiers, name: &str, value: String) {
+    let field = match name.to_lowercase().replace('-', "_").as_str() {
+        "country" => &mut qualifiers.country,
+        "geo_loc_name" => &mut qualifiers.geo_loc_name,
+        "lat_lon" => &mut qualifiers.lat_lon,
+        "collection_date" => &mut qualifiers.collection_date,
+        _ => return,
+    };
+    if field.is_none() {
+        *field = Some(value);
+    }
+}
+
+fn is_missing(value: &str) -> bool {
+    let value = value.to_lowercase();
+    let term = value.split(':').next().unwrap_or_default().trim();
+    MISSING_VALUES.contains(&term)
+}
+
+// Parses the INSDC lat_lon format "d[d.dddd] N|S d[dd.dddd] W|E"
+pub fn parse_lat_lon(lat_lon: &str) -> Result<Coordinates, NagoyaError> {
+    let invalid = || NagoyaError::InvalidCoordinates {
+        reason: format!("lat_lon \"{lat_lon}\" is not of the form \"d.dd N|S d.dd W|E\""),
+    };
+    let parts: Vec<&str> = lat_lon.split_whitespace().collect();
+    let [latitude, north_south, longitude, east_west] = parts[..] else {
+        return Err(invalid());
+    };
+    let latitude = latitude.parse::<f64>().map_err(|_| invalid())?;
+    let longitude = longitude.parse::<f64>().map_err(|_| invalid())?;
+    let latitude = match north_south.to_uppercase().as_str() {
+        "N" => latitude,
+        "S" => -latitude,
+        _ => return Err(invalid()),
+    };
+    let longitude = match east_west.to_uppercase().as_str() {
+        "E" => longitude,
+        "W" => -longitude,
+        _ => return Err(invalid()),
+    };
+    Ok(Coordinates {
+        latitude,
+        longitude,
+        uncertainty_in_meters: None,
+    })
+}
+
+// Converts the INSDC collection date formats to ISO 8601, e.g. "21-Oct-1952" to "1952-10-21" and
+// "Oct-1952" to "1952-10". Times are dropped and intervals converted part by part; anything else is
+// passed on as is, to be rejected by the check if invalid
+pub fn collection_date(value: &str) -> String {
+    value
+        .split('/')
+        .map(|date| {
+            let date = date.trim();
+            let date = date.split('T').next().unwrap_or(date);
+            let month = |name: &str| {
+                MONTHS
+                    .iter()
+                    .position(|month| name.eq_ignore_ascii_case(month))
+                    .map(|position| position + 1)
+            };
+            match date.split('-').collect::<Vec<_>>()[..] {
+                [day, name, year] if month(name).is_some() => {
+                    format!("{year}-{:02}-{day:0>2}", month(name).unwrap_or_default())
+                }
+                [name, year] if month(name).is_some() => {
+                    format!("{year}-{:02}", month(name).unwrap_or_default())
+                }
+                _ => date.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Parses the qualifiers into their components and the check to run. Records with coordinates are
+// checked by location, with the country cross-checked as the declared one, so a country not
+// mapping to a single one, e.g. an ocean, only fails records without coordinates
+pub fn parse_qualifiers(
+    qualifiers: &InsdcQualifiers,
+    names: &CountryNames,
+) -> Result<(ParsedQualifiers, Check), NagoyaError> {
+    let mut parsed = ParsedQualifiers::default();
+    let mut value = |name: &str, raw: &Option<String>| {
+        let value = raw.as_deref().and_then(|raw| qualifier_value(name, raw))?;
+        if is_missing(&value) {
+            parsed.missing_values.push(format!("{name}: {value}"));
+            return None;
+        }
+        Some(value)
+    };
+    let geo_loc_name = value("geo_loc_name", &qualifiers.geo_loc_name)
+        .or_else(|| value("country", &qualifiers.country));
+    let lat_lon = value("lat_lon", &qualifiers.lat_lon);
+    let date = value("collection_date", &qualifiers.collection_date);
+
+    parsed.collection_date = date.as_deref().map(collection_date);
+    parsed.coordinates = lat_lon.as_deref().map(parse_lat_lon).transpose()?;
+    let mut country_error = None;
+    if let Some(geo_loc_name) = geo_loc_name {
+        let (country, locality) = geo_loc_name
+            .split_once(':')
+            .map_or((geo_loc_name.as_str(), None), |(country, locality)| {
+                (country, Some(locality))
+            });
+        parsed.country = Some(country.trim().to_string());
+        parsed.locality = locality
+            .map(|locality| locality.trim().to_string())
+            .filter(|locality| !locality.is_empty());
+        match normalize_country(&geo_loc_name, names) {
+            Ok(normalized) => {
+                parsed.notes = normalized.notes;
+                match normalized.country {
+                    Some(country) => parsed.country_code = Some(country.alpha2),
+                    None => {
+                        country_error = Some(NagoyaError::NotACountry {
+                            input: geo_loc_name.clone(),
+                            reason: format!(
+                                "May refer to {}, give lat_lon to decide",
+                                normalized
+                                    .candidates
+                                    .iter()
+                                    .map(|candidate| candidate.alpha2.as_str())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ),
+                        })
+                    }
+                }
+            }
+            Err(error) => {
+                parsed.notes.push(error.to_string());
+                country_error = Some(error);
+            }
+        }
+    }
+
+    let check = match &parsed.coordinates {
+        Some(coordinates) => Check::Geo(NagoyaCheckDataGeo {
+            coordinates: CoordinateInput::Decimal(coordinates.clone()),
+            epsg: None,
+            event_date: parsed.collection_date.clone(),
+            declared_country: parsed
+                .country_code
+                .clone()
+                .map(CountryInput::try_from)
+                .transpose()?,
+            consensus: false,
+            require_agreement: None,
+        }),
+        None => match (&parsed.country_code, country_error) {
+            (Some(country_code), _) => Check::Country(NagoyaCheckDataCC {
+                probe_country: Some(CountryInput::try_from(country_code.clone())?),
+                countries: Vec::new(),
+            }),
+            (None, Some(error)) => return Err(error),
+            (None, None) => return Err(NagoyaError::MissingCountry),
+        },
+    };
+    Ok((parsed, check))
+}
+
+// Checks the qualifiers of every record of a file and reports the results by the id of the record,
+// e.g. its accession
+#[instrument(skip_all, fields(records = records.len()))]
+pub async fn check_records(
+    records: Vec<(String, InsdcQualifiers)>,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+) -> Result<RecordReport, NagoyaError> {
+    let mut ids = Vec::new();
+    let mut qualifiers = Vec::new();
+    let mut checks = Vec::new();
+    for (id, record) in records {
+        let (parsed, check) = match parse_qualifiers(&record, &implementing_countries.names) {
+            Ok((parsed, check)) => (Some(parsed), Ok(check)),
+            Err(error) => (None, Err(error)),
+        };
+        ids.push(id);
+        qualifiers.push(parsed);
+        checks.push(check);
+    }
+    let results = check_all(
+        checks,
+        implementing_countries,
+        state,
+        state.config.upload_max_records,
+    )
+    .await?;
+    let summary = summarize(&results);
+    let results = ids
+        .into_iter()
+        .zip(qualifiers)
+        .zip(results)
+        .map(|((id, qualifiers), result)| match result {
+            Ok(response) => RecordResult {
+                id,
+                qualifiers,
+                result: Some(response),
+                error: None,
+            },
+            Err(error) => {
+                event!(Level::DEBUG, "Record \"{}\" failed: {}", &id, &error);
+                RecordResult {
+                    id,
+                    qualifiers,
+                    result: None,
+                    error: Some(error.error_response()),
+                }
+            }
+        })
+        .collect();
+    Ok(RecordReport { results, summary })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qualifier_value() {
+        assert_eq!(
+            qualifier_value("geo_loc_name", "/geo_loc_name=\"Brazil: Amazonas, Manaus\""),
+            Some(String::from("Brazil: Amazonas, Manaus"))
+        );
+        assert_eq!(
+            qualifier_value("lat_lon", " 3.10 S 60.02 W "),
+            Some(String::from("3.10 S 60.02 W"))
+        );
+        assert_eq!(qualifier_value("country", "\"\""), None);
+        assert!(is_missing("missing: control sample"));
+        assert!(is_missing("Not Collected"));
+        assert!(!is_missing("Brazil"));
+    }
+
+    #[test]
+    fn test_parse_lat_lon() {
+        let coordinates = parse_lat_lon("3.10 S 60.02 W").unwrap();
+        assert_eq!(
+            (coordinates.latitude, coordinates.longitude),
+            (-3.10, -60.02)
+        );
+        assert!(matches!(
+            parse_lat_lon("3.10, -60.02"),
+            Err(NagoyaError::InvalidCoordinates { .. })
+        ));
+        assert!(parse_lat_lon("3.10 E 60.02 N").is_err());
+    }
+
+    #[test]
+    fn test_collection_date() {
+        assert_eq!(collection_date("21-Oct-1952"), "1952-10-21");
+        assert_eq!(collection_date("1-oct-1952"), "1952-10-01");
+        assert_eq!(collection_date("Oct-1952"), "1952-10");
+        assert_eq!(collection_date("1952"), "1952");
+        assert_eq!(
+            collection_date("2015-10-11T17:53:03Z/2015-10-12"),
+            "2015-10-11/2015-10-12"
+        );
+    }
+
+    #[test]
+    fn test_parse_qualifiers() {
+        let names = CountryNames::default();
+        let (parsed, check) = parse_qualifiers(
+            &InsdcQualifiers {
+                geo_loc_name: Some(String::from("/geo_loc_name=\"Brazil: Amazonas, Manaus\"")),
+                collection_date: Some(String::from("missing: control sample")),
+                ..Default::default()
+            },
+            &names,
+        )
+        .unwrap();
+        assert_eq!(parsed.country.as_deref(), Some("Brazil"));
+        assert_eq!(parsed.locality.as_deref(), Some("Amazonas, Manaus"));
+        assert_eq!(parsed.country_code.as_deref(), Some("BR"));
+        assert_eq!(
+            parsed.missing_values,
+            vec!["collection_date: missing: control sample"]
+        );
+        assert!(matches!(check, Check::Country(_)));
+
+        // An ocean fails on its own, but not with coordinates
+        let ocean = |lat_lon: Option<&str>| {
+            parse_qualifiers(
+                &InsdcQualifiers {
+                    country: Some(String::from("Atlantic Ocean")),
+                    lat_lon: lat_lon.map(String::from),
+                    ..Default::default()
+                },
+                &names,
+            )
+        };
+        assert!(matches!(ocean(None), Err(NagoyaError::NotACountry { .. })));
+        let (parsed, check) = ocean(Some("10.5 N 30 W")).unwrap();
+        assert_eq!(parsed.country_code, None);
+        assert_eq!(parsed.notes.len(), 1);
+        assert!(matches!(check, Check::Geo(_)));
+
+        assert!(matches!(
+            parse_qualifiers(
+                &InsdcQualifiers {
+                    geo_loc_name: Some(String::from("Borneo")),
+                    ..Default::default()
+                },
+                &names
+            ),
+            Err(NagoyaError::NotACountry { .. })
+        ));
+        assert!(matches!(
+            parse_qualifiers(&InsdcQualifiers::default(), &names),
+            Err(NagoyaError::MissingCountry)
+        ));
+    }
+}
@@ -2,26 +2,76 @@
 //
 // SPDX-License-Identifier: LGPL-3.0-or-later
 
-use crate::models::{AppState, Config, ImplementingCountries};
+use crate::boundaries::CountryBoundaries;
+use crate::consensus::GeocoderBackend;
+use crate::coordinate_quality::Capitals;
+use crate::country_names::CountryNames;
+use crate::disputed_areas::{DisputedAreaPolicy, DisputedAreas};
+use crate::external_data::NominatimClient;
+use crate::geocode_cache::GeocodeCache;
+use crate::historical_borders::HistoricalBorders;
+use crate::models::{AppState, Config, GeoLayers, ImplementingCountries};
+use crate::overlays::OverlayLayers;
+use crate::subnational_regimes::SubnationalRegimes;
 use axum::Router;
+use axum::extract::DefaultBodyLimit;
 use axum::routing::{get, post};
+use std::sync::Arc;
 use std::time::Duration;
 use tower_http::trace::TraceLayer;
 use tracing::{Level, event};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
+mod abcd;
 mod api;
+mod batch;
+mod batch_jobs;
+mod boundaries;
+mod consensus;
+mod coordinate_notation;
+mod coordinate_quality;
+mod country;
+mod country_names;
+mod csv_upload;
+mod dataset_metadata;
+mod disputed_areas;
+mod dwca;
 mod external_data;
+mod geocode_cache;
+mod historical_borders;
+mod insdc;
+mod metrics;
 mod models;
 mod nagoya_check;
+mod normalization;
+mod overlays;
+mod rate_limit;
+mod reprojection;
+mod sample_metadata;
+mod sequence_file;
+mod subnational_regimes;
+mod upload;
+mod xml;
 
 #[derive(OpenApi)]
 #[openapi(paths(
     api::openapi,
     api::nagoya_check_country_code,
     api::nagoya_check_geocoordinates,
-    api::health_check
+    api::nagoya_check_batch,
+    api::submit_batch_job,
+    api::batch_job,
+    api::nagoya_check_csv,
+    api::nagoya_check_dwca,
+    api::nagoya_check_abcd,
+    api::nagoya_check_insdc,
+    api::nagoya_check_sequences,
+    api::nagoya_check_samples,
+    api::nagoya_check_dataset,
+    api::normalize,
+    api::health_check,
+    api::metrics
 ))]
 pub struct ApiDoc;
 
@@ -34,8 +84,14 @@ async fn main() {
     // Without the data the service cannot work, thus the panic is justified if the data
     // cannot be fetched
     // TODO: Use timestamped struct for caching
-    let implementing_countries: ImplementingCountries =
+    let mut implementing_countries: ImplementingCountries =
         external_data::get_implementing_countries().await.unwrap();
+    // Aliases complement the country names of the ABSCH, e.g. "Ivory Coast" or "Rep. of Congo"
+    if let Ok(path) = dotenvy::var("COUNTRY_ALIASES") {
+        implementing_countries.names.set_aliases(
+            CountryNames::load_aliases(&path).expect("Could not load country aliases"),
+        );
+    }
 
     let server_address = dotenvy::var("SERVER_HOST").unwrap_or("0.0.0.0".to_string());
     let server_port = dotenvy::var("SERVER_PORT")
@@ -43,11 +99,53 @@ async fn main() {
         .parse::<u16>()
         .expect("Please select a valid port number of between 0 and 65535");
 
-    let config = Config {
+    let mut config = Config {
         nominatim_host: dotenvy::var("NOMINATIM_HOST")
             // A custom host should be provided to not hog the service provided by OSM
             .expect("Please provide a Nominatim Host")
             .to_string(),
+        nominatim_email: dotenvy::var("NOMINATIM_EMAIL").ok(),
+        nominatim_accept_language: dotenvy::var("NOMINATIM_ACCEPT_LANGUAGE").ok(),
+        declared_country_tolerance: dotenvy::var("DECLARED_COUNTRY_TOLERANCE")
+            .unwrap_or("5000".to_string())
+            .parse::<f64>()
+            .expect("Could not parse declared country tolerance to f64"),
+        consensus_geocoders: dotenvy::var("CONSENSUS_GEOCODERS")
+            .unwrap_or("nominatim,boundaries".to_string())
+            .split(',')
+            .map(|backend| backend.parse::<GeocoderBackend>())
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Please select known geocoder backends for consensus mode"),
+        consensus_require_agreement: dotenvy::var("CONSENSUS_REQUIRE_AGREEMENT")
+            .unwrap_or("false".to_string())
+            .parse::<bool>()
+            .expect("Could not parse consensus agreement requirement to bool"),
+        batch_concurrency: dotenvy::var("BATCH_CONCURRENCY")
+            .unwrap_or("4".to_string())
+            .parse::<usize>()
+            .expect("Could not parse batch concurrency to usize"),
+        batch_max_items: dotenvy::var("BATCH_MAX_ITEMS")
+            .unwrap_or("1000".to_string())
+            .parse::<usize>()
+            .expect("Could not parse maximum batch size to usize"),
+        batch_job_max_items: dotenvy::var("BATCH_JOB_MAX_ITEMS")
+            .unwrap_or("100000".to_string())
+            .parse::<usize>()
+            .expect("Could not parse maximum batch job size to usize"),
+        batch_job_retention: Duration::from_secs(
+            dotenvy::var("BATCH_JOB_RETENTION")
+                .unwrap_or("86400".to_string())
+                .parse::<u64>()
+                .expect("Could not parse batch job retention to u64"),
+        ),
+        upload_max_records: dotenvy::var("UPLOAD_MAX_RECORDS")
+            .unwrap_or("100000".to_string())
+            .parse::<usize>()
+            .expect("Could not parse maximum records per upload to usize"),
+        max_extracted_size: dotenvy::var("MAX_EXTRACTED_SIZE")
+            .unwrap_or("268435456".to_string())
+            .parse::<u64>()
+            .expect("Could not parse maximum extracted size to u64"),
         server_host: server_address.to_string(),
         server_port,
     };
@@ -64,6 +162,107 @@ async fn main() {
         _ => Level::INFO,
     };
 
+    // Country boundaries are optional; without them, uncertainty radii and border distances are
+    // not evaluated
+    let boundaries = dotenvy::var("COUNTRY_BOUNDARIES").ok().map(|path| {
+        CountryBoundaries::from_file(&path).expect("Could not load country boundaries")
+    });
+    // The offline geocoder needs the local boundaries, without them it would always disagree.
+    // Consensus requests are rejected while fewer than two geocoders remain
+    let boundaries_geocoder_dropped = boundaries.is_none()
+        && config
+            .consensus_geocoders
+            .contains(&GeocoderBackend::Boundaries);
+    if boundaries_geocoder_dropped {
+        config
+            .consensus_geocoders
+            .retain(|backend| *backend != GeocoderBackend::Boundaries);
+    }
+
+    let capitals = dotenvy::var("CAPITALS")
+        .ok()
+        .map(|path| Capitals::from_file(&path).expect("Could not load capitals"));
+
+    // Reverse geocoding results are cached per geohash cell. The defaults (~150 m fine cells,
+    // ~5 km coarse cells) keep neighbouring sampling sites from hitting Nominatim repeatedly
+    let geocode_cache = Arc::new(GeocodeCache::new(
+        dotenvy::var("GEOCODE_CACHE_PRECISION")
+            .unwrap_or("7".to_string())
+            .parse::<usize>()
+            .expect("Could not parse geocode cache precision to usize"),
+        dotenvy::var("GEOCODE_CACHE_COARSE_PRECISION")
+            .unwrap_or("5".to_string())
+            .parse::<usize>()
+            .expect("Could not parse coarse geocode cache precision to usize"),
+        Duration::new(
+            dotenvy::var("GEOCODE_CACHE_TTL")
+                .unwrap_or("2592000".to_string())
+                .parse::<u64>()
+                .expect("Could not parse geocode cache TTL to u64"),
+            0,
+        ),
+        dotenvy::var("GEOCODE_CACHE_SIZE")
+            .unwrap_or("100000".to_string())
+            .parse::<usize>()
+            .expect("Could not parse geocode cache size to usize"),
+    ));
+    let geocode_cache_file = dotenvy::var("GEOCODE_CACHE_FILE").ok();
+    if let Some(path) = &geocode_cache_file
+        && std::path::Path::new(path).exists()
+    {
+        geocode_cache
+            .load(path)
+            .expect("Could not load persisted geocode cache");
+    }
+
+    // Shared by all requests, so the rate limit applies globally
+    let nominatim = NominatimClient::new(
+        dotenvy::var("NOMINATIM_RATE_LIMIT")
+            .unwrap_or("1".to_string())
+            .parse::<f64>()
+            .ok()
+            .filter(|rate| *rate > 0.0)
+            .expect("Please select a positive Nominatim rate limit"),
+        dotenvy::var("NOMINATIM_QUEUE_SIZE")
+            .unwrap_or("60".to_string())
+            .parse::<usize>()
+            .ok()
+            // A queue without room would turn every request away
+            .filter(|size| *size > 0)
+            .expect("Please select a positive Nominatim queue size"),
+        Duration::new(
+            dotenvy::var("NOMINATIM_TIMEOUT")
+                .unwrap_or("10".to_string())
+                .parse::<u64>()
+                .expect("Could not parse Nominatim timeout to u64"),
+            0,
+        ),
+    );
+
+    let disputed_areas = dotenvy::var("DISPUTED_AREAS").ok().map(|path| {
+        let policy = dotenvy::var("DISPUTED_AREA_POLICY")
+            .unwrap_or("all_claimants".to_string())
+            .parse::<DisputedAreaPolicy>()
+            .expect("Please select a disputed area policy");
+        DisputedAreas::from_file(&path, policy).expect("Could not load disputed areas")
+    });
+
+    let historical_borders = dotenvy::var("HISTORICAL_BORDERS").ok().map(|path| {
+        HistoricalBorders::from_file(&path).expect("Could not load historical borders")
+    });
+
+    let overlays = dotenvy::var("OVERLAY_LAYERS").ok().map(|path| {
+        Arc::new(OverlayLayers::from_manifest(&path).expect("Could not load overlay layers"))
+    });
+    let overlay_reload_interval = dotenvy::var("OVERLAY_RELOAD_INTERVAL")
+        .unwrap_or("60".to_string())
+        .parse::<u64>()
+        .expect("Could not parse overlay reload interval to u64");
+
+    let subnational_regimes = dotenvy::var("SUBNATIONAL_REGIMES").ok().map(|path| {
+        SubnationalRegimes::from_file(&path).expect("Could not load subnational regimes")
+    });
+
     let state = AppState::new(
         config.clone(),
         implementing_countries,
@@ -74,8 +273,23 @@ async fn main() {
                 .expect("Could not parse TTL to u64"),
             0,
         ),
+        GeoLayers {
+            boundaries: boundaries.map(Arc::new),
+            capitals: capitals.map(Arc::new),
+            disputed_areas: disputed_areas.map(Arc::new),
+            overlays: overlays.clone(),
+            historical_borders: historical_borders.map(Arc::new),
+        },
+        geocode_cache.clone(),
+        nominatim,
+        subnational_regimes,
     );
 
+    let max_upload_size = dotenvy::var("MAX_UPLOAD_SIZE")
+        .unwrap_or("67108864".to_string())
+        .parse::<usize>()
+        .expect("Could not parse maximum upload size to usize");
+
     let listener = tokio::net::TcpListener::bind(format!(
         "{host}:{port}",
         host = config.server_host,
@@ -87,13 +301,85 @@ async fn main() {
     let app = Router::new()
         .route("/nagoya_check_cc", post(api::nagoya_check_country_code))
         .route("/nagoya_check_geo", post(api::nagoya_check_geocoordinates))
+        // Batches and uploads of tens of thousands of records exceed the default body limit of 2 MB
+        .route(
+            "/nagoya_check_batch",
+            post(api::nagoya_check_batch).layer(DefaultBodyLimit::max(max_upload_size)),
+        )
+        .route(
+            "/nagoya_check_batch/jobs",
+            post(api::submit_batch_job).layer(DefaultBodyLimit::max(max_upload_size)),
+        )
+        .route("/nagoya_check_batch/jobs/{job_id}", get(api::batch_job))
+        .route(
+            "/nagoya_check_csv",
+            post(api::nagoya_check_csv).layer(DefaultBodyLimit::max(max_upload_size)),
+        )
+        .route(
+            "/nagoya_check_dwca",
+            post(api::nagoya_check_dwca).layer(DefaultBodyLimit::max(max_upload_size)),
+        )
+        .route(
+            "/nagoya_check_abcd",
+            post(api::nagoya_check_abcd).layer(DefaultBodyLimit::max(max_upload_size)),
+        )
+        .route("/nagoya_check_insdc", post(api::nagoya_check_insdc))
+        .route(
+            "/nagoya_check_sequences",
+            post(api::nagoya_check_sequences).layer(DefaultBodyLimit::max(max_upload_size)),
+        )
+        .route(
+            "/nagoya_check_samples",
+            post(api::nagoya_check_samples).layer(DefaultBodyLimit::max(max_upload_size)),
+        )
+        .route("/nagoya_check_dataset", post(api::nagoya_check_dataset))
+        .route("/normalize", post(api::normalize))
         .route("/openapi.json", get(api::openapi))
         .route("/health", get(api::health_check))
+        .route("/metrics", get(api::metrics))
         .merge(SwaggerUi::new("/swagger-ui").url("/docs", ApiDoc::openapi()))
         .with_state(state)
         .layer(TraceLayer::new_for_http());
 
     tracing_subscriber::fmt().with_max_level(log_level).init();
+    if boundaries_geocoder_dropped {
+        event!(
+            Level::WARN,
+            "COUNTRY_BOUNDARIES is not set, the boundaries geocoder is left out of consensus mode"
+        );
+    }
+
+    // Persist the geocode cache periodically, so a restart does not start from scratch
+    if let Some(path) = geocode_cache_file {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                if let Err(error) = geocode_cache.persist(&path) {
+                    event!(Level::WARN, "Could not persist geocode cache: {}", error);
+                }
+            }
+        });
+    }
+
+    // Overlay layers are reloaded once their files change, so they can be updated without a restart
+    if let Some(overlays) = overlays
+        && overlay_reload_interval > 0
+    {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(overlay_reload_interval));
+            loop {
+                interval.tick().await;
+                match overlays.reload_if_changed() {
+                    Ok(true) => event!(Level::INFO, "Reloaded overlay layers"),
+                    Ok(false) => {}
+                    Err(error) => {
+                        event!(Level::WARN, "Could not reload overlay layers: {}", error)
+                    }
+                }
+            }
+        });
+    }
 
     event!(
         Level::INFO,
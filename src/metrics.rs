@@ -0,0 +1,59 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Counters exposed in the Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    consensus_checks: AtomicU64,
+    consensus_disagreements: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_consensus(&self, agreement: bool) {
+        self.consensus_checks.fetch_add(1, Ordering::Relaxed);
+        if !agreement {
+            self.consensus_disagreements.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn render(&self) -> String {
+        [
+            (
+                "nagoya_consensus_checks_total",
+                "Checks in consensus mode",
+                &self.consensus_checks,
+            ),
+            (
+                "nagoya_consensus_disagreements_total",
+                "Checks in consensus mode the geocoders disagreed on",
+                &self.consensus_disagreements,
+            ),
+        ]
+        .iter()
+        .map(|(name, help, counter)| {
+            format!(
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}\n",
+                counter.load(Ordering::Relaxed)
+            )
+        })
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record_consensus(true);
+        metrics.record_consensus(false);
+        let rendered = metrics.render();
+        assert!(rendered.contains("\nnagoya_consensus_checks_total 2\n"));
+        assert!(rendered.contains("\nnagoya_consensus_disagreements_total 1\n"));
+    }
+}
@@ -2,11 +2,29 @@
 //
 // SPDX-License-Identifier: LGPL-3.0-or-later
 
+use crate::batch_jobs::BatchJobs;
+use crate::boundaries::CountryBoundaries;
+use crate::consensus::GeocoderBackend;
+use crate::coordinate_quality::Capitals;
+use crate::country::CountryId;
+use crate::country_names::CountryNames;
+use crate::disputed_areas::DisputedAreas;
 use crate::external_data;
+use crate::external_data::NominatimClient;
+use crate::geocode_cache::GeocodeCache;
+use crate::historical_borders::HistoricalBorders;
+use crate::metrics::Metrics;
+use crate::overlays::OverlayLayers;
+use crate::subnational_regimes::SubnationalRegimes;
+use axum::Json;
 use axum::extract::FromRef;
+use axum::extract::rejection::JsonRejection;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::Instant;
 use tracing::{Level, span};
@@ -16,28 +34,594 @@ use utoipa::{IntoParams, IntoResponses, ToSchema};
 // - Input
 #[derive(Deserialize, IntoParams, ToSchema)]
 pub struct NagoyaCheckDataCC {
-    // TODO: Use additional validation
+    // Alpha-2, alpha-3 or numeric country code, an ISO 3166-2 subdivision code such as AU-QLD or a
+    // country name in one of many languages
     // TODO: Add data for registered collection
     // TODO: Add data for Certificates
-    pub(crate) probe_country: String,
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    #[param(value_type = Option<String>)]
+    pub(crate) probe_country: Option<CountryInput>,
+    // Several countries with their role for samples of mixed provenance, e.g. hybrids, pooled
+    // samples or material passed through intermediaries. Used instead of probe_country
+    #[serde(default)]
+    pub(crate) countries: Vec<ProvenanceCountry>,
+}
+
+// Country as given in a request. Anything shaped like a code is validated as an ISO 3166-1
+// alpha-2, alpha-3 or numeric code or an ISO 3166-2 subdivision code when the request is parsed, so
+// malformed codes are rejected before any check runs; everything else is taken as a name
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub enum CountryInput {
+    Country(CountryId),
+    // ISO 3166-2 subdivision, e.g. AU-QLD, with the country it belongs to
+    Subdivision { country: CountryId, code: String },
+    Name(String),
+}
+
+impl TryFrom<String> for CountryInput {
+    type Error = NagoyaError;
+
+    fn try_from(input: String) -> Result<Self, NagoyaError> {
+        let input = input.trim().to_string();
+        if !looks_like_code(&input) {
+            return Ok(CountryInput::Name(input));
+        }
+        if !input.contains('-') {
+            return Ok(CountryInput::Country(input.parse()?));
+        }
+        let subdivision =
+            rust_iso3166::iso3166_2::from_code(&input.to_uppercase()).ok_or_else(|| {
+                NagoyaError::MalformedCountryCode {
+                    input: input.clone(),
+                    reason: String::from("Unknown ISO 3166-2 subdivision code"),
+                }
+            })?;
+        Ok(CountryInput::Subdivision {
+            country: subdivision.country_code.parse()?,
+            code: subdivision.code.to_string(),
+        })
+    }
+}
+
+// Whether the input is shaped like an ISO 3166-1 or ISO 3166-2 code rather than a name, regardless
+// of whether the code exists
+pub fn looks_like_code(input: &str) -> bool {
+    let is_alpha = |part: &str| part.chars().all(|c| c.is_ascii_alphabetic());
+    match input.split_once('-') {
+        Some((country, subdivision)) => {
+            country.len() == 2
+                && is_alpha(country)
+                && (1..=3).contains(&subdivision.len())
+                && subdivision.chars().all(|c| c.is_ascii_alphanumeric())
+        }
+        None => {
+            ((2..=3).contains(&input.len()) && is_alpha(input))
+                || (!input.is_empty() && input.chars().all(|c| c.is_ascii_digit()))
+        }
+    }
 }
 
-// TODO: Find out whether there is a proper way to do this / access the data directly
 #[derive(Deserialize, ToSchema)]
-pub struct NagoyaCheckDataGeo {
-    pub(crate) coordinates: Coordinates,
+pub struct NagoyaCheckBatch {
+    pub(crate) items: Vec<BatchItem>,
+}
+
+// Item of a batch: a client-supplied id with the fields of /nagoya_check_cc or, if coordinates are
+// given, of /nagoya_check_geo. The fields are parsed per item, so a malformed one fails on its own
+#[derive(Deserialize, ToSchema)]
+pub struct BatchItem {
+    pub(crate) id: String,
+    #[serde(flatten)]
+    #[schema(value_type = Object)]
+    pub(crate) check: serde_json::Map<String, serde_json::Value>,
+}
+
+// Multipart form of a CSV upload, documenting the fields of CsvColumns followed by the file, which
+// is not held in memory but checked while it is read
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct CsvUpload {
+    pub(crate) country_column: Option<String>,
+    pub(crate) latitude_column: Option<String>,
+    pub(crate) longitude_column: Option<String>,
+    pub(crate) date_column: Option<String>,
+    pub(crate) id_column: Option<String>,
+    // Field delimiter, detected from the header line if not given
+    pub(crate) delimiter: Option<String>,
+    #[schema(value_type = String, format = Binary)]
+    pub(crate) file: Vec<u8>,
+}
+
+// Columns of a CSV upload. They default to country, latitude, longitude, date and id and are
+// matched case-insensitively
+#[derive(Default, Debug)]
+pub struct CsvColumns {
+    pub(crate) country_column: Option<String>,
+    pub(crate) latitude_column: Option<String>,
+    pub(crate) longitude_column: Option<String>,
+    pub(crate) date_column: Option<String>,
+    pub(crate) id_column: Option<String>,
+    pub(crate) delimiter: Option<String>,
+}
+
+// Multipart form of an upload consisting of a single file, e.g. a Darwin Core Archive
+#[derive(ToSchema)]
+pub struct FileUpload {
+    #[schema(value_type = String, format = Binary)]
+    pub(crate) file: Vec<u8>,
+}
+
+// Source qualifiers of a sequence record, either as bare values or as in the flat file, e.g.
+// /geo_loc_name="Brazil: Amazonas, Manaus"
+#[derive(Deserialize, ToSchema, Default, Debug)]
+pub struct InsdcQualifiers {
+    // Replaced by geo_loc_name in 2024, used if geo_loc_name is not given
+    #[serde(default)]
+    pub(crate) country: Option<String>,
+    #[serde(default)]
+    pub(crate) geo_loc_name: Option<String>,
+    // e.g. "3.10 S 60.02 W"
+    #[serde(default)]
+    pub(crate) lat_lon: Option<String>,
+    #[serde(default)]
+    pub(crate) collection_date: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NormalizeRequest {
+    // Country code, name, withdrawn ISO 3166-3 code, territory code or INSDC country string such as
+    // "Brazil: Amazonas, Manaus"
+    pub(crate) country: String,
 }
 
 #[derive(Deserialize, ToSchema, Debug)]
+pub struct ProvenanceCountry {
+    #[schema(value_type = String)]
+    pub(crate) country_code: CountryInput,
+    pub(crate) role: ProvenanceRole,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProvenanceRole {
+    // Country which possesses the genetic resources in in-situ conditions
+    CountryOfOrigin,
+    // Country supplying the genetic resources, which needs to be the country of origin or to have
+    // acquired them in accordance with the CBD
+    ProvidingCountry,
+    // Country the material passed through without being the provider
+    Intermediary,
+}
+
+impl std::fmt::Display for ProvenanceRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProvenanceRole::CountryOfOrigin => write!(f, "Country of origin"),
+            ProvenanceRole::ProvidingCountry => write!(f, "Providing country"),
+            ProvenanceRole::Intermediary => write!(f, "Intermediary"),
+        }
+    }
+}
+
+// TODO: Find out whether there is a proper way to do this / access the data directly
+#[derive(Deserialize, ToSchema, Clone, Debug)]
+pub struct NagoyaCheckDataGeo {
+    pub(crate) coordinates: CoordinateInput,
+    // CRS of decimal coordinates if not WGS84. For projected CRSs, longitude holds the easting and
+    // latitude the northing
+    #[serde(default)]
+    pub(crate) epsg: Option<u16>,
+    // Date of the collection event as in Darwin Core's eventDate, e.g. "1985" or "1991-06-01/1991-07".
+    // Resolves the location against historical borders, if available
+    #[serde(default)]
+    pub(crate) event_date: Option<String>,
+    // Country given in the record, e.g. Darwin Core's countryCode, to be cross-checked against the
+    // coordinates
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub(crate) declared_country: Option<CountryInput>,
+    // Ask every configured geocoder for the country instead of Nominatim only
+    #[serde(default)]
+    pub(crate) consensus: bool,
+    // Fail instead of answering if the geocoders disagree, defaults to the configuration
+    #[serde(default)]
+    pub(crate) require_agreement: Option<bool>,
+}
+
+// Coordinates are either given as decimal degrees or as a notation found on labels and in legacy
+// databases, e.g. DMS, WKT, geo URIs, UTM, MGRS or Open Location Codes
+#[derive(Deserialize, ToSchema, Clone, Debug)]
+#[serde(untagged)]
+pub enum CoordinateInput {
+    Decimal(Coordinates),
+    Notation(CoordinateNotation),
+}
+
+#[derive(Deserialize, ToSchema, Clone, Debug)]
+pub struct CoordinateNotation {
+    pub(crate) notation: String,
+    #[serde(default)]
+    pub(crate) uncertainty_in_meters: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug, Clone)]
 pub struct Coordinates {
     pub(crate) latitude: f64,
     pub(crate) longitude: f64,
+    // Radius around the point in which the actual location lies, cf. Darwin Core's
+    // coordinateUncertaintyInMeters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) uncertainty_in_meters: Option<f64>,
 }
 // - Output
-#[derive(Serialize, IntoResponses, ToSchema)]
+#[derive(Serialize, IntoResponses, ToSchema, Default, Debug)]
 #[response(status = 200)]
 pub struct NagoyaResponse {
     pub(crate) check_result: bool,
+    // Alpha-2 code of the country checked, after resolving names and numeric codes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) country_code: Option<String>,
+    // Human readable grounds for check_result, including subnational ABS regimes
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) reasons: Vec<String>,
+    // ISO 3166-2 code of the subdivision the check was run for, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) subdivision: Option<String>,
+    // Set if more than one country lies within the coordinate uncertainty
+    pub(crate) ambiguous_location: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) candidate_countries: Vec<CandidateCountry>,
+    // Distance to the nearest international border, only available with country boundaries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) border_distance_in_meters: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) quality_flags: Vec<CoordinateQualityFlag>,
+    // Decimal WGS84 coordinates the check was run with, after parsing the input notation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) normalized_coordinates: Option<Coordinates>,
+    // Set if the location is within a disputed area, check_result then follows the configured policy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) disputed_area: Option<DisputedAreaInfo>,
+    // Overlay layers with additional access restrictions the location falls into
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) overlays: Vec<OverlayMatch>,
+    // States the location belonged to at the time of the collection event
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) historical_sovereigns: Vec<HistoricalSovereign>,
+    // Present-day state whose ABS measures govern utilization today, set alongside
+    // historical_sovereigns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) successor_state: Option<String>,
+    // Comparison with the declared country, if one was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) declared_country: Option<DeclaredCountryCheck>,
+    // Answers of all geocoders, only in consensus mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) consensus: Option<ConsensusInfo>,
+    // Verdict per country for samples of mixed provenance
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) provenance: Vec<ProvenanceStatus>,
+    // How a country name given instead of a code was resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) name_resolution: Option<NameResolution>,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct NameResolution {
+    pub(crate) input: String,
+    // Alpha-3 code of the best match
+    pub(crate) country_code: String,
+    pub(crate) matched_name: String,
+    // Between 0 and 1, lower for typos and for names matching several countries
+    pub(crate) confidence: f64,
+    // Further countries matching about as well, e.g. the other Congo
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) alternatives: Vec<NameCandidate>,
+}
+
+#[derive(Serialize, ToSchema, Clone, Debug, PartialEq)]
+pub struct NameCandidate {
+    pub(crate) country_code: String,
+    pub(crate) matched_name: String,
+    pub(crate) confidence: f64,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct ProvenanceStatus {
+    pub(crate) country_code: String,
+    pub(crate) role: ProvenanceRole,
+    pub(crate) check_result: bool,
+    // Whether the country's measures went into the overall check_result
+    pub(crate) applied: bool,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct ConsensusInfo {
+    pub(crate) agreement: bool,
+    pub(crate) answers: Vec<GeocoderAnswer>,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct GeocoderAnswer {
+    pub(crate) geocoder: String,
+    // Missing if the geocoder could not resolve the coordinates
+    pub(crate) country_code: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct DeclaredCountryCheck {
+    // Set if the declared country is not plausible for the coordinates, even within the tolerance
+    pub(crate) mismatch: bool,
+    pub(crate) declared: CandidateCountry,
+    pub(crate) resolved: CandidateCountry,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct HistoricalSovereign {
+    pub(crate) name: String,
+    // Code used by the historical dataset, e.g. the Gleditsch-Ward code in CShapes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) code: Option<String>,
+    pub(crate) valid_from: String,
+    pub(crate) valid_until: String,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct OverlayMatch {
+    pub(crate) layer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) licence: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) advisory: Option<String>,
+    // Names of the matching features, e.g. of protected areas
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) features: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct DisputedAreaInfo {
+    pub(crate) name: String,
+    pub(crate) policy: String,
+    pub(crate) claimants: Vec<ClaimantStatus>,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct ClaimantStatus {
+    pub(crate) country_code: String,
+    pub(crate) check_result: bool,
+    pub(crate) administering: bool,
+    // Whether the claimant's measures went into check_result according to the policy
+    pub(crate) applied: bool,
+}
+
+// Possible issues with the georeference, modelled after GBIF's occurrence issues
+#[derive(Serialize, ToSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CoordinateQualityFlag {
+    ZeroCoordinate,
+    PresumedSwappedCoordinate,
+    CountryCentroid,
+    CapitalCoordinate,
+    LowPrecision,
+    CountryCoordinateMismatch,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct CandidateCountry {
+    pub(crate) country_code: String,
+    pub(crate) check_result: bool,
+}
+
+#[derive(Serialize, IntoResponses, ToSchema)]
+#[response(status = 200)]
+pub struct BatchResponse {
+    // In the order of the items
+    pub(crate) results: Vec<BatchItemResult>,
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchJobStatus {
+    Running,
+    Done,
+    // The job as a whole failed, e.g. because the implementing countries were unavailable
+    Failed,
+}
+
+// State of a batch job, with the results once it is done. Results are shared, as a job may be
+// polled many times until it expires
+#[derive(Serialize, IntoResponses, ToSchema, Clone)]
+#[response(status = 200)]
+pub struct BatchJobResponse {
+    pub(crate) job_id: String,
+    pub(crate) status: BatchJobStatus,
+    // Number of submitted items
+    pub(crate) items: usize,
+    // In the order of the items
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<BatchItemResult>>)]
+    pub(crate) results: Option<Arc<Vec<BatchItemResult>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<ErrorResponse>)]
+    pub(crate) error: Option<Arc<ErrorResponse>>,
+}
+
+// Either the result of the check or the error it failed with
+#[derive(Serialize, ToSchema)]
+pub struct BatchItemResult {
+    pub(crate) id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) result: Option<NagoyaResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<ErrorResponse>,
+}
+
+// Outcome of checking all records of an uploaded dataset
+#[derive(Serialize, ToSchema, Debug, Default, PartialEq)]
+pub struct DatasetSummary {
+    pub(crate) records: usize,
+    // Records with a positive check, i.e. from a party to the Nagoya Protocol
+    pub(crate) positive: usize,
+    // Records the check failed for, e.g. for invalid coordinates
+    pub(crate) failed: usize,
+    // Alpha-2 codes of the provider countries with positively checked records
+    pub(crate) affected_countries: Vec<String>,
+    // Affected countries first, then by number of records
+    pub(crate) countries: Vec<CountrySummary>,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+pub struct CountrySummary {
+    pub(crate) country_code: String,
+    pub(crate) records: usize,
+    pub(crate) positive: usize,
+}
+
+#[derive(Serialize, IntoResponses, ToSchema, Debug)]
+#[response(status = 200)]
+pub struct InsdcResponse {
+    pub(crate) qualifiers: ParsedQualifiers,
+    pub(crate) result: NagoyaResponse,
+}
+
+#[derive(Serialize, IntoResponses, ToSchema)]
+#[response(status = 200)]
+pub struct DatasetCheck {
+    // Set if any location may lie in a party to the Nagoya Protocol, e.g. to show a notice on the
+    // landing page of the dataset. Null if none does but some locations could not be checked
+    pub(crate) nagoya_notice: Option<bool>,
+    // Alpha-2 codes of the parties the locations resolve to, including candidates within boxes
+    pub(crate) notice_countries: Vec<String>,
+    // In the order of the metadata
+    pub(crate) locations: Vec<LocationResult>,
+    pub(crate) summary: DatasetSummary,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LocationResult {
+    pub(crate) location: GeoLocation,
+    // How a box was compared with the countries it covers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) method: Option<BoxMethod>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) result: Option<NagoyaResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<ErrorResponse>,
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BoxMethod {
+    // Intersected with the polygons of COUNTRY_BOUNDARIES
+    Boundaries,
+    // Geocoded at its corners, the midpoints of its edges and its center, as no boundaries are
+    // loaded. Countries only within the box but not at these points are missed
+    Sampled,
+}
+
+// Location of a dataset as given in DataCite geoLocations or EML geographicCoverage
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GeoLocation {
+    Point {
+        latitude: f64,
+        longitude: f64,
+    },
+    Box {
+        west: f64,
+        east: f64,
+        south: f64,
+        north: f64,
+    },
+    Place {
+        name: String,
+    },
+}
+
+#[derive(Serialize, IntoResponses, ToSchema)]
+#[response(status = 200)]
+pub struct RecordReport {
+    // In the order of the records in the file
+    pub(crate) results: Vec<RecordResult>,
+    pub(crate) summary: DatasetSummary,
+}
+
+// Result of a record of a sequence or sample file, by accession or name, with the qualifiers it
+// was checked with
+#[derive(Serialize, ToSchema)]
+pub struct RecordResult {
+    pub(crate) id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) qualifiers: Option<ParsedQualifiers>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) result: Option<NagoyaResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<ErrorResponse>,
+}
+
+// Components of INSDC source qualifiers, as the check understood them
+#[derive(Serialize, ToSchema, Debug, Default)]
+pub struct ParsedQualifiers {
+    // Entry of the INSDC country vocabulary, e.g. "Brazil"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) country: Option<String>,
+    // Part following the country, e.g. "Amazonas, Manaus"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) locality: Option<String>,
+    // Alpha-2 code of the country, missing if it maps to none or several, e.g. oceans or Borneo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) country_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) coordinates: Option<Coordinates>,
+    // ISO 8601 form of the collection date, e.g. 1952-10-21 for 21-Oct-1952
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) collection_date: Option<String>,
+    // Qualifiers carrying an INSDC missing value, e.g. "lat_lon: not collected"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) missing_values: Vec<String>,
+    // How the country was mapped, cf. /normalize
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) notes: Vec<String>,
+}
+
+#[derive(Serialize, IntoResponses, ToSchema, Debug, Default)]
+#[response(status = 200)]
+pub struct NormalizedCountry {
+    pub(crate) input: String,
+    // Current country the input refers to, missing if it maps to several, e.g. Yugoslavia
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) country: Option<CanonicalCountry>,
+    // Current countries the input may refer to if it does not map to a single one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) candidates: Vec<CanonicalCountry>,
+    // ISO 3166-2 code, if a subdivision was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) subdivision: Option<String>,
+    // Part following the country in INSDC strings, e.g. "Amazonas, Manaus"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) locality: Option<String>,
+    // How the input was mapped, e.g. from a withdrawn code to its successor
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) notes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) name_resolution: Option<NameResolution>,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct CanonicalCountry {
+    pub(crate) alpha2: String,
+    pub(crate) alpha3: String,
+    pub(crate) numeric: String,
+    // ISO 3166-1 short name
+    pub(crate) name: String,
+    // Alpha-2 code of the sovereign state, the country itself if independent. Missing for areas
+    // without an undisputed sovereign, e.g. Antarctica
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) sovereign_state: Option<String>,
 }
 
 #[derive(Serialize, IntoResponses, ToSchema)]
@@ -46,11 +630,30 @@ pub struct GenericResponse {
     pub(crate) message: String,
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    // Machine readable kind of the error, e.g. malformed_country_code
+    pub(crate) error: &'static str,
+    pub(crate) message: String,
+    // The offending part of the request, if there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) input: Option<String>,
+    // Issues with the coordinates, if they could not be resolved
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) quality_flags: Vec<CoordinateQualityFlag>,
+    // Countries an ambiguous name may refer to, with the confidence of each
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) alternatives: Vec<NameCandidate>,
+}
+
 // External Requests
 #[derive(Deserialize, PartialEq, Hash, Eq, Debug)]
 pub struct NagoyaCountryInfo {
     pub(crate) code2: String,
     pub(crate) code3: String,
+    // Names by language code, e.g. {"en": "Brazil", "es": "Brasil", "fr": "Brésil"}
+    #[serde(default)]
+    pub(crate) name: BTreeMap<String, String>,
     //pub(crate) nagoya_info: NagoyaTreatyInfo,
     //#[serde(flatten)]
     pub(crate) treaties: Treaties,
@@ -70,29 +673,79 @@ pub struct Treaty {
 }
 
 // Internal
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct ImplementingCountries {
-    pub(crate) countries: HashSet<String>,
+    pub(crate) countries: HashSet<CountryId>,
+    // Names of all countries, not only the implementing ones, to resolve names given instead of codes
+    pub(crate) names: CountryNames,
 }
 
-impl FromRef<AppState> for ImplementingCountries {
-    fn from_ref(app_state: &AppState) -> ImplementingCountries {
+impl FromRef<AppState> for Arc<ImplementingCountries> {
+    fn from_ref(app_state: &AppState) -> Arc<ImplementingCountries> {
         app_state.implementing_countries.data.clone()
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default, Debug, PartialEq)]
 pub struct NominatimAddress {
+    // Missing for places outside of any country, e.g. in international waters
+    pub(crate) country_code: Option<String>,
+    // ISO 3166-2 codes of the enclosing subdivisions per OSM admin level. States and provinces are
+    // mostly found on level 4
+    #[serde(rename = "ISO3166-2-lvl3")]
+    pub(crate) iso3166_2_lvl3: Option<String>,
+    #[serde(rename = "ISO3166-2-lvl4")]
+    pub(crate) iso3166_2_lvl4: Option<String>,
+    #[serde(rename = "ISO3166-2-lvl5")]
+    pub(crate) iso3166_2_lvl5: Option<String>,
+    #[serde(rename = "ISO3166-2-lvl6")]
+    pub(crate) iso3166_2_lvl6: Option<String>,
+}
+
+// Result of the reverse lookup of coordinates
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ResolvedLocation {
+    // Alpha-2 code as returned by Nominatim
     pub(crate) country_code: String,
+    // ISO 3166-2 code of the state or province, if Nominatim knows it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) subdivision: Option<String>,
 }
-#[derive(Deserialize)]
-pub struct NominatimResponse {
-    pub(crate) address: NominatimAddress,
+
+// Nominatim answers with HTTP 200 in both cases, e.g. {"error":"Unable to geocode"} if there is
+// nothing to be found at the coordinates
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum NominatimResponse {
+    Error {
+        error: serde_json::Value,
+    },
+    Place {
+        #[serde(default)]
+        address: NominatimAddress,
+    },
 }
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Config {
     pub nominatim_host: String,
+    pub nominatim_email: Option<String>,
+    pub nominatim_accept_language: Option<String>,
+    // Distance in meters from the coordinates within which a declared country is still consistent
+    pub declared_country_tolerance: f64,
+    // Geocoders asked in consensus mode and whether they need to agree by default
+    pub consensus_geocoders: Vec<GeocoderBackend>,
+    pub consensus_require_agreement: bool,
+    // Checks of a batch running at the same time and the maximum number of items per batch
+    pub batch_concurrency: usize,
+    pub batch_max_items: usize,
+    // Maximum number of items of a batch job and how long its result is kept once finished
+    pub batch_job_max_items: usize,
+    pub batch_job_retention: Duration,
+    // Maximum number of records per uploaded file, e.g. rows of a CSV or occurrences of a DwC-A
+    pub upload_max_records: usize,
+    // Maximum number of bytes extracted from an uploaded archive
+    pub max_extracted_size: u64,
     pub server_host: String,
     pub server_port: u16,
 }
@@ -106,23 +759,54 @@ impl FromRef<AppState> for Config {
 pub struct AppState {
     //pub implementing_countries: ImplementingCountries, //replace with Cache<ImplementingCountries>
     pub config: Config,
-    implementing_countries: Cache<ImplementingCountries>,
+    // Shared, as the state is cloned for every request
+    implementing_countries: Cache<Arc<ImplementingCountries>>,
+    pub layers: GeoLayers,
+    pub geocode_cache: Arc<GeocodeCache>,
+    pub nominatim: Arc<NominatimClient>,
+    pub subnational_regimes: Option<Arc<SubnationalRegimes>>,
+    pub metrics: Arc<Metrics>,
+    pub batch_jobs: Arc<BatchJobs>,
+}
+
+// Optional local datasets, each one enabling additional checks if configured
+#[derive(Clone, Default)]
+pub struct GeoLayers {
+    pub boundaries: Option<Arc<CountryBoundaries>>,
+    pub capitals: Option<Arc<Capitals>>,
+    pub disputed_areas: Option<Arc<DisputedAreas>>,
+    pub overlays: Option<Arc<OverlayLayers>>,
+    pub historical_borders: Option<Arc<HistoricalBorders>>,
 }
 
 impl AppState {
-    pub fn new(config: Config, countries: ImplementingCountries, ttl: Duration) -> Self {
+    pub fn new(
+        config: Config,
+        countries: ImplementingCountries,
+        ttl: Duration,
+        layers: GeoLayers,
+        geocode_cache: Arc<GeocodeCache>,
+        nominatim: NominatimClient,
+        subnational_regimes: Option<SubnationalRegimes>,
+    ) -> Self {
         Self {
+            batch_jobs: Arc::new(BatchJobs::new(config.batch_job_retention)),
             config,
             implementing_countries: Cache {
                 last_updated: Instant::now(),
                 ttl,
-                data: countries,
+                data: Arc::new(countries),
             },
+            layers,
+            geocode_cache,
+            nominatim: Arc::new(nominatim),
+            subnational_regimes: subnational_regimes.map(Arc::new),
+            metrics: Arc::new(Metrics::default()),
         }
     }
     // Returns the current version of implementing countries; fetching a new one, if needed
-    pub async fn implementing_countries(&mut self) -> &ImplementingCountries {
-        self.implementing_countries.get().await
+    pub async fn implementing_countries(&mut self) -> Arc<ImplementingCountries> {
+        self.implementing_countries.get().await.clone()
     }
 }
 
@@ -134,7 +818,7 @@ pub struct Cache<T> {
     ttl: Duration,
 }
 
-impl Cache<ImplementingCountries> {
+impl Cache<Arc<ImplementingCountries>> {
     async fn is_fresh(&self) -> bool {
         self.last_updated.elapsed() <= self.ttl
     }
@@ -142,12 +826,15 @@ impl Cache<ImplementingCountries> {
         let span = span!(Level::INFO, "Updating cached data");
         let _enter = span.enter();
         // TODO: Add proper error handling
-        self.data = external_data::get_implementing_countries().await.unwrap();
+        let mut data = external_data::get_implementing_countries().await.unwrap();
+        // The configured aliases are not part of the ABSCH data
+        data.names.set_aliases(self.data.names.aliases().clone());
+        self.data = Arc::new(data);
         self.last_updated = Instant::now();
     }
     // When the data is accessed, check whether it is fresh. If it is fresh, just return
     // the data. If not, update it and then return the data
-    async fn get(&mut self) -> &ImplementingCountries {
+    async fn get(&mut self) -> &Arc<ImplementingCountries> {
         return if self.is_fresh().await {
             &self.data
         } else {
@@ -159,20 +846,177 @@ impl Cache<ImplementingCountries> {
 
 #[derive(Debug, Snafu, PartialEq)]
 pub enum NagoyaError {
-    #[snafu(display("Malformed country code"))]
-    MalformedCountryCode,
-    #[snafu(display("Could not resolve Geocoordinates"))]
-    UnresolvableCoordinates,
+    #[snafu(display("Malformed country code \"{input}\": {reason}"))]
+    MalformedCountryCode { input: String, reason: String },
+    #[snafu(display("Unknown country name \"{name}\""))]
+    UnknownCountryName { name: String },
+    #[snafu(display(
+        "Country name \"{name}\" is ambiguous, it may refer to {}",
+        alternatives
+            .iter()
+            .map(|alternative| alternative.country_code.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))]
+    AmbiguousCountryName {
+        name: String,
+        alternatives: Vec<NameCandidate>,
+    },
+    #[snafu(display("\"{input}\" does not refer to a country: {reason}"))]
+    NotACountry { input: String, reason: String },
+    #[snafu(display("Neither a probe country nor a list of countries given"))]
+    MissingCountry,
+    #[snafu(display("Malformed request: {reason}"))]
+    MalformedRequest { reason: String },
+    #[snafu(display("Malformed item: {reason}"))]
+    MalformedItem { reason: String },
+    #[snafu(display("Malformed upload: {reason}"))]
+    MalformedUpload { reason: String },
+    #[snafu(display("Batch of {items} items exceeds the maximum of {max} items"))]
+    BatchTooLarge { items: usize, max: usize },
+    #[snafu(display("Unknown batch job \"{job_id}\", it may have expired"))]
+    UnknownBatchJob { job_id: String },
+    #[snafu(display("Invalid coordinates: {reason}"))]
+    InvalidCoordinates { reason: String },
+    #[snafu(display("Invalid event date \"{event_date}\""))]
+    InvalidEventDate { event_date: String },
+    #[snafu(display("Unsupported coordinate reference system EPSG:{epsg}"))]
+    UnsupportedCrs { epsg: u16 },
+    // Quality flags of the coordinates, as they often explain why no country was found, e.g. 0, 0
+    #[snafu(display("Could not resolve Geocoordinates to a country"))]
+    UnresolvableCoordinates {
+        quality_flags: Vec<CoordinateQualityFlag>,
+    },
+    #[snafu(display("Geocoders disagree: {answers}"))]
+    GeocoderDisagreement { answers: String },
+    #[snafu(display(
+        "Consensus mode needs at least two geocoders, {geocoders} configured on this server"
+    ))]
+    ConsensusUnavailable { geocoders: usize },
+    #[snafu(display("Too many pending requests, retry after {retry_after} seconds"))]
+    RateLimited { retry_after: u64 },
     // TODO: Auf internal server error mappen nach außen, aber verschieden wegloggen?
     // Für User bis auf maybe temporär eigentlich egal
     #[snafu(display("External Resource unreachable"))]
     UnreachableExternalResource,
+    #[snafu(display("External Resource timed out"))]
+    ExternalResourceTimeout,
     #[snafu(display("Could not parse external response"))]
     UnparsableExternalResponse,
     #[snafu(display("Internal Server Error"))]
     GenericInternalServerError,
 }
 
+impl NagoyaError {
+    // Machine readable kind of the error, stable across changes of the message
+    fn kind(&self) -> &'static str {
+        match self {
+            NagoyaError::MalformedCountryCode { .. } => "malformed_country_code",
+            NagoyaError::UnknownCountryName { .. } => "unknown_country_name",
+            NagoyaError::AmbiguousCountryName { .. } => "ambiguous_country_name",
+            NagoyaError::NotACountry { .. } => "not_a_country",
+            NagoyaError::MissingCountry => "missing_country",
+            NagoyaError::MalformedRequest { .. } => "malformed_request",
+            NagoyaError::MalformedItem { .. } => "malformed_item",
+            NagoyaError::MalformedUpload { .. } => "malformed_upload",
+            NagoyaError::BatchTooLarge { .. } => "batch_too_large",
+            NagoyaError::UnknownBatchJob { .. } => "unknown_batch_job",
+            NagoyaError::InvalidCoordinates { .. } => "invalid_coordinates",
+            NagoyaError::InvalidEventDate { .. } => "invalid_event_date",
+            NagoyaError::UnsupportedCrs { .. } => "unsupported_crs",
+            NagoyaError::UnresolvableCoordinates { .. } => "unresolvable_coordinates",
+            NagoyaError::GeocoderDisagreement { .. } => "geocoder_disagreement",
+            NagoyaError::ConsensusUnavailable { .. } => "consensus_unavailable",
+            NagoyaError::RateLimited { .. } => "rate_limited",
+            NagoyaError::UnreachableExternalResource => "unreachable_external_resource",
+            NagoyaError::ExternalResourceTimeout => "external_resource_timeout",
+            NagoyaError::UnparsableExternalResponse => "unparsable_external_response",
+            NagoyaError::GenericInternalServerError => "internal_server_error",
+        }
+    }
+
+    fn input(&self) -> Option<String> {
+        match self {
+            NagoyaError::MalformedCountryCode { input, .. } => Some(input.clone()),
+            NagoyaError::UnknownCountryName { name } => Some(name.clone()),
+            NagoyaError::AmbiguousCountryName { name, .. } => Some(name.clone()),
+            NagoyaError::NotACountry { input, .. } => Some(input.clone()),
+            NagoyaError::InvalidEventDate { event_date } => Some(event_date.clone()),
+            NagoyaError::UnknownBatchJob { job_id } => Some(job_id.clone()),
+            _ => None,
+        }
+    }
+
+    fn quality_flags(&self) -> Vec<CoordinateQualityFlag> {
+        match self {
+            NagoyaError::UnresolvableCoordinates { quality_flags } => quality_flags.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn alternatives(&self) -> Vec<NameCandidate> {
+        match self {
+            NagoyaError::AmbiguousCountryName { alternatives, .. } => alternatives.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn error_response(&self) -> ErrorResponse {
+        ErrorResponse {
+            error: self.kind(),
+            message: self.to_string(),
+            input: self.input(),
+            quality_flags: self.quality_flags(),
+            alternatives: self.alternatives(),
+        }
+    }
+}
+
+impl From<JsonRejection> for NagoyaError {
+    fn from(rejection: JsonRejection) -> Self {
+        NagoyaError::MalformedRequest {
+            reason: rejection.body_text(),
+        }
+    }
+}
+
+impl IntoResponse for NagoyaError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            NagoyaError::MalformedCountryCode { .. }
+            | NagoyaError::MissingCountry
+            | NagoyaError::MalformedRequest { .. }
+            | NagoyaError::MalformedItem { .. }
+            | NagoyaError::MalformedUpload { .. }
+            | NagoyaError::UnknownCountryName { .. }
+            | NagoyaError::AmbiguousCountryName { .. }
+            | NagoyaError::NotACountry { .. }
+            | NagoyaError::InvalidCoordinates { .. }
+            | NagoyaError::InvalidEventDate { .. }
+            | NagoyaError::UnsupportedCrs { .. }
+            | NagoyaError::ConsensusUnavailable { .. }
+            | NagoyaError::UnresolvableCoordinates { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            NagoyaError::UnreachableExternalResource | NagoyaError::UnparsableExternalResponse => {
+                StatusCode::BAD_GATEWAY
+            }
+            NagoyaError::ExternalResourceTimeout => StatusCode::GATEWAY_TIMEOUT,
+            NagoyaError::BatchTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            NagoyaError::UnknownBatchJob { .. } => StatusCode::NOT_FOUND,
+            NagoyaError::GeocoderDisagreement { .. } => StatusCode::CONFLICT,
+            NagoyaError::RateLimited { retry_after } => {
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(self.error_response()),
+                )
+                    .into_response();
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(self.error_response())).into_response()
+    }
+}
+
 //impl Default for Cache<ImplementingCountries> {
 //    fn default() -> Self {
 //        Cache {
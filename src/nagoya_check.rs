@@ -2,44 +2,501 @@
 //
 // SPDX-License-Identifier: LGPL-3.0-or-later
 
+use crate::boundaries::CountryBoundaries;
+use crate::consensus::consensus;
+use crate::coordinate_notation::normalize;
+use crate::coordinate_quality::{quality_flags, validate};
+use crate::country::{CountryId, ResolvedCountry, resolve_country};
+use crate::disputed_areas::{DisputedArea, DisputedAreaPolicy};
 use crate::external_data::fetch_country_code_by_coordinates;
-use crate::models::{Config, Coordinates, ImplementingCountries, NagoyaError, NagoyaResponse};
+use crate::historical_borders::parse_event_date;
+use crate::models::{
+    AppState, CandidateCountry, ClaimantStatus, CoordinateInput, CoordinateQualityFlag,
+    Coordinates, CountryInput, DeclaredCountryCheck, DisputedAreaInfo, ImplementingCountries,
+    NagoyaCheckDataCC, NagoyaCheckDataGeo, NagoyaError, NagoyaResponse, ProvenanceCountry,
+    ProvenanceRole, ProvenanceStatus,
+};
+use crate::rate_limit::Queueing;
+use crate::reprojection::to_wgs84;
+use crate::subnational_regimes::SubnationalRegimes;
 use axum::Json;
 use tracing::{Level, event, instrument, span};
 
-#[instrument(skip(implementing_countries))]
+// The probe country is either a country code or an ISO 3166-2 subdivision code. For subdivisions,
+// the verdict follows the country, with the subdivision's own regimes added as reasons
+#[instrument(skip(implementing_countries, subnational_regimes))]
 pub async fn nagoya_check_cc(
-    probe_country: String,
+    probe_country: CountryInput,
     implementing_countries: &ImplementingCountries,
+    subnational_regimes: Option<&SubnationalRegimes>,
     //) -> Result<Json<NagoyaResponse>, Box<dyn Error + Send + Sync>> {
 ) -> Result<Json<crate::models::NagoyaResponse>, NagoyaError> {
     let span = span!(Level::DEBUG, "Lookup via Country Code");
     let _enter = span.enter();
+    let ResolvedCountry {
+        country,
+        subdivision,
+        name_resolution,
+    } = resolve_country(&probe_country, &implementing_countries.names)?;
+    let country_code = country.alpha2();
+    let check_result = implementing_countries.countries.contains(&country);
+    let mut reasons = vec![party_reason(country_code, check_result)];
+    if let (Some(subdivision), Some(subnational_regimes)) = (&subdivision, subnational_regimes) {
+        reasons.extend(subnational_regimes.reasons(subdivision));
+    }
     Ok(Json(NagoyaResponse {
-        check_result: is_probe_in_implementing_country(implementing_countries, &probe_country)
-            .await?,
+        check_result,
+        country_code: Some(country_code.to_string()),
+        reasons,
+        subdivision,
+        name_resolution,
+        ..Default::default()
+    }))
+}
+
+// Checks either the probe country or the countries of a sample of mixed provenance
+pub async fn nagoya_check_country(
+    request: NagoyaCheckDataCC,
+    implementing_countries: &ImplementingCountries,
+    subnational_regimes: Option<&SubnationalRegimes>,
+) -> Result<Json<NagoyaResponse>, NagoyaError> {
+    match request.probe_country {
+        Some(probe_country) if request.countries.is_empty() => {
+            nagoya_check_cc(probe_country, implementing_countries, subnational_regimes).await
+        }
+        // A probe country alongside a list is taken as one more country of origin
+        probe_country => {
+            let mut countries = request.countries;
+            countries.extend(probe_country.map(|country_code| ProvenanceCountry {
+                country_code,
+                role: ProvenanceRole::CountryOfOrigin,
+            }));
+            nagoya_check_provenance(countries, implementing_countries, subnational_regimes).await
+        }
+    }
+}
+
+// Evaluates every country of a sample of mixed provenance. Obligations arise from the measures of
+// the countries of origin and the providing countries, as a providing country may only grant
+// access if it is the country of origin or acquired the material in accordance with the CBD.
+// Intermediaries are only considered if nothing else is known about the provenance
+#[instrument(skip(implementing_countries, subnational_regimes))]
+pub async fn nagoya_check_provenance(
+    countries: Vec<ProvenanceCountry>,
+    implementing_countries: &ImplementingCountries,
+    subnational_regimes: Option<&SubnationalRegimes>,
+) -> Result<Json<NagoyaResponse>, NagoyaError> {
+    if countries.is_empty() {
+        return Err(NagoyaError::MissingCountry);
+    }
+    let has_role = |role: ProvenanceRole| countries.iter().any(|country| country.role == role);
+    let only_intermediaries =
+        !has_role(ProvenanceRole::CountryOfOrigin) && !has_role(ProvenanceRole::ProvidingCountry);
+    let mut reasons = Vec::new();
+    if only_intermediaries {
+        reasons.push(String::from(
+            "Neither a country of origin nor a providing country given, intermediaries are treated as providing countries",
+        ));
+    } else if has_role(ProvenanceRole::ProvidingCountry)
+        && !has_role(ProvenanceRole::CountryOfOrigin)
+    {
+        reasons.push(String::from(
+            "The providing country needs to be the country of origin or to have acquired the material in accordance with the CBD",
+        ));
+    }
+
+    let mut provenance = Vec::with_capacity(countries.len());
+    for country in countries {
+        let Json(response) = nagoya_check_cc(
+            country.country_code.clone(),
+            implementing_countries,
+            subnational_regimes,
+        )
+        .await?;
+        reasons.extend(
+            response
+                .reasons
+                .into_iter()
+                .map(|reason| format!("{}: {reason}", country.role)),
+        );
+        provenance.push(ProvenanceStatus {
+            country_code: response
+                .subdivision
+                .or(response.country_code)
+                .unwrap_or_default(),
+            role: country.role,
+            check_result: response.check_result,
+            applied: only_intermediaries || country.role != ProvenanceRole::Intermediary,
+        });
+    }
+    Ok(Json(NagoyaResponse {
+        check_result: provenance
+            .iter()
+            .any(|country| country.applied && country.check_result),
+        reasons,
+        provenance,
+        ..Default::default()
     }))
 }
 
-#[instrument(skip(implementing_countries))]
+#[instrument(skip(implementing_countries, state))]
 pub async fn nagoya_check_geo(
-    coordinates: Coordinates,
+    request: NagoyaCheckDataGeo,
     implementing_countries: &ImplementingCountries,
-    config: &Config, // Host meaningless here, so unpacked just before use
-                     //) -> Result<Json<NagoyaResponse>, Box<dyn Error + Send + Sync>> {
+    // Provides the config, the shared Nominatim client and the optional local data
+    state: &AppState,
+    queueing: Queueing,
+    //) -> Result<Json<NagoyaResponse>, Box<dyn Error + Send + Sync>> {
 ) -> Result<Json<NagoyaResponse>, NagoyaError> {
     let span = span!(Level::DEBUG, "Lookup via Geocoordinates");
     let _enter = span.enter();
-    nagoya_check_cc(
-        fetch_country_code_by_coordinates(config, coordinates).await?,
-        // Let's stick with this instead of differentiating between malformed coordinates and
-        // external service not reachable. Either way the external service tells us both
-        // If reverse lookup is not possible, nominatim returns {"error":"Unable to geocode"}
-        // with Status 200
-        //.map_err(|_| NagoyaError::UnresolvableCoordinates)?,
-        implementing_countries,
-    )
-    .await
+    let boundaries = state.layers.boundaries.as_deref();
+    let coordinates = match (request.coordinates, request.epsg) {
+        (CoordinateInput::Decimal(coordinates), Some(epsg)) => to_wgs84(coordinates, epsg)?,
+        // Notations come with their own reference system, mostly WGS84
+        (CoordinateInput::Notation(_), Some(_)) => {
+            return Err(NagoyaError::InvalidCoordinates {
+                reason: String::from("An EPSG code can only be given for decimal coordinates"),
+            });
+        }
+        (coordinates, None) => normalize(coordinates)?,
+    };
+    validate(&coordinates)?;
+    // Dropped backends, e.g. boundaries without COUNTRY_BOUNDARIES, would otherwise leave a single
+    // geocoder agreeing with itself
+    if request.consensus && state.config.consensus_geocoders.len() < 2 {
+        return Err(NagoyaError::ConsensusUnavailable {
+            geocoders: state.config.consensus_geocoders.len(),
+        });
+    }
+    let event_period = request
+        .event_date
+        .as_deref()
+        .map(parse_event_date)
+        .transpose()?;
+    let declared_country = request
+        .declared_country
+        .as_ref()
+        .map(|declared| resolve_country(declared, &implementing_countries.names))
+        .transpose()?
+        .map(|resolved| resolved.country);
+    let border_distance = boundaries
+        .and_then(|boundaries| {
+            boundaries.distance_to_border(coordinates.latitude, coordinates.longitude)
+        })
+        .map(f64::round);
+    // Computed up front, so coordinates that cannot be resolved are reported with their flags
+    let quality_flags = quality_flags(&coordinates, boundaries, state.layers.capitals.as_deref());
+    let subdivision_needed = |country_code: &str| {
+        state
+            .subnational_regimes
+            .as_deref()
+            .is_some_and(|regimes| regimes.covers_country(country_code))
+    };
+    let location = match state.geocode_cache.get(
+        coordinates.latitude,
+        coordinates.longitude,
+        subdivision_needed,
+    ) {
+        Some(location) => location,
+        None => {
+            let location = fetch_country_code_by_coordinates(
+                &state.config,
+                &state.nominatim,
+                coordinates.clone(),
+                queueing,
+            )
+            .await
+            .map_err(|error| match error {
+                NagoyaError::UnresolvableCoordinates { .. } => {
+                    NagoyaError::UnresolvableCoordinates {
+                        quality_flags: quality_flags.clone(),
+                    }
+                }
+                error => error,
+            })?;
+            state.geocode_cache.insert(
+                coordinates.latitude,
+                coordinates.longitude,
+                &location,
+                border_distance,
+            );
+            location
+        }
+    };
+    let consensus = request.consensus.then(|| {
+        consensus(
+            &state.config.consensus_geocoders,
+            &location.country_code,
+            &coordinates,
+            boundaries,
+        )
+    });
+    if let Some(consensus) = &consensus {
+        state.metrics.record_consensus(consensus.agreement);
+        if !consensus.agreement
+            && request
+                .require_agreement
+                .unwrap_or(state.config.consensus_require_agreement)
+        {
+            return Err(NagoyaError::GeocoderDisagreement {
+                answers: consensus
+                    .answers
+                    .iter()
+                    .map(|answer| {
+                        format!(
+                            "{} resolved {}",
+                            answer.geocoder,
+                            answer.country_code.as_deref().unwrap_or("nothing")
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            });
+        }
+    }
+    // Within a disputed area the policy decides instead of the reverse lookup, which may return a
+    // code outside ISO 3166-1 there, e.g. XK
+    let disputed_area = state
+        .layers
+        .disputed_areas
+        .as_deref()
+        .and_then(|disputed_areas| {
+            disputed_areas
+                .area_at(coordinates.latitude, coordinates.longitude)
+                .map(|area| (area, &disputed_areas.policy))
+        });
+    let mut response = match disputed_area {
+        Some((area, policy)) => {
+            disputed_area_response(area, policy, &location.country_code, implementing_countries)
+                .await
+        }
+        None => {
+            // OSM may carry subdivision codes unknown to ISO 3166-2, those fall back to the country
+            let probe_country = location
+                .subdivision
+                .clone()
+                .filter(|code| rust_iso3166::iso3166_2::from_code(&code.to_uppercase()).is_some())
+                .unwrap_or_else(|| location.country_code.clone());
+            nagoya_check_cc(
+                CountryInput::try_from(probe_country)?,
+                implementing_countries,
+                state.subnational_regimes.as_deref(),
+            )
+            .await?
+            .0
+        }
+    };
+    response.quality_flags = quality_flags;
+    response.normalized_coordinates = Some(coordinates.clone());
+    response.consensus = consensus;
+    if let (Some(historical_borders), Some(event_period)) =
+        (state.layers.historical_borders.as_deref(), event_period)
+    {
+        response.historical_sovereigns = historical_borders.sovereigns_at(
+            coordinates.latitude,
+            coordinates.longitude,
+            event_period,
+        );
+        response.successor_state = Some(location.country_code.to_uppercase());
+    }
+    if let Some(overlays) = state.layers.overlays.as_deref() {
+        response.overlays = overlays.matches(
+            coordinates.latitude,
+            coordinates.longitude,
+            coordinates.uncertainty_in_meters,
+        );
+    }
+
+    if let Some(declared_country) = declared_country {
+        let check = declared_country_check(
+            declared_country,
+            &location.country_code,
+            &coordinates,
+            &response,
+            boundaries,
+            state.config.declared_country_tolerance,
+            implementing_countries,
+        )
+        .await?;
+        if check.mismatch {
+            response
+                .quality_flags
+                .push(CoordinateQualityFlag::CountryCoordinateMismatch);
+        }
+        response.declared_country = Some(check);
+    }
+
+    // Everything below needs local country boundaries, Nominatim only knows about the point
+    let Some(boundaries) = boundaries else {
+        return Ok(Json(response));
+    };
+    response.border_distance_in_meters = border_distance;
+    if let Some(radius) = coordinates.uncertainty_in_meters {
+        let (candidates, unknown) = candidate_countries(
+            &location.country_code,
+            boundaries.countries_within(coordinates.latitude, coordinates.longitude, radius),
+            implementing_countries,
+        )
+        .await;
+        response.ambiguous_location = candidates.len() + unknown.len() > 1;
+        response.candidate_countries = candidates;
+        response.reasons.extend(unknown.iter().map(|code| {
+            format!("{code} is within the coordinate uncertainty but not an ISO 3166 country")
+        }));
+    }
+    Ok(Json(response))
+}
+
+// Compares the declared country with the one resolved from the coordinates. Countries within the
+// tolerance or the coordinate uncertainty and the claimants of a disputed area count as consistent,
+// as the declared country may well be right close to a border
+async fn declared_country_check(
+    declared_country: CountryId,
+    resolved_country: &str,
+    coordinates: &Coordinates,
+    response: &NagoyaResponse,
+    boundaries: Option<&CountryBoundaries>,
+    tolerance: f64,
+    implementing_countries: &ImplementingCountries,
+) -> Result<DeclaredCountryCheck, NagoyaError> {
+    let declared_country = declared_country.alpha2().to_string();
+    let resolved_country = resolved_country.to_uppercase();
+    let radius = coordinates
+        .uncertainty_in_meters
+        .unwrap_or(0.0)
+        .max(tolerance);
+    let consistent = declared_country == resolved_country
+        || response.disputed_area.as_ref().is_some_and(|area| {
+            area.claimants
+                .iter()
+                .any(|claimant| claimant.country_code == declared_country)
+        })
+        || boundaries.is_some_and(|boundaries| {
+            boundaries
+                .countries_within(coordinates.latitude, coordinates.longitude, radius)
+                .contains(&declared_country)
+        });
+    if !consistent {
+        event!(
+            Level::DEBUG,
+            "Declared country {} does not match {} resolved from the coordinates",
+            &declared_country,
+            &resolved_country
+        );
+    }
+    Ok(DeclaredCountryCheck {
+        mismatch: !consistent,
+        declared: CandidateCountry {
+            check_result: is_probe_in_implementing_country(
+                implementing_countries,
+                &declared_country,
+            )
+            .await?,
+            country_code: declared_country,
+        },
+        resolved: CandidateCountry {
+            // Within a disputed area the resolved code may not even be a country, the verdict of
+            // the policy applies instead
+            check_result: match response.disputed_area {
+                Some(_) => response.check_result,
+                None => {
+                    is_probe_in_implementing_country(implementing_countries, &resolved_country)
+                        .await?
+                }
+            },
+            country_code: resolved_country,
+        },
+    })
+}
+
+// Within a disputed area, the verdict is the one for the countries the policy deems applicable
+// rather than for the result of the reverse lookup. Every claimant's status is reported either way.
+// Claimants outside ISO 3166-1 cannot be party to the Nagoya Protocol
+async fn disputed_area_response(
+    area: &DisputedArea,
+    policy: &DisputedAreaPolicy,
+    resolved_country: &str,
+    implementing_countries: &ImplementingCountries,
+) -> NagoyaResponse {
+    let applicable_countries = area.applicable_countries(policy);
+    let mut claimants = Vec::with_capacity(area.claimants.len());
+    for country_code in area.claimants.iter().chain(
+        applicable_countries
+            .iter()
+            .filter(|country_code| !area.claimants.contains(country_code)),
+    ) {
+        claimants.push(ClaimantStatus {
+            country_code: country_code.clone(),
+            check_result: is_probe_in_implementing_country(implementing_countries, country_code)
+                .await
+                .unwrap_or(false),
+            administering: area.administered_by.as_ref() == Some(country_code),
+            applied: applicable_countries.contains(country_code),
+        });
+    }
+    let mut reasons = vec![format!(
+        "Location is within the disputed area \"{}\", applying the {} policy",
+        area.name, policy
+    )];
+    reasons.extend(
+        claimants
+            .iter()
+            .filter(|claimant| claimant.applied)
+            .map(|claimant| party_reason(&claimant.country_code, claimant.check_result)),
+    );
+    event!(
+        Level::DEBUG,
+        "Location is within disputed area \"{}\"",
+        &area.name
+    );
+    NagoyaResponse {
+        check_result: claimants
+            .iter()
+            .any(|claimant| claimant.applied && claimant.check_result),
+        country_code: Some(resolved_country.to_uppercase()),
+        reasons,
+        disputed_area: Some(DisputedAreaInfo {
+            name: area.name.clone(),
+            policy: policy.to_string(),
+            claimants,
+        }),
+        ..Default::default()
+    }
+}
+
+// Evaluates every country within the uncertainty radius. The country resolved by Nominatim is
+// always a candidate, even if the local boundaries disagree at this point. Codes outside ISO
+// 3166-1, e.g. XK in some boundary datasets, cannot be evaluated and are returned separately
+async fn candidate_countries(
+    resolved_country: &str,
+    mut codes: Vec<String>,
+    implementing_countries: &ImplementingCountries,
+) -> (Vec<CandidateCountry>, Vec<String>) {
+    let resolved_country = resolved_country.to_uppercase();
+    if !codes.contains(&resolved_country) {
+        codes.insert(0, resolved_country);
+    }
+    let mut candidates = Vec::with_capacity(codes.len());
+    let mut unknown = Vec::new();
+    for country_code in codes {
+        match is_probe_in_implementing_country(implementing_countries, &country_code).await {
+            Ok(check_result) => candidates.push(CandidateCountry {
+                country_code,
+                check_result,
+            }),
+            Err(_) => unknown.push(country_code),
+        }
+    }
+    (candidates, unknown)
+}
+
+fn party_reason(country_code: &str, check_result: bool) -> String {
+    if check_result {
+        format!("{country_code} is party to the Nagoya Protocol")
+    } else {
+        format!("{country_code} is not party to the Nagoya Protocol")
+    }
 }
 
 #[instrument]
@@ -54,45 +511,47 @@ async fn is_probe_in_implementing_country(
         "Checking whether \"{}\" is implementing the Nagoya Protocol",
         &probe_country
     );
-    let probe_country_code3: &str;
-    if probe_country.len() == 3 {
-        probe_country_code3 = rust_iso3166::from_alpha3(&probe_country.to_uppercase())
-            .ok_or(NagoyaError::MalformedCountryCode)?
-            .alpha3;
-    } else if probe_country.len() == 2 {
-        //TODO: Add error handling (e.g. if correct length, but country code incorrect
-        probe_country_code3 = rust_iso3166::from_alpha2(&probe_country.to_uppercase())
-            .ok_or(NagoyaError::MalformedCountryCode)?
-            .alpha3;
-    } else {
-        // TODO: Fix error handling
-        panic!("Invalid country code")
-    }
-    Ok(implementing_countries
-        .countries
-        .contains(&probe_country_code3.to_uppercase()))
+    let probe_country: CountryId = probe_country.parse()?;
+    Ok(implementing_countries.countries.contains(&probe_country))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::country_names::CountryNames;
+    use crate::disputed_areas::DisputedAreas;
     use std::collections::HashSet;
+
+    fn countries(codes: &[&str]) -> HashSet<CountryId> {
+        codes.iter().map(|code| code.parse().unwrap()).collect()
+    }
+
     #[tokio::test]
     #[allow(clippy::needless_borrow)]
     async fn test_probe_in_implementing_country() {
         let data_included = ImplementingCountries {
-            countries: HashSet::from_iter(vec![String::from("DEU"), String::from("AUS")]),
+            countries: countries(&["DEU", "AUS"]),
+            ..Default::default()
         };
         let data_included_single = ImplementingCountries {
-            countries: HashSet::from_iter(vec![String::from("DEU")]),
+            countries: countries(&["DEU"]),
+            ..Default::default()
         };
         let data_not_included = ImplementingCountries {
-            countries: HashSet::from_iter(vec![String::from("AFG")]),
+            countries: countries(&["AFG"]),
+            ..Default::default()
         };
         let data_empty = ImplementingCountries {
             countries: HashSet::new(),
+            ..Default::default()
         };
         let probe = "DEU";
+        // Numeric codes as in spreadsheets with the leading zero lost
+        assert!(
+            is_probe_in_implementing_country(&data_included, "276")
+                .await
+                .unwrap()
+        );
 
         assert!(
             is_probe_in_implementing_country(&data_included, &probe)
@@ -116,12 +575,332 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_subdivision_probe() {
+        let data = ImplementingCountries {
+            countries: countries(&["AUS"]),
+            ..Default::default()
+        };
+        let regimes = SubnationalRegimes::from_json(
+            r#"{"AU-QLD": [{"name": "Biodiscovery Act 2004 (Qld)"}]}"#,
+        )
+        .unwrap();
+        let Json(response) = nagoya_check_cc(
+            String::from("au-qld").try_into().unwrap(),
+            &data,
+            Some(&regimes),
+        )
+        .await
+        .unwrap();
+        assert!(response.check_result);
+        assert_eq!(response.subdivision, Some(String::from("AU-QLD")));
+        assert_eq!(
+            response.reasons,
+            vec![
+                "AU is party to the Nagoya Protocol",
+                "AU-QLD regulates access with its own regime: Biodiscovery Act 2004 (Qld)"
+            ]
+        );
+        // Unknown subdivisions are rejected when the request is parsed
+        assert_eq!(
+            CountryInput::try_from(String::from("AU-XX")).unwrap_err(),
+            NagoyaError::MalformedCountryCode {
+                input: String::from("AU-XX"),
+                reason: String::from("Unknown ISO 3166-2 subdivision code"),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_provenance() {
+        let data = ImplementingCountries {
+            countries: countries(&["BRA"]),
+            ..Default::default()
+        };
+        let country = |country_code: &str, role| ProvenanceCountry {
+            country_code: CountryInput::try_from(country_code.to_string()).unwrap(),
+            role,
+        };
+
+        // Brazilian material provided by Germany, which is not party in this test
+        let Json(response) = nagoya_check_provenance(
+            vec![
+                country("BR", ProvenanceRole::CountryOfOrigin),
+                country("DE", ProvenanceRole::ProvidingCountry),
+                country("NLD", ProvenanceRole::Intermediary),
+            ],
+            &data,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(response.check_result);
+        assert_eq!(
+            response
+                .provenance
+                .iter()
+                .map(|country| (country.country_code.as_str(), country.applied))
+                .collect::<Vec<_>>(),
+            vec![("BR", true), ("DE", true), ("NL", false)]
+        );
+
+        // An intermediary alone is all there is to go by
+        let Json(response) = nagoya_check_provenance(
+            vec![country("BR", ProvenanceRole::Intermediary)],
+            &data,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(response.check_result);
+
+        let Json(response) = nagoya_check_provenance(
+            vec![
+                country("DE", ProvenanceRole::CountryOfOrigin),
+                country("BR", ProvenanceRole::Intermediary),
+            ],
+            &data,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(!response.check_result);
+
+        assert_eq!(
+            nagoya_check_provenance(Vec::new(), &data, None)
+                .await
+                .unwrap_err(),
+            NagoyaError::MissingCountry
+        );
+    }
+
+    #[tokio::test]
+    async fn test_country_name_probe() {
+        let data = ImplementingCountries {
+            countries: countries(&["BRA"]),
+            names: CountryNames::new(vec![(String::from("Brasil"), String::from("BRA"))]),
+        };
+        let Json(response) =
+            nagoya_check_cc(String::from("Brasil").try_into().unwrap(), &data, None)
+                .await
+                .unwrap();
+        assert!(response.check_result);
+        assert_eq!(response.name_resolution.unwrap().country_code, "BRA");
+
+        // Guinea-Bissau contains a hyphen, but is no subdivision code
+        let Json(response) = nagoya_check_cc(
+            String::from("Guinea-Bissau").try_into().unwrap(),
+            &data,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.name_resolution.unwrap().country_code, "GNB");
+
+        let error = nagoya_check_cc(String::from("Congo").try_into().unwrap(), &data, None)
+            .await
+            .unwrap_err();
+        let NagoyaError::AmbiguousCountryName { alternatives, .. } = &error else {
+            panic!("{error:?}");
+        };
+        assert_eq!(alternatives.len(), 2);
+        assert_eq!(error.error_response().alternatives, *alternatives);
+    }
+
+    #[tokio::test]
+    async fn test_declared_country_check() {
+        let data = ImplementingCountries {
+            countries: countries(&["DEU"]),
+            ..Default::default()
+        };
+        let boundaries = CountryBoundaries::from_geojson(
+            r#"
+            {
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "properties": { "ISO_A2": "DE" },
+                        "geometry": {
+                            "type": "Polygon",
+                            "coordinates": [[[9, 0], [10, 0], [10, 1], [9, 1], [9, 0]]]
+                        }
+                    },
+                    {
+                        "type": "Feature",
+                        "properties": { "ISO_A2": "CH" },
+                        "geometry": {
+                            "type": "Polygon",
+                            "coordinates": [[[10, 0], [11, 0], [11, 1], [10, 1], [10, 0]]]
+                        }
+                    }
+                ]
+            }
+            "#,
+        )
+        .unwrap();
+        let coordinates = Coordinates {
+            latitude: 0.5,
+            longitude: 9.99,
+            uncertainty_in_meters: None,
+        };
+        let response = NagoyaResponse::default();
+
+        // About 1 km from the border, so Switzerland is consistent within the tolerance
+        let check = declared_country_check(
+            "CH".parse().unwrap(),
+            "de",
+            &coordinates,
+            &response,
+            Some(&boundaries),
+            2_000.0,
+            &data,
+        )
+        .await
+        .unwrap();
+        assert!(!check.mismatch);
+        assert!(check.resolved.check_result);
+        assert!(!check.declared.check_result);
+
+        let check = declared_country_check(
+            "CH".parse().unwrap(),
+            "de",
+            &coordinates,
+            &response,
+            Some(&boundaries),
+            500.0,
+            &data,
+        )
+        .await
+        .unwrap();
+        assert!(check.mismatch);
+
+        // Without boundaries only an exact match is consistent
+        let check = declared_country_check(
+            "DE".parse().unwrap(),
+            "de",
+            &coordinates,
+            &response,
+            None,
+            2_000.0,
+            &data,
+        )
+        .await
+        .unwrap();
+        assert!(!check.mismatch);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_candidate_country() {
+        let data = ImplementingCountries {
+            countries: countries(&["ALB"]),
+            ..Default::default()
+        };
+        let (candidates, unknown) =
+            candidate_countries("rs", vec![String::from("AL"), String::from("XK")], &data).await;
+        assert_eq!(
+            candidates,
+            vec![
+                CandidateCountry {
+                    country_code: String::from("RS"),
+                    check_result: false
+                },
+                CandidateCountry {
+                    country_code: String::from("AL"),
+                    check_result: true
+                },
+            ]
+        );
+        assert_eq!(unknown, vec!["XK"]);
+    }
+
+    #[tokio::test]
+    async fn test_disputed_area_response() {
+        let disputed_areas = DisputedAreas::from_geojson(
+            r#"
+            {
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "properties": {
+                            "name": "Kosovo",
+                            "claimants": ["RS", "XK"],
+                            "administered_by": "XK"
+                        },
+                        "geometry": {
+                            "type": "Polygon",
+                            "coordinates": [[[20, 42], [21, 42], [21, 43], [20, 43], [20, 42]]]
+                        }
+                    }
+                ]
+            }
+            "#,
+            DisputedAreaPolicy::AllClaimants,
+        )
+        .unwrap();
+        let data = ImplementingCountries {
+            countries: countries(&["SRB"]),
+            ..Default::default()
+        };
+        let area = disputed_areas.area_at(42.5, 20.5).unwrap();
+        // Nominatim resolves Kosovo to xk, which is no ISO 3166-1 code
+        let response = disputed_area_response(area, &disputed_areas.policy, "xk", &data).await;
+        assert!(response.check_result);
+        assert_eq!(response.country_code.as_deref(), Some("XK"));
+        assert_eq!(
+            response.reasons,
+            vec![
+                "Location is within the disputed area \"Kosovo\", applying the all_claimants policy",
+                "RS is party to the Nagoya Protocol",
+                "XK is not party to the Nagoya Protocol",
+            ]
+        );
+
+        let response =
+            disputed_area_response(area, &DisputedAreaPolicy::DeFacto, "xk", &data).await;
+        assert!(!response.check_result);
+        assert_eq!(
+            response.reasons.last().map(String::as_str),
+            Some("XK is not party to the Nagoya Protocol")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_consensus_unavailable() {
+        // The test state has no consensus geocoders, so Nominatim is never asked
+        let request = NagoyaCheckDataGeo {
+            coordinates: CoordinateInput::Decimal(Coordinates {
+                latitude: 49.0,
+                longitude: 8.4,
+                uncertainty_in_meters: None,
+            }),
+            epsg: None,
+            event_date: None,
+            declared_country: None,
+            consensus: true,
+            require_agreement: None,
+        };
+        assert_eq!(
+            nagoya_check_geo(
+                request,
+                &ImplementingCountries::default(),
+                &crate::batch::tests::state(10),
+                Queueing::Reject
+            )
+            .await
+            .unwrap_err(),
+            NagoyaError::ConsensusUnavailable { geocoders: 0 }
+        );
+    }
+
     #[tokio::test]
     #[allow(clippy::needless_borrow)]
     async fn test_malformed_probe() {
         // Test is about handling of an illegal country code, so the contents of the data do not matter
         let data_empty = ImplementingCountries {
             countries: HashSet::new(),
+            ..Default::default()
         };
 
         let malformed_probe = "XYZ";
@@ -134,7 +913,15 @@ mod tests {
             //    .unwrap_err()
             //    .downcast::<NagoyaError>()
             //    .unwrap(),
-            NagoyaError::MalformedCountryCode
-        )
+            NagoyaError::MalformedCountryCode {
+                input: String::from("XYZ"),
+                reason: String::from("Unknown alpha-3 code"),
+            }
+        );
+        // Used to panic
+        assert!(matches!(
+            is_probe_in_implementing_country(&data_empty, "Deutschland").await,
+            Err(NagoyaError::MalformedCountryCode { .. })
+        ));
     }
 }
@@ -0,0 +1,493 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::country::CountryId;
+use crate::country_names::{CountryNames, normalize_name};
+use crate::models::{CanonicalCountry, NagoyaError, NormalizedCountry, looks_like_code};
+use rust_iso3166::iso3166_3::CountryCode3;
+
+// Territories with an ISO 3166-1 code of their own and the alpha-2 code of their sovereign state
+const TERRITORIES: [(&str, &str); 49] = [
+    ("AS", "US"),
+    ("GU", "US"),
+    ("MP", "US"),
+    ("PR", "US"),
+    ("UM", "US"),
+    ("VI", "US"),
+    ("AI", "GB"),
+    ("BM", "GB"),
+    ("FK", "GB"),
+    ("GG", "GB"),
+    ("GI", "GB"),
+    ("GS", "GB"),
+    ("IM", "GB"),
+    ("IO", "GB"),
+    ("JE", "GB"),
+    ("KY", "GB"),
+    ("MS", "GB"),
+    ("PN", "GB"),
+    ("SH", "GB"),
+    ("TC", "GB"),
+    ("VG", "GB"),
+    ("BL", "FR"),
+    ("GF", "FR"),
+    ("GP", "FR"),
+    ("MF", "FR"),
+    ("MQ", "FR"),
+    ("NC", "FR"),
+    ("PF", "FR"),
+    ("PM", "FR"),
+    ("RE", "FR"),
+    ("TF", "FR"),
+    ("WF", "FR"),
+    ("YT", "FR"),
+    ("AW", "NL"),
+    ("BQ", "NL"),
+    ("CW", "NL"),
+    ("SX", "NL"),
+    ("FO", "DK"),
+    ("GL", "DK"),
+    ("BV", "NO"),
+    ("SJ", "NO"),
+    ("AX", "FI"),
+    ("TK", "NZ"),
+    ("CC", "AU"),
+    ("CX", "AU"),
+    ("HM", "AU"),
+    ("NF", "AU"),
+    ("HK", "CN"),
+    ("MO", "CN"),
+];
+
+// Areas without an undisputed sovereign state
+const NO_SOVEREIGN: [(&str, &str); 3] = [
+    (
+        "AQ",
+        "Antarctica is governed by the Antarctic Treaty System",
+    ),
+    ("EH", "Sovereignty over Western Sahara is disputed"),
+    ("TW", "The status of Taiwan is disputed"),
+];
+
+// ISO 3166-2 codes of territories with an ISO 3166-1 code of their own, besides the ones made up
+// of the sovereign's and the territory's code such as FR-GF
+const TERRITORY_SUBDIVISIONS: [(&str, &str); 11] = [
+    ("FI-01", "AX"),
+    ("FR-971", "GP"),
+    ("FR-972", "MQ"),
+    ("FR-973", "GF"),
+    ("FR-974", "RE"),
+    ("FR-976", "YT"),
+    ("NL-BQ1", "BQ"),
+    ("NL-BQ2", "BQ"),
+    ("NL-BQ3", "BQ"),
+    ("NO-21", "SJ"),
+    ("NO-22", "SJ"),
+];
+
+// Exceptionally reserved ISO 3166-1 codes in use outside of ISO 3166
+const RESERVED_CODES: [(&str, &str, &str); 8] = [
+    ("AC", "SH", "Ascension Island"),
+    ("CP", "FR", "Clipperton Island"),
+    ("DG", "IO", "Diego Garcia"),
+    ("EA", "ES", "Ceuta and Melilla"),
+    ("EL", "GR", "Greece in EU usage"),
+    ("IC", "ES", "the Canary Islands"),
+    ("TA", "SH", "Tristan da Cunha"),
+    ("UK", "GB", "the United Kingdom"),
+];
+
+// Names of the INSDC country vocabulary the ISO 3166 names do not cover, including islands without
+// a code of their own
+const INSDC_NAMES: [(&str, &[&str]); 53] = [
+    ("Akrotiri", &["GB"]),
+    ("Ashmore and Cartier Islands", &["AU"]),
+    ("Baker Island", &["UM"]),
+    ("Bassas da India", &["TF"]),
+    ("Bolivia", &["BO"]),
+    ("Borneo", &["BN", "ID", "MY"]),
+    ("Brunei", &["BN"]),
+    ("Cape Verde", &["CV"]),
+    ("Clipperton Island", &["FR"]),
+    ("Coral Sea Islands", &["AU"]),
+    ("Czech Republic", &["CZ"]),
+    ("Democratic Republic of the Congo", &["CD"]),
+    ("Dhekelia", &["GB"]),
+    ("East Timor", &["TL"]),
+    ("Europa Island", &["TF"]),
+    ("Falkland Islands (Islas Malvinas)", &["FK"]),
+    ("Gaza Strip", &["PS"]),
+    ("Glorioso Islands", &["TF"]),
+    ("Howland Island", &["UM"]),
+    ("Iran", &["IR"]),
+    ("Jan Mayen", &["SJ"]),
+    ("Jarvis Island", &["UM"]),
+    ("Johnston Atoll", &["UM"]),
+    ("Juan de Nova Island", &["TF"]),
+    ("Kerguelen Archipelago", &["TF"]),
+    ("Kingman Reef", &["UM"]),
+    ("Laos", &["LA"]),
+    ("Line Islands", &["KI"]),
+    ("Macedonia", &["MK"]),
+    ("Micronesia, Federated States of", &["FM"]),
+    ("Midway Islands", &["UM"]),
+    ("Moldova", &["MD"]),
+    ("Navassa Island", &["UM"]),
+    ("North Korea", &["KP"]),
+    ("Palmyra Atoll", &["UM"]),
+    ("Paracel Islands", &["CN", "TW", "VN"]),
+    ("Republic of the Congo", &["CG"]),
+    ("Russia", &["RU"]),
+    ("South Korea", &["KR"]),
+    ("Spratly Islands", &["BN", "CN", "MY", "PH", "TW", "VN"]),
+    ("Svalbard", &["SJ"]),
+    ("Swaziland", &["SZ"]),
+    ("Syria", &["SY"]),
+    ("Taiwan", &["TW"]),
+    ("Tanzania", &["TZ"]),
+    ("Tromelin Island", &["TF"]),
+    ("Turkey", &["TR"]),
+    ("USA", &["US"]),
+    ("Venezuela", &["VE"]),
+    ("Viet Nam", &["VN"]),
+    ("Vietnam", &["VN"]),
+    ("Virgin Islands", &["VI"]),
+    ("West Bank", &["PS"]),
+];
+
+const MARINE_AREA: &str =
+    "Marine area, the country depends on whether the location lies within national jurisdiction";
+
+// Entries of the INSDC country vocabulary and codes in common use which are no country
+const NOT_COUNTRIES: [(&str, &str); 14] = [
+    ("Arctic Ocean", MARINE_AREA),
+    ("Atlantic Ocean", MARINE_AREA),
+    ("Baltic Sea", MARINE_AREA),
+    ("Indian Ocean", MARINE_AREA),
+    ("Mediterranean Sea", MARINE_AREA),
+    ("North Sea", MARINE_AREA),
+    ("Pacific Ocean", MARINE_AREA),
+    ("Ross Sea", MARINE_AREA),
+    ("Southern Ocean", MARINE_AREA),
+    ("Tasman Sea", MARINE_AREA),
+    ("Kosovo", "Kosovo has no ISO 3166-1 code"),
+    (
+        "XK",
+        "XK is a user-assigned code for Kosovo, which has no ISO 3166-1 code",
+    ),
+    (
+        "European Union",
+        "The European Union is party to the Nagoya Protocol, but no country",
+    ),
+    (
+        "EU",
+        "The European Union is party to the Nagoya Protocol, but no country",
+    ),
+];
+
+fn sovereign_state(alpha2: &str) -> Option<&'static str> {
+    TERRITORIES
+        .iter()
+        .find(|(territory, _)| *territory == alpha2)
+        .map(|(_, sovereign)| *sovereign)
+}
+
+fn canonical(country: CountryId) -> CanonicalCountry {
+    let alpha2 = country.alpha2();
+    CanonicalCountry {
+        alpha2: alpha2.to_string(),
+        alpha3: country.alpha3().to_string(),
+        numeric: country.numeric(),
+        name: country.name().to_string(),
+        sovereign_state: if NO_SOVEREIGN.iter().any(|(area, _)| *area == alpha2) {
+            None
+        } else {
+            Some(sovereign_state(alpha2).unwrap_or(alpha2).to_string())
+        },
+    }
+}
+
+// Codes from the tables above are valid ISO 3166-1 codes
+fn country_id(alpha2: &str) -> CountryId {
+    alpha2
+        .parse()
+        .expect("Country tables contain ISO 3166-1 codes only")
+}
+
+// Withdrawn countries matching a former code or name, e.g. SU, SUN, SUHH or USSR
+fn withdrawn_countries(input: &str) -> Vec<&'static CountryCode3> {
+    let code = input.to_uppercase();
+    let name = normalize_name(input);
+    rust_iso3166::iso3166_3::ALL
+        .iter()
+        .filter(|withdrawn| {
+            withdrawn.code == code
+                || withdrawn.former.alpha2 == code
+                || withdrawn.former.alpha3 == code
+                || normalize_name(withdrawn.name) == name
+        })
+        .collect()
+}
+
+// Current countries succeeding a withdrawn one, following renamings such as Yugoslavia to Serbia and
+// Montenegro, which was itself divided later
+fn successors(withdrawn: &CountryCode3) -> Vec<CountryId> {
+    let mut countries = Vec::new();
+    for new_country in withdrawn.new_countries {
+        match new_country.alpha3.parse::<CountryId>() {
+            Ok(country) => countries.push(country),
+            Err(_) => countries.extend(
+                withdrawn_countries(new_country.alpha3)
+                    .into_iter()
+                    .filter(|next| next.code != withdrawn.code)
+                    .flat_map(successors),
+            ),
+        }
+    }
+    countries
+}
+
+// Maps a country given in any of the ways found in provenance fields to the current ISO 3166-1
+// country, noting every step that goes beyond reading a current code
+pub fn normalize_country(
+    input: &str,
+    names: &CountryNames,
+) -> Result<NormalizedCountry, NagoyaError> {
+    let mut normalized = NormalizedCountry {
+        input: input.to_string(),
+        ..Default::default()
+    };
+    // INSDC strings name the country first, followed by the locality, e.g. "Brazil: Amazonas"
+    let country = match input.split_once(':') {
+        Some((country, locality)) => {
+            normalized.locality = Some(locality.trim().to_string()).filter(|l| !l.is_empty());
+            country.trim()
+        }
+        None => input.trim(),
+    };
+    let name = normalize_name(country);
+    if name.is_empty() {
+        return Err(NagoyaError::UnknownCountryName {
+            name: input.to_string(),
+        });
+    }
+    if let Some((_, reason)) = NOT_COUNTRIES
+        .iter()
+        .find(|(entry, _)| normalize_name(entry) == name)
+    {
+        return Err(NagoyaError::NotACountry {
+            input: input.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    let mut countries = Vec::new();
+    let code = country.to_uppercase();
+    if let Some((reserved, alpha2, description)) = RESERVED_CODES
+        .iter()
+        .find(|(reserved, _, _)| *reserved == code)
+    {
+        normalized.notes.push(format!(
+            "{reserved} is exceptionally reserved for {description} and belongs to {alpha2}"
+        ));
+        countries.push(country_id(alpha2));
+    } else if let Some((entry, codes)) = INSDC_NAMES
+        .iter()
+        .find(|(entry, _)| normalize_name(entry) == name)
+    {
+        if codes.len() > 1 {
+            normalized
+                .notes
+                .push(format!("{entry} may refer to {}", codes.join(", ")));
+        }
+        countries.extend(codes.iter().map(|alpha2| country_id(alpha2)));
+    } else {
+        let country = country.trim().to_string();
+        match (looks_like_code(&country), country.contains('-')) {
+            (true, true) => {
+                let code = country.to_uppercase();
+                let territory = TERRITORY_SUBDIVISIONS
+                    .iter()
+                    .find(|(subdivision, _)| *subdivision == code)
+                    .map(|(_, territory)| *territory)
+                    .or_else(|| {
+                        let (sovereign, territory) = code.split_once('-')?;
+                        (sovereign_state(territory) == Some(sovereign)).then_some(territory)
+                    });
+                match territory {
+                    Some(territory) => {
+                        normalized.notes.push(format!(
+                            "{code} is a territory with the ISO 3166-1 code {territory}"
+                        ));
+                        countries.push(country_id(territory));
+                    }
+                    None => {
+                        let subdivision =
+                            rust_iso3166::iso3166_2::from_code(&code).ok_or_else(|| {
+                                NagoyaError::MalformedCountryCode {
+                                    input: code.clone(),
+                                    reason: String::from("Unknown ISO 3166-2 subdivision code"),
+                                }
+                            })?;
+                        normalized.subdivision = Some(subdivision.code.to_string());
+                        countries.push(subdivision.country_code.parse()?);
+                    }
+                }
+            }
+            (true, false) => match country.parse::<CountryId>() {
+                Ok(country) => countries.push(country),
+                Err(error) => {
+                    let withdrawn = withdrawn_countries(&country);
+                    if withdrawn.is_empty() {
+                        return Err(error);
+                    }
+                    push_successors(&mut normalized, &mut countries, &withdrawn);
+                }
+            },
+            (false, _) => {
+                let name = country;
+                let withdrawn = withdrawn_countries(&name);
+                if withdrawn.is_empty() {
+                    let resolution = names.resolve(&name)?;
+                    countries.push(resolution.country_code.parse()?);
+                    // Ambiguous names like "Congo" leave all near matches as candidates
+                    for alternative in &resolution.alternatives {
+                        countries.push(alternative.country_code.parse()?);
+                    }
+                    normalized.name_resolution = Some(resolution);
+                } else {
+                    push_successors(&mut normalized, &mut countries, &withdrawn);
+                }
+            }
+        }
+    }
+
+    countries.sort_by_key(|country| country.alpha2());
+    countries.dedup();
+    if let [country] = countries[..] {
+        let alpha2 = country.alpha2();
+        if let Some((_, note)) = NO_SOVEREIGN.iter().find(|(area, _)| *area == alpha2) {
+            normalized.notes.push(note.to_string());
+        } else if let Some(sovereign) = sovereign_state(alpha2) {
+            normalized.notes.push(format!(
+                "{} is a territory of {}",
+                country.name(),
+                country_id(sovereign).name()
+            ));
+        }
+        normalized.country = Some(canonical(country));
+    } else {
+        normalized.candidates = countries.into_iter().map(canonical).collect();
+    }
+    Ok(normalized)
+}
+
+fn push_successors(
+    normalized: &mut NormalizedCountry,
+    countries: &mut Vec<CountryId>,
+    withdrawn: &[&CountryCode3],
+) {
+    for withdrawn in withdrawn {
+        normalized.notes.push(format!(
+            "{} ({}) was withdrawn from ISO 3166-1 in {}: {}",
+            withdrawn.former.alpha2,
+            withdrawn.name,
+            withdrawn.validity.last().copied().unwrap_or_default(),
+            withdrawn.desc.trim()
+        ));
+        countries.extend(successors(withdrawn));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(input: &str) -> NormalizedCountry {
+        normalize_country(input, &CountryNames::default()).unwrap()
+    }
+
+    fn alpha2(normalized: &NormalizedCountry) -> Option<&str> {
+        normalized
+            .country
+            .as_ref()
+            .map(|country| country.alpha2.as_str())
+    }
+
+    #[test]
+    fn test_current_codes_and_names() {
+        let brazil = normalize("076");
+        assert_eq!(
+            brazil.country,
+            Some(CanonicalCountry {
+                alpha2: String::from("BR"),
+                alpha3: String::from("BRA"),
+                numeric: String::from("076"),
+                name: String::from("Brazil"),
+                sovereign_state: Some(String::from("BR")),
+            })
+        );
+        assert!(brazil.notes.is_empty());
+        assert_eq!(alpha2(&normalize("Germany")), Some("DE"));
+        assert_eq!(alpha2(&normalize("UK")), Some("GB"));
+
+        let congo = normalize("Congo");
+        assert_eq!(congo.country, None);
+        let candidates: Vec<&str> = congo
+            .candidates
+            .iter()
+            .map(|candidate| candidate.alpha2.as_str())
+            .collect();
+        assert_eq!(candidates, vec!["CD", "CG"]);
+
+        let queensland = normalize("AU-QLD");
+        assert_eq!(alpha2(&queensland), Some("AU"));
+        assert_eq!(queensland.subdivision.as_deref(), Some("AU-QLD"));
+    }
+
+    #[test]
+    fn test_withdrawn_codes() {
+        assert_eq!(alpha2(&normalize("ZR")), Some("CD"));
+        assert_eq!(alpha2(&normalize("Burma")), Some("MM"));
+        assert_eq!(alpha2(&normalize("DDR")), Some("DE"));
+
+        // Yugoslavia became Serbia and Montenegro, which was divided in turn
+        let yugoslavia = normalize("YU");
+        assert!(yugoslavia.country.is_none());
+        assert_eq!(
+            yugoslavia
+                .candidates
+                .iter()
+                .map(|country| country.alpha2.as_str())
+                .collect::<Vec<_>>(),
+            vec!["ME", "RS"]
+        );
+        assert!(!yugoslavia.notes.is_empty());
+    }
+
+    #[test]
+    fn test_territories() {
+        let french_guiana = normalize("FR-973");
+        assert_eq!(alpha2(&french_guiana), Some("GF"));
+        assert_eq!(
+            french_guiana.country.unwrap().sovereign_state.as_deref(),
+            Some("FR")
+        );
+        assert_eq!(alpha2(&normalize("us-pr")), Some("PR"));
+        assert_eq!(normalize("AQ").country.unwrap().sovereign_state, None);
+    }
+
+    #[test]
+    fn test_insdc_strings() {
+        let brazil = normalize("Brazil: Amazonas, Manaus");
+        assert_eq!(alpha2(&brazil), Some("BR"));
+        assert_eq!(brazil.locality.as_deref(), Some("Amazonas, Manaus"));
+        assert_eq!(alpha2(&normalize("USA: Alaska")), Some("US"));
+        assert_eq!(normalize("Borneo").candidates.len(), 3);
+        assert!(matches!(
+            normalize_country("Pacific Ocean: Mariana Trench", &CountryNames::default()),
+            Err(NagoyaError::NotACountry { .. })
+        ));
+    }
+}
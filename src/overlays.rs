@@ -0,0 +1,258 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::boundaries::{EARTH_RADIUS, PolygonFeature, polygon_features};
+use crate::models::OverlayMatch;
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{AABB, RTree};
+use serde::Deserialize;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+use tracing::{Level, event, instrument};
+
+// Named GeoJSON layers with additional access restrictions, e.g. protected areas (WDPA),
+// indigenous territories or military zones. The layers are listed in a JSON manifest:
+// [{"name": "WDPA", "path": "wdpa.geojson", "source": "...", "licence": "...", "advisory": "..."}]
+// Relative paths are resolved against the manifest's directory
+pub struct OverlayLayers {
+    manifest_path: PathBuf,
+    layers: RwLock<Arc<Vec<OverlayLayer>>>,
+    // Latest modification time of the manifest and the layer files at the last load
+    loaded: Mutex<Option<SystemTime>>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct OverlayMetadata {
+    pub name: String,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub licence: Option<String>,
+    #[serde(default)]
+    pub advisory: Option<String>,
+    // Feature property holding the name of e.g. the protected area
+    #[serde(default = "default_name_property")]
+    pub name_property: String,
+}
+
+fn default_name_property() -> String {
+    String::from("name")
+}
+
+struct OverlayLayer {
+    metadata: OverlayMetadata,
+    features: Vec<PolygonFeature>,
+    // Bounding rectangles of the features, pointing to their index
+    index: RTree<GeomWithData<Rectangle<[f64; 2]>, usize>>,
+}
+
+impl OverlayLayers {
+    #[instrument]
+    pub fn from_manifest(path: &str) -> Result<Self, Box<dyn Error>> {
+        let manifest_path = PathBuf::from(path);
+        let modified = last_modified(&manifest_path)?;
+        let layers = load_layers(&manifest_path)?;
+        Ok(Self {
+            manifest_path,
+            layers: RwLock::new(Arc::new(layers)),
+            loaded: Mutex::new(Some(modified)),
+        })
+    }
+
+    // Reloads all layers if the manifest or a layer file changed since the last load. Requests
+    // keep being answered with the previous layers until the new ones are ready
+    #[instrument(skip(self))]
+    pub fn reload_if_changed(&self) -> Result<bool, Box<dyn Error>> {
+        let modified = last_modified(&self.manifest_path)?;
+        if *self.loaded.lock().unwrap() == Some(modified) {
+            return Ok(false);
+        }
+        let layers = load_layers(&self.manifest_path)?;
+        *self.layers.write().unwrap() = Arc::new(layers);
+        *self.loaded.lock().unwrap() = Some(modified);
+        Ok(true)
+    }
+
+    // Every layer with features at the point or, given a radius, within it
+    pub fn matches(&self, latitude: f64, longitude: f64, radius: Option<f64>) -> Vec<OverlayMatch> {
+        let radius = radius.unwrap_or(0.0);
+        let envelope = envelope(latitude, longitude, radius);
+        let layers = self.layers.read().unwrap().clone();
+        layers
+            .iter()
+            .filter_map(|layer| {
+                let matched: Vec<&PolygonFeature> = layer
+                    .index
+                    .locate_in_envelope_intersecting(&envelope)
+                    .map(|entry| &layer.features[entry.data])
+                    .filter(|feature| feature.within(latitude, longitude, radius))
+                    .collect();
+                if matched.is_empty() {
+                    return None;
+                }
+                // Unnamed features are still a match, they are just not listed
+                let mut features: Vec<String> = matched
+                    .iter()
+                    .filter_map(|feature| feature.property(&layer.metadata.name_property))
+                    .map(str::to_string)
+                    .collect();
+                features.sort();
+                features.dedup();
+                Some(OverlayMatch {
+                    layer: layer.metadata.name.clone(),
+                    source: layer.metadata.source.clone(),
+                    licence: layer.metadata.licence.clone(),
+                    advisory: layer.metadata.advisory.clone(),
+                    features,
+                })
+            })
+            .collect()
+    }
+}
+
+fn load_layers(manifest_path: &Path) -> Result<Vec<OverlayLayer>, Box<dyn Error>> {
+    let base = manifest_path.parent().unwrap_or(Path::new("."));
+    let manifest: Vec<OverlayMetadata> =
+        serde_json::from_str(&std::fs::read_to_string(manifest_path)?)?;
+    let mut layers = Vec::with_capacity(manifest.len());
+    for metadata in manifest {
+        let geojson = std::fs::read_to_string(base.join(&metadata.path))
+            .map_err(|error| format!("Could not read layer \"{}\": {error}", metadata.name))?;
+        layers.push(OverlayLayer::from_geojson(metadata, &geojson)?);
+    }
+    Ok(layers)
+}
+
+// Latest modification time of the manifest and the layer files it lists
+fn last_modified(manifest_path: &Path) -> Result<SystemTime, Box<dyn Error>> {
+    let base = manifest_path.parent().unwrap_or(Path::new("."));
+    let mut modified = std::fs::metadata(manifest_path)?.modified()?;
+    let manifest: Vec<OverlayMetadata> =
+        serde_json::from_str(&std::fs::read_to_string(manifest_path)?)?;
+    for metadata in manifest {
+        if let Ok(layer_modified) =
+            std::fs::metadata(base.join(&metadata.path)).and_then(|file| file.modified())
+        {
+            modified = modified.max(layer_modified);
+        }
+    }
+    Ok(modified)
+}
+
+impl OverlayLayer {
+    fn from_geojson(metadata: OverlayMetadata, geojson: &str) -> Result<Self, Box<dyn Error>> {
+        let features = polygon_features(geojson)?;
+        let index = RTree::bulk_load(
+            features
+                .iter()
+                .enumerate()
+                .map(|(position, feature)| {
+                    let (min, max) = (feature.bounding_rect.min(), feature.bounding_rect.max());
+                    GeomWithData::new(
+                        Rectangle::from_corners([min.x, min.y], [max.x, max.y]),
+                        position,
+                    )
+                })
+                .collect(),
+        );
+        event!(
+            Level::INFO,
+            "Loaded {} features for overlay layer \"{}\"",
+            features.len(),
+            &metadata.name
+        );
+        Ok(Self {
+            metadata,
+            features,
+            index,
+        })
+    }
+}
+
+// Bounding box in degrees of the radius around the point
+fn envelope(latitude: f64, longitude: f64, radius: f64) -> AABB<[f64; 2]> {
+    let d_latitude = radius / EARTH_RADIUS.to_radians();
+    let d_longitude = d_latitude / latitude.to_radians().cos().max(1e-6);
+    AABB::from_corners(
+        [longitude - d_longitude, latitude - d_latitude],
+        [longitude + d_longitude, latitude + d_latitude],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TESTDATA: &str = r#"
+    {
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "properties": { "NAME": "Test Park" },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[9, 0], [10, 0], [10, 1], [9, 1], [9, 0]]]
+                }
+            }
+        ]
+    }
+    "#;
+
+    fn write_layers(directory: &Path) -> PathBuf {
+        std::fs::create_dir_all(directory).unwrap();
+        std::fs::write(directory.join("parks.geojson"), TESTDATA).unwrap();
+        let manifest = directory.join("overlays.json");
+        std::fs::write(
+            &manifest,
+            r#"[{"name": "Parks", "path": "parks.geojson", "licence": "CC-BY", "advisory": "Permit needed", "name_property": "NAME"}]"#,
+        )
+        .unwrap();
+        manifest
+    }
+
+    #[test]
+    fn test_matches() {
+        let directory = std::env::temp_dir().join("nagoya_overlays_test_matches");
+        let manifest = write_layers(&directory);
+        let overlays = OverlayLayers::from_manifest(manifest.to_str().unwrap()).unwrap();
+        assert_eq!(
+            overlays.matches(0.5, 9.5, None),
+            vec![OverlayMatch {
+                layer: String::from("Parks"),
+                source: None,
+                licence: Some(String::from("CC-BY")),
+                advisory: Some(String::from("Permit needed")),
+                features: vec![String::from("Test Park")],
+            }]
+        );
+        assert!(overlays.matches(0.5, 10.05, None).is_empty());
+        // About 5.5 km from the park, so only within a larger radius
+        assert!(overlays.matches(0.5, 10.05, Some(1_000.0)).is_empty());
+        assert_eq!(overlays.matches(0.5, 10.05, Some(10_000.0)).len(), 1);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_reload() {
+        let directory = std::env::temp_dir().join("nagoya_overlays_test_reload");
+        let manifest = write_layers(&directory);
+        let overlays = OverlayLayers::from_manifest(manifest.to_str().unwrap()).unwrap();
+        assert!(!overlays.reload_if_changed().unwrap());
+        std::fs::write(&manifest, "[]").unwrap();
+        // Make sure the change is visible even on file systems with coarse timestamps
+        std::fs::File::options()
+            .write(true)
+            .open(&manifest)
+            .unwrap()
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        assert!(overlays.reload_if_changed().unwrap());
+        assert!(overlays.matches(0.5, 9.5, None).is_empty());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::models::NagoyaError;
+use std::time::Duration;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::Instant;
+
+// Global rate limiter spacing requests to an external service evenly. Callers wait for their slot
+// in a bounded queue; if the queue is full, they are turned away instead of piling up
+pub struct RateLimiter {
+    interval: Duration,
+    queue_size: usize,
+    queue: Semaphore,
+    next_slot: Mutex<Instant>,
+}
+
+// Whether callers finding the queue full are turned away or wait for room
+#[derive(Clone, Copy, Debug)]
+pub enum Queueing {
+    // Single requests, whose clients can retry after the time given
+    Reject,
+    // Items of a batch, which clients cannot retry one by one
+    Wait,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64, queue_size: usize) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            queue_size,
+            queue: Semaphore::new(queue_size),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Waits until the next request may be sent
+    pub async fn acquire(&self) -> Result<(), NagoyaError> {
+        let _permit = self
+            .queue
+            .try_acquire()
+            .map_err(|_| NagoyaError::RateLimited {
+                retry_after: self.retry_after(),
+            })?;
+        self.wait_for_slot().await;
+        Ok(())
+    }
+
+    // Like acquire, but waits for room in a full queue instead of turning the caller away. The
+    // permit is held until the slot, so no other caller can take the place in between
+    pub async fn acquire_waiting(&self) {
+        // The semaphore is never closed, and waiting callers are let in in order
+        let _permit = self.queue.acquire().await;
+        self.wait_for_slot().await;
+    }
+
+    async fn wait_for_slot(&self) {
+        // The mutex is fair, so waiting callers get their slots in order
+        let mut next_slot = self.next_slot.lock().await;
+        if *next_slot > Instant::now() {
+            tokio::time::sleep_until(*next_slot).await;
+        }
+        *next_slot = Instant::now() + self.interval;
+    }
+
+    // Time in seconds until a full queue has been worked off
+    fn retry_after(&self) -> u64 {
+        // Queues too long for a u32 take practically forever anyway
+        u32::try_from(self.queue_size)
+            .map_or(Duration::MAX, |queue_size| {
+                self.interval.saturating_mul(queue_size)
+            })
+            .as_secs_f64()
+            .ceil()
+            .max(1.0) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(RateLimiter::new(1.0, 60).retry_after(), 60);
+        assert_eq!(RateLimiter::new(20.0, 10).retry_after(), 1);
+        // Larger than u32::MAX, which must not wrap around
+        assert!(RateLimiter::new(1.0, 1 << 33).retry_after() >= u64::from(u32::MAX));
+    }
+
+    #[tokio::test]
+    async fn test_spacing() {
+        let limiter = RateLimiter::new(20.0, 10);
+        let start = Instant::now();
+        limiter.acquire().await.unwrap();
+        limiter.acquire().await.unwrap();
+        limiter.acquire().await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_full_queue() {
+        let limiter = RateLimiter::new(10.0, 1);
+        // The first request is sent right away, the second one waits in the queue and the third
+        // one does not fit in anymore
+        let (first, second, third) =
+            tokio::join!(limiter.acquire(), limiter.acquire(), limiter.acquire());
+        assert!(first.is_ok());
+        assert!(second.is_ok());
+        assert_eq!(third, Err(NagoyaError::RateLimited { retry_after: 1 }));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_waiting() {
+        let limiter = RateLimiter::new(10.0, 1);
+        let start = Instant::now();
+        // The third caller waits for room instead of being turned away
+        let (first, second, ()) = tokio::join!(
+            limiter.acquire(),
+            limiter.acquire(),
+            limiter.acquire_waiting()
+        );
+        assert!(first.is_ok());
+        assert!(second.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,120 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::models::{Coordinates, NagoyaError};
+use proj4rs::Proj;
+use proj4rs::errors::Error;
+use tracing::{Level, event, instrument};
+
+const WGS84: u16 = 4326;
+// Ellipsoids for which a missing datum shift is off by less than a meter
+const WGS84_COMPATIBLE_ELLIPSOIDS: [&str; 2] = ["+ellps=WGS84", "+ellps=GRS80"];
+
+// Reprojects coordinates given in the CRS identified by the EPSG code to WGS84. For geographic
+// CRSs latitude and longitude are given in degrees, for projected CRSs longitude holds the
+// easting (x) and latitude the northing (y)
+#[instrument]
+pub fn to_wgs84(coordinates: Coordinates, epsg: u16) -> Result<Coordinates, NagoyaError> {
+    if epsg == WGS84 {
+        return Ok(coordinates);
+    }
+    let unsupported = || NagoyaError::UnsupportedCrs { epsg };
+    let definition = crs_definitions::from_code(epsg).ok_or_else(unsupported)?;
+    // Without datum information the shift to WGS84 would silently be skipped, which is off by
+    // up to a few hundred meters for older national datums
+    if !["+datum=", "+towgs84=", "+nadgrids="]
+        .iter()
+        .chain(WGS84_COMPATIBLE_ELLIPSOIDS.iter())
+        .any(|parameter| definition.proj4.contains(parameter))
+    {
+        event!(
+            Level::DEBUG,
+            "EPSG:{} lacks a datum shift: {}",
+            epsg,
+            definition.proj4
+        );
+        return Err(unsupported());
+    }
+    let source = Proj::from_proj_string(definition.proj4).map_err(|_| unsupported())?;
+    let target = Proj::from_proj_string(
+        crs_definitions::from_code(WGS84)
+            .ok_or_else(unsupported)?
+            .proj4,
+    )
+    .map_err(|_| unsupported())?;
+
+    let mut point = if source.is_latlong() {
+        (
+            coordinates.longitude.to_radians(),
+            coordinates.latitude.to_radians(),
+            0.0,
+        )
+    } else {
+        (coordinates.longitude, coordinates.latitude, 0.0)
+    };
+    // Fails for definitions relying on grid files we do not ship, as well as for points outside of
+    // the projection's domain
+    proj4rs::transform::transform(&source, &target, &mut point).map_err(|error| {
+        if matches!(
+            error,
+            Error::NadGridNotAvailable | Error::GridFileNotFound(_)
+        ) {
+            return unsupported();
+        }
+        event!(
+            Level::DEBUG,
+            "Reprojection from EPSG:{} failed: {}",
+            epsg,
+            error
+        );
+        NagoyaError::InvalidCoordinates {
+            reason: format!("Could not reproject coordinates from EPSG:{epsg}: {error}"),
+        }
+    })?;
+
+    Ok(Coordinates {
+        latitude: point.1.to_degrees(),
+        longitude: point.0.to_degrees(),
+        ..coordinates
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coordinates(latitude: f64, longitude: f64) -> Coordinates {
+        Coordinates {
+            latitude,
+            longitude,
+            uncertainty_in_meters: None,
+        }
+    }
+
+    #[test]
+    fn test_to_wgs84() {
+        // ETRS89 / UTM zone 32N
+        let reprojected = to_wgs84(coordinates(5_429_127.0, 456_448.0), 25832).unwrap();
+        assert!((reprojected.latitude - 49.0135).abs() < 1e-4);
+        assert!((reprojected.longitude - 8.4044).abs() < 1e-4);
+
+        // ETRS89, geographic
+        let reprojected = to_wgs84(coordinates(49.0135, 8.4044), 4258).unwrap();
+        assert!((reprojected.latitude - 49.0135).abs() < 1e-6);
+        assert!((reprojected.longitude - 8.4044).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_unsupported_crs() {
+        assert_eq!(
+            to_wgs84(coordinates(0.0, 0.0), 1).unwrap_err(),
+            NagoyaError::UnsupportedCrs { epsg: 1 }
+        );
+        // DHDN / 3-degree Gauss-Kruger zone 3 comes without a datum shift
+        assert_eq!(
+            to_wgs84(coordinates(5_430_000.0, 3_456_000.0), 31467).unwrap_err(),
+            NagoyaError::UnsupportedCrs { epsg: 31467 }
+        );
+    }
+}
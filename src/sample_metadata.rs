@@ -0,0 +1,213 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::insdc::{check_records, set_qualifier};
+use crate::models::{AppState, ImplementingCountries, InsdcQualifiers, NagoyaError, RecordReport};
+use crate::upload::malformed;
+use crate::xml::walk;
+use csv::ReaderBuilder;
+use tracing::{Level, event, instrument};
+
+// Columns identifying a sample in MIxS checklists, in order of preference
+const ID_COLUMNS: [&str; 4] = [
+    "biosample_accession",
+    "sample_accession",
+    "accession",
+    "sample_name",
+];
+
+// Samples of a BioSample document, as downloaded from NCBI or submitted, by accession or else by
+// sample name. Attributes are read by their harmonized name if there is one
+fn biosample_entries(xml: &str) -> Result<Vec<(String, InsdcQualifiers)>, NagoyaError> {
+    let mut entries = Vec::new();
+    let mut qualifiers = InsdcQualifiers::default();
+    let mut accession = None;
+    let mut sample_name = None;
+    walk(xml, |element| {
+        let text = Some(element.text.clone()).filter(|text| !text.is_empty());
+        if element.ends_with(&["BioSample", "Attributes", "Attribute"]) {
+            let name = element
+                .attributes
+                .get("harmonized_name")
+                .or_else(|| element.attributes.get("attribute_name"));
+            if let (Some(name), Some(text)) = (name, text) {
+                set_qualifier(&mut qualifiers, name, text);
+            }
+        } else if element.ends_with(&["BioSample", "Ids", "Id"]) {
+            if element.attributes.get("db").map(String::as_str) == Some("BioSample") {
+                accession = accession.take().or(text);
+            } else if element.attributes.get("db_label").map(String::as_str) == Some("Sample name")
+            {
+                sample_name = sample_name.take().or(text);
+            }
+        } else if element.ends_with(&["BioSample", "SampleId", "SPUID"]) {
+            sample_name = sample_name.take().or(text);
+        } else if element.ends_with(&["BioSample"]) {
+            let id = element
+                .attributes
+                .get("accession")
+                .cloned()
+                .or(accession.take())
+                .or(sample_name.take())
+                .unwrap_or_else(|| (entries.len() + 1).to_string());
+            accession = None;
+            sample_name = None;
+            entries.push((id, std::mem::take(&mut qualifiers)));
+        }
+        Ok(())
+    })?;
+    if entries.is_empty() {
+        return Err(malformed("No BioSample found"));
+    }
+    Ok(entries)
+}
+
+// Samples of a tab-separated MIxS checklist, as filled in for NCBI or ENA. Comment lines starting
+// with # are skipped and mandatory columns may be marked with *, e.g. *sample_name
+fn mixs_entries(content: &str) -> Result<Vec<(String, InsdcQualifiers)>, NagoyaError> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(b'\t')
+        .comment(Some(b'#'))
+        .flexible(true)
+        .from_reader(content.as_bytes());
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(malformed)?
+        .iter()
+        .map(|header| header.trim().trim_start_matches('*').to_lowercase())
+        .collect();
+    let id = ID_COLUMNS
+        .iter()
+        .find_map(|column| headers.iter().position(|header| header == column));
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(malformed)?;
+        if record.iter().all(|value| value.trim().is_empty()) {
+            continue;
+        }
+        let mut qualifiers = InsdcQualifiers::default();
+        for (header, value) in headers.iter().zip(record.iter()) {
+            let value = value.trim();
+            if !value.is_empty() {
+                set_qualifier(&mut qualifiers, header, value.to_string());
+            }
+        }
+        let id = id
+            .and_then(|id| record.get(id))
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map_or_else(|| (entries.len() + 1).to_string(), String::from);
+        entries.push((id, qualifiers));
+    }
+    Ok(entries)
+}
+
+// Checks every sample of a BioSample XML document or MIxS checklist by geo_loc_name, lat_lon and
+// collection_date and reports the results by sample accession or name
+#[instrument(skip_all)]
+pub async fn check_sample_metadata(
+    file: Vec<u8>,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+) -> Result<RecordReport, NagoyaError> {
+    let content = String::from_utf8(file).map_err(malformed)?;
+    let content = content.trim_start_matches('\u{feff}');
+    let entries = if content.trim_start().starts_with('<') {
+        biosample_entries(content)?
+    } else {
+        mixs_entries(content)?
+    };
+    event!(Level::DEBUG, "Checking {} samples", entries.len());
+    check_records(entries, implementing_countries, state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::tests::state;
+    use crate::country::CountryId;
+
+    const BIOSAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<BioSampleSet>
+  <BioSample access="public" id="2911890" accession="SAMN02911890">
+    <Ids>
+      <Id db="BioSample" is_primary="1">SAMN02911890</Id>
+      <Id db_label="Sample name">soil-7</Id>
+    </Ids>
+    <Attributes>
+      <Attribute attribute_name="geographic location" harmonized_name="geo_loc_name" display_name="geographic location">Brazil: Para</Attribute>
+      <Attribute attribute_name="lat_lon" harmonized_name="lat_lon">1.45 S 48.49 W</Attribute>
+      <Attribute attribute_name="collection_date" harmonized_name="collection_date">2013-06</Attribute>
+      <Attribute attribute_name="env_medium">soil</Attribute>
+    </Attributes>
+  </BioSample>
+  <BioSample>
+    <SampleId><SPUID spuid_namespace="MfN">mfn-42</SPUID></SampleId>
+    <Attributes>
+      <Attribute attribute_name="geo_loc_name">Germany</Attribute>
+    </Attributes>
+  </BioSample>
+</BioSampleSet>"#;
+
+    #[test]
+    fn test_biosample_entries() {
+        let entries = biosample_entries(BIOSAMPLE).unwrap();
+        assert_eq!(entries.len(), 2);
+        let (id, qualifiers) = &entries[0];
+        assert_eq!(id, "SAMN02911890");
+        assert_eq!(qualifiers.geo_loc_name.as_deref(), Some("Brazil: Para"));
+        assert_eq!(qualifiers.lat_lon.as_deref(), Some("1.45 S 48.49 W"));
+        assert_eq!(qualifiers.collection_date.as_deref(), Some("2013-06"));
+        assert_eq!(entries[1].0, "mfn-42");
+        assert_eq!(entries[1].1.geo_loc_name.as_deref(), Some("Germany"));
+        assert!(matches!(
+            biosample_entries("<BioSampleSet/>"),
+            Err(NagoyaError::MalformedUpload { .. })
+        ));
+    }
+
+    #[test]
+    fn test_mixs_entries() {
+        let entries = mixs_entries(
+            "# MIMARKS.survey.soil.6.0\n*sample_name\t*geo_loc_name\tlat_lon\t*collection_date\n\
+             S1\tPeru: Cusco\t13.52 S 71.97 W\t2019\n\t\t\t\n\tGermany\t\tmissing\n",
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "S1");
+        assert_eq!(entries[0].1.geo_loc_name.as_deref(), Some("Peru: Cusco"));
+        assert_eq!(entries[0].1.lat_lon.as_deref(), Some("13.52 S 71.97 W"));
+        assert_eq!(entries[1].0, "2");
+        assert_eq!(entries[1].1.collection_date.as_deref(), Some("missing"));
+    }
+
+    #[tokio::test]
+    async fn test_check_sample_metadata() {
+        let implementing_countries = ImplementingCountries {
+            countries: ["BRA".parse::<CountryId>().unwrap()].into(),
+            ..Default::default()
+        };
+        let report = check_sample_metadata(
+            BIOSAMPLE.as_bytes().to_vec(),
+            &implementing_countries,
+            &state(10),
+        )
+        .await
+        .unwrap();
+        let ids: Vec<&str> = report
+            .results
+            .iter()
+            .map(|result| result.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["SAMN02911890", "mfn-42"]);
+        assert_eq!(
+            report.results[1]
+                .result
+                .as_ref()
+                .map(|response| response.check_result),
+            Some(false)
+        );
+        assert_eq!(report.summary.records, 2);
+    }
+}
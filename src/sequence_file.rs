@@ -0,0 +1,322 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::insdc::{check_records, set_qualifier};
+use crate::models::{AppState, ImplementingCountries, InsdcQualifiers, NagoyaError, RecordReport};
+use crate::upload::malformed;
+use tracing::{Level, event, instrument};
+
+// Feature table lines start with the feature key or qualifier in column 6, after five spaces in
+// GenBank and after "FT" and three spaces in EMBL
+const FEATURE_INDENT: usize = 5;
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    GenBank,
+    Embl,
+    Fasta,
+}
+
+fn detect_format(content: &str) -> Result<Format, NagoyaError> {
+    let first = content
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default();
+    if first.starts_with("LOCUS") {
+        Ok(Format::GenBank)
+    } else if first.starts_with("ID   ") {
+        Ok(Format::Embl)
+    } else if first.starts_with('>') {
+        Ok(Format::Fasta)
+    } else {
+        Err(malformed("Expected a GenBank or EMBL flat file or FASTA"))
+    }
+}
+
+// Qualifiers of the first source feature. Values continue on the following lines until the next
+// qualifier or feature
+fn source_qualifiers<'a>(lines: impl Iterator<Item = &'a str>) -> InsdcQualifiers {
+    let mut qualifiers = InsdcQualifiers::default();
+    let mut in_source = false;
+    let mut seen_source = false;
+    let mut current: Option<(String, String)> = None;
+    for line in lines {
+        let Some(content) = line.get(FEATURE_INDENT..) else {
+            continue;
+        };
+        if !content.starts_with(' ') {
+            if let Some((name, value)) = current.take() {
+                set_qualifier(&mut qualifiers, &name, value);
+            }
+            in_source = !seen_source && content.split_whitespace().next() == Some("source");
+            seen_source |= in_source;
+            continue;
+        }
+        if !in_source {
+            continue;
+        }
+        let content = content.trim();
+        if let Some(qualifier) = content.strip_prefix('/') {
+            if let Some((name, value)) = current.take() {
+                set_qualifier(&mut qualifiers, &name, value);
+            }
+            let (name, value) = qualifier.split_once('=').unwrap_or((qualifier, ""));
+            current = Some((name.to_string(), value.to_string()));
+        } else if let Some((_, value)) = current.as_mut() {
+            value.push(' ');
+            value.push_str(content);
+        }
+    }
+    if let Some((name, value)) = current {
+        set_qualifier(&mut qualifiers, &name, value);
+    }
+    qualifiers
+}
+
+// Entries of a GenBank file by accession. VERSION gives the accession with its version, e.g.
+// X56734.1, and the feature table runs from FEATURES to ORIGIN or CONTIG
+fn genbank_entries(content: &str) -> Vec<(String, InsdcQualifiers)> {
+    content
+        .split("\n//")
+        .filter(|entry| entry.contains("LOCUS"))
+        .enumerate()
+        .map(|(position, entry)| {
+            let field = |keyword: &str| {
+                entry
+                    .lines()
+                    .find(|line| line.split_whitespace().next() == Some(keyword))
+                    .and_then(|line| line.split_whitespace().nth(1))
+            };
+            let accession = field("VERSION")
+                .or_else(|| field("ACCESSION"))
+                .or_else(|| field("LOCUS"))
+                .map_or_else(|| (position + 1).to_string(), String::from);
+            let features = entry
+                .lines()
+                .skip_while(|line| !line.starts_with("FEATURES"))
+                .skip(1)
+                .take_while(|line| line.starts_with(' '));
+            (accession, source_qualifiers(features))
+        })
+        .collect()
+}
+
+// Entries of an EMBL file by accession, from the AC line with the version of the ID line
+fn embl_entries(content: &str) -> Vec<(String, InsdcQualifiers)> {
+    content
+        .split("\n//")
+        .filter(|entry| entry.contains("ID   "))
+        .enumerate()
+        .map(|(position, entry)| {
+            let line = |code: &str| {
+                entry
+                    .lines()
+                    .find(|line| line.starts_with(&format!("{code}   ")))
+                    .map(|line| line[code.len()..].trim())
+            };
+            let version = line("ID").and_then(|id| {
+                id.split(';')
+                    .find_map(|part| part.trim().strip_prefix("SV "))
+                    .map(str::trim)
+            });
+            let accession = line("AC")
+                .and_then(|accessions| accessions.split(';').next())
+                .map(str::trim)
+                .filter(|accession| !accession.is_empty())
+                .map_or_else(
+                    || (position + 1).to_string(),
+                    |accession| match version {
+                        Some(version) => format!("{accession}.{version}"),
+                        None => accession.to_string(),
+                    },
+                );
+            let features = entry
+                .lines()
+                .filter(|line| line.starts_with("FT"))
+                .map(|line| line.get(2..).unwrap_or_default());
+            // Shifted by the "FT" prefix, so the columns match those of GenBank
+            let features: Vec<String> = features.map(|line| format!("  {line}")).collect();
+            (
+                accession,
+                source_qualifiers(features.iter().map(String::as_str)),
+            )
+        })
+        .collect()
+}
+
+// Entries of a FASTA file by the first word of their header, with the qualifiers given as source
+// modifiers, e.g. >MN908947.3 [geo_loc_name=China: Wuhan] [collection-date=2019-12]
+fn fasta_entries(content: &str) -> Vec<(String, InsdcQualifiers)> {
+    content
+        .lines()
+        .filter_map(|line| line.strip_prefix('>'))
+        .enumerate()
+        .map(|(position, header)| {
+            let accession = header
+                .split_whitespace()
+                .next()
+                .map_or_else(|| (position + 1).to_string(), String::from);
+            let mut qualifiers = InsdcQualifiers::default();
+            for modifier in header.split('[').skip(1) {
+                let Some((modifier, _)) = modifier.split_once(']') else {
+                    continue;
+                };
+                if let Some((name, value)) = modifier.split_once('=') {
+                    set_qualifier(&mut qualifiers, name.trim(), value.trim().to_string());
+                }
+            }
+            (accession, qualifiers)
+        })
+        .collect()
+}
+
+// Checks every entry of a GenBank or EMBL flat file or FASTA file by its source qualifiers and
+// reports the results by accession
+#[instrument(skip_all)]
+pub async fn check_sequence_file(
+    file: Vec<u8>,
+    implementing_countries: &ImplementingCountries,
+    state: &AppState,
+) -> Result<RecordReport, NagoyaError> {
+    let content = String::from_utf8(file)
+        .map_err(malformed)?
+        .replace("\r\n", "\n");
+    let format = detect_format(&content)?;
+    let entries = match format {
+        Format::GenBank => genbank_entries(&content),
+        Format::Embl => embl_entries(&content),
+        Format::Fasta => fasta_entries(&content),
+    };
+    event!(
+        Level::DEBUG,
+        "Checking {} {:?} entries",
+        entries.len(),
+        format
+    );
+    check_records(entries, implementing_countries, state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::tests::state;
+    use crate::country::CountryId;
+
+    const GENBANK: &str =
+        "LOCUS       OQ123456                 658 bp    DNA     linear   INV 01-FEB-2023
+DEFINITION  Apis mellifera cytochrome oxidase subunit I (COI) gene, partial cds.
+ACCESSION   OQ123456
+VERSION     OQ123456.1
+FEATURES             Location/Qualifiers
+     source          1..658
+                     /organism=\"Apis mellifera\"
+                     /mol_type=\"genomic DNA\"
+                     /geo_loc_name=\"Brazil: Amazonas, Reserva Ducke,
+                     Manaus\"
+                     /lat_lon=\"2.95 S 59.95 W\"
+                     /collection_date=\"21-Oct-2019\"
+     CDS             <1..>658
+                     /country=\"Germany\"
+ORIGIN
+        1 aacttatatt ttatttttgg
+//
+LOCUS       OQ123457                 658 bp    DNA     linear   INV 01-FEB-2023
+ACCESSION   OQ123457
+FEATURES             Location/Qualifiers
+     source          1..658
+                     /country=\"Germany\"
+ORIGIN
+//
+";
+
+    const EMBL: &str = "ID   X56734; SV 1; linear; mRNA; STD; PLN; 1859 BP.
+XX
+AC   X56734; S46826;
+XX
+FH   Key             Location/Qualifiers
+FH
+FT   source          1..1859
+FT                   /organism=\"Trifolium repens\"
+FT                   /country=\"Peru\"
+FT                   /collection_date=\"2001\"
+SQ   Sequence 1859 BP; 609 A; 314 C; 355 G; 581 T; 0 other;
+     aaacaaacca aatatggatt ttattgtagc catatttgct ctgtttgtta ttagctcatt        60
+//
+";
+
+    #[test]
+    fn test_genbank_entries() {
+        let entries = genbank_entries(GENBANK);
+        assert_eq!(entries.len(), 2);
+        let (accession, qualifiers) = &entries[0];
+        assert_eq!(accession, "OQ123456.1");
+        assert_eq!(
+            qualifiers.geo_loc_name.as_deref(),
+            Some("\"Brazil: Amazonas, Reserva Ducke, Manaus\"")
+        );
+        assert_eq!(qualifiers.lat_lon.as_deref(), Some("\"2.95 S 59.95 W\""));
+        // Qualifiers of other features are not provenance
+        assert_eq!(qualifiers.country, None);
+        assert_eq!(entries[1].0, "OQ123457");
+        assert_eq!(entries[1].1.country.as_deref(), Some("\"Germany\""));
+    }
+
+    #[test]
+    fn test_embl_entries() {
+        let entries = embl_entries(EMBL);
+        assert_eq!(entries.len(), 1);
+        let (accession, qualifiers) = &entries[0];
+        assert_eq!(accession, "X56734.1");
+        assert_eq!(qualifiers.country.as_deref(), Some("\"Peru\""));
+        assert_eq!(qualifiers.collection_date.as_deref(), Some("\"2001\""));
+    }
+
+    #[test]
+    fn test_fasta_entries() {
+        let entries = fasta_entries(
+            ">MN908947.3 [organism=Severe acute respiratory syndrome coronavirus 2] [geo_loc_name=China: Wuhan] [collection-date=Dec-2019]\nATTAAAGG\n>seq2\nACGT\n",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "MN908947.3");
+        assert_eq!(entries[0].1.geo_loc_name.as_deref(), Some("China: Wuhan"));
+        assert_eq!(entries[0].1.collection_date.as_deref(), Some("Dec-2019"));
+        assert_eq!(entries[1].0, "seq2");
+        assert_eq!(entries[1].1.geo_loc_name, None);
+    }
+
+    #[tokio::test]
+    async fn test_check_sequence_file() {
+        let implementing_countries = ImplementingCountries {
+            countries: ["BRA".parse::<CountryId>().unwrap()].into(),
+            ..Default::default()
+        };
+        let report = check_sequence_file(
+            EMBL.as_bytes().to_vec(),
+            &implementing_countries,
+            &state(10),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.results[0].id, "X56734.1");
+        let qualifiers = report.results[0].qualifiers.as_ref().unwrap();
+        assert_eq!(qualifiers.country_code.as_deref(), Some("PE"));
+        assert_eq!(
+            report.results[0]
+                .result
+                .as_ref()
+                .map(|response| response.check_result),
+            Some(false)
+        );
+        assert_eq!(report.summary.records, 1);
+        assert!(matches!(
+            check_sequence_file(
+                b"accession,country\n".to_vec(),
+                &implementing_countries,
+                &state(10)
+            )
+            .await,
+            Err(NagoyaError::MalformedUpload { .. })
+        ));
+    }
+}
@@ -0,0 +1,103 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use tracing::{Level, event, instrument};
+
+// ABS regimes of subdivisions regulating access on their own, e.g. the biodiscovery acts of
+// Australian states. Loaded from a local JSON file mapping ISO 3166-2 codes to regimes, e.g.
+// {"AU-QLD": [{"name": "Biodiscovery Act 2004 (Qld)", "url": "https://..."}]}
+#[derive(Default)]
+pub struct SubnationalRegimes {
+    regimes: HashMap<String, Vec<SubnationalRegime>>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct SubnationalRegime {
+    pub name: String,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+impl SubnationalRegimes {
+    #[instrument]
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Box<dyn Error>> {
+        let regimes: HashMap<String, Vec<SubnationalRegime>> = serde_json::from_str(json)?;
+        let mut normalized = HashMap::with_capacity(regimes.len());
+        for (code, regimes) in regimes {
+            let code = code.to_uppercase();
+            if rust_iso3166::iso3166_2::from_code(&code).is_none() {
+                return Err(format!("Unknown subdivision code \"{code}\"").into());
+            }
+            normalized.insert(code, regimes);
+        }
+        event!(
+            Level::INFO,
+            "Loaded subnational regimes for {} subdivisions",
+            normalized.len()
+        );
+        Ok(Self {
+            regimes: normalized,
+        })
+    }
+
+    // Whether any subdivision of the country has a regime of its own
+    pub fn covers_country(&self, country_code: &str) -> bool {
+        let prefix = format!("{}-", country_code.to_uppercase());
+        self.regimes.keys().any(|code| code.starts_with(&prefix))
+    }
+
+    // Verdict reasons for the regimes of a subdivision, empty if it follows the national rules only
+    pub fn reasons(&self, subdivision: &str) -> Vec<String> {
+        self.regimes
+            .get(&subdivision.to_uppercase())
+            .map(|regimes| {
+                regimes
+                    .iter()
+                    .map(|regime| match &regime.url {
+                        Some(url) => format!(
+                            "{subdivision} regulates access with its own regime: {} ({url})",
+                            regime.name
+                        ),
+                        None => format!(
+                            "{subdivision} regulates access with its own regime: {}",
+                            regime.name
+                        ),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reasons() {
+        let regimes = SubnationalRegimes::from_json(
+            r#"{"au-qld": [{"name": "Biodiscovery Act 2004 (Qld)"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            regimes.reasons("AU-QLD"),
+            vec!["AU-QLD regulates access with its own regime: Biodiscovery Act 2004 (Qld)"]
+        );
+        assert!(regimes.reasons("AU-NSW").is_empty());
+        assert!(regimes.covers_country("au"));
+        assert!(!regimes.covers_country("DE"));
+    }
+
+    #[test]
+    fn test_unknown_subdivision() {
+        assert!(SubnationalRegimes::from_json(r#"{"AU-XX": []}"#).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::models::{FileUpload, NagoyaError};
+use axum::extract::Multipart;
+
+pub fn malformed(reason: impl ToString) -> NagoyaError {
+    NagoyaError::MalformedUpload {
+        reason: reason.to_string(),
+    }
+}
+
+// Reads a multipart form with the single field file
+pub async fn read_file(mut multipart: Multipart) -> Result<FileUpload, NagoyaError> {
+    let mut file = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|error| malformed(error.body_text()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        if name != "file" {
+            return Err(malformed(format!("Unknown field \"{name}\"")));
+        }
+        file = Some(
+            field
+                .bytes()
+                .await
+                .map_err(|error| malformed(error.body_text()))?
+                .to_vec(),
+        );
+    }
+    match file {
+        Some(file) if !file.is_empty() => Ok(FileUpload { file }),
+        _ => Err(malformed("No file given")),
+    }
+}
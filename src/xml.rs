@@ -0,0 +1,148 @@
+// SPDX-FileCopyrightText: 2025 Constantin Breß <constantin.bress@partner.kit.edu>
+//
+// SPDX-License-Identifier: LGPL-3.0-or-later
+
+use crate::models::NagoyaError;
+use crate::upload::malformed;
+use quick_xml::Reader;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+
+// Element of an XML document, passed to the visitor of walk once it is closed
+pub struct Element<'a> {
+    // Local names from the root down to the element itself, without namespace prefixes
+    pub path: &'a [String],
+    pub attributes: HashMap<String, String>,
+    // Trimmed text of the element, without the text of its children
+    pub text: String,
+}
+
+impl Element<'_> {
+    pub fn ends_with(&self, suffix: &[&str]) -> bool {
+        self.path.len() >= suffix.len()
+            && self.path[self.path.len() - suffix.len()..]
+                .iter()
+                .zip(suffix)
+                .all(|(name, expected)| name == expected)
+    }
+}
+
+// Attributes by local name, with entities resolved
+pub fn attributes(element: &BytesStart) -> Result<HashMap<String, String>, NagoyaError> {
+    element
+        .attributes()
+        .map(|attribute| {
+            let attribute = attribute.map_err(malformed)?;
+            Ok((
+                String::from_utf8_lossy(attribute.key.local_name().as_ref()).to_string(),
+                attribute.unescape_value().map_err(malformed)?.to_string(),
+            ))
+        })
+        .collect()
+}
+
+// Visits every element of the document in the order they are closed, so the children of an
+// element are visited before the element itself. Namespaces are ignored, as the formats read with
+// this are identified by their element names and published with varying prefixes
+pub fn walk(
+    xml: &str,
+    mut visit: impl FnMut(Element) -> Result<(), NagoyaError>,
+) -> Result<(), NagoyaError> {
+    let mut reader = Reader::from_str(xml);
+    let mut path: Vec<String> = Vec::new();
+    let mut open: Vec<(HashMap<String, String>, String)> = Vec::new();
+    loop {
+        match reader.read_event().map_err(malformed)? {
+            Event::Start(element) => {
+                path.push(String::from_utf8_lossy(element.local_name().as_ref()).to_string());
+                open.push((attributes(&element)?, String::new()));
+            }
+            Event::Empty(element) => {
+                path.push(String::from_utf8_lossy(element.local_name().as_ref()).to_string());
+                visit(Element {
+                    path: &path,
+                    attributes: attributes(&element)?,
+                    text: String::new(),
+                })?;
+                path.pop();
+            }
+            Event::Text(text) => {
+                if let Some((_, content)) = open.last_mut() {
+                    content.push_str(&text.xml_content().map_err(malformed)?);
+                }
+            }
+            Event::CData(text) => {
+                if let Some((_, content)) = open.last_mut() {
+                    content.push_str(&text.decode().map_err(malformed)?);
+                }
+            }
+            Event::GeneralRef(reference) => {
+                if let Some((_, content)) = open.last_mut() {
+                    match reference.resolve_char_ref().map_err(malformed)? {
+                        Some(character) => content.push(character),
+                        None => {
+                            let name = reference.decode().map_err(malformed)?;
+                            content.push_str(resolve_predefined_entity(&name).ok_or_else(
+                                || malformed(format!("Unknown entity \"&{name};\"")),
+                            )?);
+                        }
+                    }
+                }
+            }
+            Event::End(_) => {
+                let (attributes, text) = open
+                    .pop()
+                    .ok_or_else(|| malformed("Unbalanced XML document"))?;
+                visit(Element {
+                    path: &path,
+                    attributes,
+                    text: text.trim().to_string(),
+                })?;
+                path.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if !path.is_empty() {
+        return Err(malformed("Unexpected end of XML document"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walk() {
+        let mut elements = Vec::new();
+        walk(
+            r#"<a:root xmlns:a="urn:a"><a:b id="1">x &amp; y<c/> z&#33;</a:b><![CDATA[<d>]]></a:root>"#,
+            |element| {
+                elements.push((
+                    element.path.join("/"),
+                    element.attributes.get("id").cloned(),
+                    element.text,
+                ));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            elements,
+            vec![
+                (String::from("root/b/c"), None, String::new()),
+                (
+                    String::from("root/b"),
+                    Some(String::from("1")),
+                    String::from("x & y z!")
+                ),
+                (String::from("root"), None, String::from("<d>")),
+            ]
+        );
+        assert!(walk("<a><b></a>", |_| Ok(())).is_err());
+        assert!(walk("<a>", |_| Ok(())).is_err());
+    }
+}